@@ -48,6 +48,6 @@ async fn main() -> Result<(), SingerIcebergError> {
 
         Ok(())
     } else {
-        ingest(plugin.clone(), &mut BufReader::new(io::stdin())).await
+        ingest(plugin.clone(), BufReader::new(io::stdin()), io::stdout()).await
     }
 }
@@ -3,11 +3,15 @@ use std::{collections::HashMap, fs, sync::Arc};
 use anyhow::anyhow;
 use async_trait::async_trait;
 use dashbook_catalog::DashbookS3CatalogList;
-use iceberg_rust::catalog::{Catalog, CatalogList};
+use iceberg_rust::catalog::{bucket::Bucket, Catalog, CatalogList};
+use object_store::ObjectStore;
 use serde::{Deserialize, Serialize};
 use target_iceberg::{
     error::SingerIcebergError,
-    plugin::{BaseConfig, TargetPlugin},
+    plugin::{
+        AuditConfig, BaseConfig, BufferConfig, CommitConfig, MetricsConfig, StreamConfig,
+        TargetPlugin,
+    },
 };
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -22,6 +26,7 @@ pub struct Config {
 #[derive(Debug)]
 pub(crate) struct DashbookTargetPlugin {
     config: BaseConfig,
+    object_store: Arc<dyn ObjectStore>,
     catalog: Arc<dyn Catalog>,
 }
 
@@ -45,8 +50,18 @@ impl DashbookTargetPlugin {
                     &config.catalog
                 )))?;
 
+        // The dashbook catalog provides the object store of its S3 bucket
+        let object_store = catalog.object_store(Bucket::from_path(
+            config
+                .base
+                .bucket
+                .as_deref()
+                .ok_or(SingerIcebergError::Anyhow(anyhow!("No bucket specified.")))?,
+        )?);
+
         Ok(Self {
             config: config.base,
+            object_store,
             catalog,
         })
     }
@@ -60,10 +75,31 @@ impl TargetPlugin for DashbookTargetPlugin {
     fn bucket(&self) -> Option<&str> {
         self.config.bucket.as_deref()
     }
-    fn streams(&self) -> &HashMap<String, String> {
+    fn object_store(&self) -> Arc<dyn ObjectStore> {
+        self.object_store.clone()
+    }
+    fn streams(&self) -> &HashMap<String, StreamConfig> {
         &self.config.streams
     }
     fn branch(&self) -> &Option<String> {
         &self.config.branch
     }
+    fn commit(&self) -> &CommitConfig {
+        &self.config.commit
+    }
+    fn buffer(&self) -> &BufferConfig {
+        &self.config.buffer
+    }
+    fn emit_state(&self) -> bool {
+        self.config.emit_state
+    }
+    fn audit(&self) -> Option<&AuditConfig> {
+        self.config.audit.as_ref()
+    }
+    fn state_location(&self) -> Option<&str> {
+        self.config.state_location.as_deref()
+    }
+    fn metrics(&self) -> &MetricsConfig {
+        &self.config.metrics
+    }
 }
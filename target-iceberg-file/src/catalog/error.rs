@@ -0,0 +1,24 @@
+use iceberg_rust::error::Error as IcebergError;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Text(String),
+    #[error("Table {0} was modified concurrently.")]
+    Conflict(String),
+    #[error(transparent)]
+    ObjectStore(#[from] object_store::Error),
+    #[error(transparent)]
+    Serde(#[from] serde_json::Error),
+    #[error(transparent)]
+    ParseInt(#[from] std::num::ParseIntError),
+    #[error(transparent)]
+    Utf8(#[from] std::str::Utf8Error),
+}
+
+impl From<Error> for IcebergError {
+    fn from(value: Error) -> Self {
+        IcebergError::InvalidFormat(value.to_string())
+    }
+}
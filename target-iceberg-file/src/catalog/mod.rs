@@ -0,0 +1,362 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use dashmap::DashMap;
+use futures::{StreamExt, TryStreamExt};
+use iceberg_rust::{
+    catalog::{
+        bucket::Bucket,
+        commit::{apply_table_updates, check_table_requirements, CommitTable, CommitView},
+        create::{CreateMaterializedView, CreateTable, CreateView},
+        identifier::Identifier,
+        namespace::Namespace,
+        tabular::Tabular,
+        Catalog,
+    },
+    error::Error as IcebergError,
+    materialized_view::MaterializedView,
+    spec::{table_metadata::TableMetadata, util::strip_prefix},
+    table::Table,
+    view::View,
+};
+use object_store::{path::Path, ObjectStore, PutMode};
+
+use crate::catalog::error::Error;
+
+pub mod error;
+
+static VERSION_HINT: &str = "version-hint.text";
+
+// Catalog without a database. Tables are stored in directories of the warehouse named after their
+// namespace and name. The metadata of version N is stored in "metadata/vN.metadata.json" and the
+// file "metadata/version-hint.text" contains the current version.
+#[derive(Debug)]
+pub struct FileCatalog {
+    warehouse: String,
+    object_store: Arc<dyn ObjectStore>,
+    cache: Arc<DashMap<Identifier, (i64, TableMetadata)>>,
+}
+
+impl FileCatalog {
+    pub fn new(warehouse: &str, object_store: Arc<dyn ObjectStore>) -> Self {
+        FileCatalog {
+            warehouse: warehouse.trim_end_matches('/').to_owned(),
+            object_store,
+            cache: Arc::new(DashMap::new()),
+        }
+    }
+
+    fn namespace_location(&self, namespace: &Namespace) -> String {
+        namespace
+            .iter()
+            .fold(self.warehouse.clone(), |acc, x| acc + "/" + x)
+    }
+
+    fn table_location(&self, identifier: &Identifier) -> String {
+        self.namespace_location(identifier.namespace()) + "/" + identifier.name()
+    }
+
+    fn metadata_path(&self, identifier: &Identifier, file: &str) -> Path {
+        strip_prefix(&(self.table_location(identifier) + "/metadata/" + file)).into()
+    }
+
+    async fn current_version(&self, identifier: &Identifier) -> Result<Option<i64>, Error> {
+        match self
+            .object_store
+            .get(&self.metadata_path(identifier, VERSION_HINT))
+            .await
+        {
+            Ok(result) => Ok(Some(
+                std::str::from_utf8(&result.bytes().await?)?
+                    .trim()
+                    .parse()?,
+            )),
+            Err(object_store::Error::NotFound { .. }) => self.latest_version(identifier).await,
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    // Determine the version from the metadata files if the version hint is missing
+    async fn latest_version(&self, identifier: &Identifier) -> Result<Option<i64>, Error> {
+        let prefix = self.metadata_path(identifier, "");
+        let versions = self
+            .object_store
+            .list(Some(&prefix))
+            .map_ok(|object| {
+                object
+                    .location
+                    .filename()
+                    .and_then(|x| x.strip_prefix('v'))
+                    .and_then(|x| x.strip_suffix(".metadata.json"))
+                    .and_then(|x| x.parse::<i64>().ok())
+            })
+            .try_collect::<Vec<_>>()
+            .await?;
+        Ok(versions.into_iter().flatten().max())
+    }
+
+    async fn load_metadata(&self, identifier: &Identifier) -> Result<(i64, TableMetadata), Error> {
+        let version = self
+            .current_version(identifier)
+            .await?
+            .ok_or(Error::Text(format!("Table {} not found.", identifier)))?;
+        let bytes = self
+            .object_store
+            .get(&self.metadata_path(identifier, &format!("v{}.metadata.json", version)))
+            .await?
+            .bytes()
+            .await?;
+        let metadata: TableMetadata = serde_json::from_slice(&bytes)?;
+        self.cache
+            .insert(identifier.clone(), (version, metadata.clone()));
+        Ok((version, metadata))
+    }
+
+    // Writing the metadata file of a version fails if the version already exists. Object stores
+    // without conditional puts fall back to checking for the file first.
+    async fn write_metadata(
+        &self,
+        identifier: &Identifier,
+        version: i64,
+        metadata: &TableMetadata,
+    ) -> Result<(), Error> {
+        let path = self.metadata_path(identifier, &format!("v{}.metadata.json", version));
+        let bytes = serde_json::to_vec(metadata)?;
+        match self
+            .object_store
+            .put_opts(&path, bytes.clone().into(), PutMode::Create.into())
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(object_store::Error::AlreadyExists { .. }) => {
+                Err(Error::Conflict(identifier.to_string()))
+            }
+            Err(object_store::Error::NotImplemented) => match self.object_store.head(&path).await {
+                Ok(_) => Err(Error::Conflict(identifier.to_string())),
+                Err(object_store::Error::NotFound { .. }) => {
+                    self.object_store.put(&path, bytes.into()).await?;
+                    Ok(())
+                }
+                Err(err) => Err(err.into()),
+            },
+            Err(err) => Err(err.into()),
+        }?;
+        self.object_store
+            .put(
+                &self.metadata_path(identifier, VERSION_HINT),
+                version.to_string().into(),
+            )
+            .await?;
+        self.cache
+            .insert(identifier.clone(), (version, metadata.clone()));
+        Ok(())
+    }
+
+    // Directories directly below the location
+    async fn children(&self, location: &str) -> Result<Vec<String>, Error> {
+        let prefix: Path = strip_prefix(location).into();
+        Ok(self
+            .object_store
+            .list_with_delimiter(Some(&prefix))
+            .await?
+            .common_prefixes
+            .iter()
+            .filter_map(|x| x.filename().map(ToOwned::to_owned))
+            .collect())
+    }
+
+    async fn is_table(&self, identifier: &Identifier) -> Result<bool, Error> {
+        Ok(self.current_version(identifier).await?.is_some())
+    }
+}
+
+#[async_trait]
+impl Catalog for FileCatalog {
+    // Namespaces are directories of the warehouse that only exist implicitly through their tables
+    async fn create_namespace(
+        &self,
+        _namespace: &Namespace,
+        properties: Option<HashMap<String, String>>,
+    ) -> Result<HashMap<String, String>, IcebergError> {
+        Ok(properties.unwrap_or_default())
+    }
+    async fn drop_namespace(&self, namespace: &Namespace) -> Result<(), IcebergError> {
+        if self.list_tabulars(namespace).await?.is_empty() {
+            Ok(())
+        } else {
+            Err(IcebergError::InvalidFormat(format!(
+                "Namespace {} is not empty",
+                namespace
+            )))
+        }
+    }
+    async fn load_namespace(
+        &self,
+        _namespace: &Namespace,
+    ) -> Result<HashMap<String, String>, IcebergError> {
+        Ok(HashMap::new())
+    }
+    async fn update_namespace(
+        &self,
+        _namespace: &Namespace,
+        _updates: Option<HashMap<String, String>>,
+        _removals: Option<Vec<String>>,
+    ) -> Result<(), IcebergError> {
+        Err(IcebergError::NotSupported(
+            "namespace properties in the file catalog".to_owned(),
+        ))
+    }
+    async fn namespace_exists(&self, namespace: &Namespace) -> Result<bool, IcebergError> {
+        let prefix: Path = strip_prefix(&self.namespace_location(namespace)).into();
+        Ok(self.object_store.list(Some(&prefix)).next().await.is_some())
+    }
+    async fn list_tabulars(&self, namespace: &Namespace) -> Result<Vec<Identifier>, IcebergError> {
+        let mut identifiers = Vec::new();
+        for name in self.children(&self.namespace_location(namespace)).await? {
+            let identifier = Identifier::new(namespace, &name);
+            if self.is_table(&identifier).await? {
+                identifiers.push(identifier);
+            }
+        }
+        Ok(identifiers)
+    }
+    async fn list_namespaces(&self, parent: Option<&str>) -> Result<Vec<Namespace>, IcebergError> {
+        let parent = match parent {
+            Some(parent) => parent.split('.').map(ToOwned::to_owned).collect(),
+            None => Vec::new(),
+        };
+        let mut namespaces = Vec::new();
+        for name in self
+            .children(&self.namespace_location(&Namespace::try_new(&parent)?))
+            .await?
+        {
+            if !self.is_table(&Identifier::new(&parent, &name)).await? {
+                namespaces.push(Namespace::try_new(&[parent.clone(), vec![name]].concat())?);
+            }
+        }
+        Ok(namespaces)
+    }
+    async fn tabular_exists(&self, identifier: &Identifier) -> Result<bool, IcebergError> {
+        Ok(self.is_table(identifier).await?)
+    }
+    async fn drop_table(&self, identifier: &Identifier) -> Result<(), IcebergError> {
+        let prefix: Path = strip_prefix(&self.table_location(identifier)).into();
+        let locations = self
+            .object_store
+            .list(Some(&prefix))
+            .map_ok(|x| x.location)
+            .boxed();
+        self.object_store
+            .delete_stream(locations)
+            .try_collect::<Vec<_>>()
+            .await?;
+        self.cache.remove(identifier);
+        Ok(())
+    }
+    async fn drop_view(&self, _identifier: &Identifier) -> Result<(), IcebergError> {
+        Err(IcebergError::NotSupported(
+            "views in the file catalog".to_owned(),
+        ))
+    }
+    async fn drop_materialized_view(&self, _identifier: &Identifier) -> Result<(), IcebergError> {
+        Err(IcebergError::NotSupported(
+            "materialized views in the file catalog".to_owned(),
+        ))
+    }
+    async fn load_tabular(
+        self: Arc<Self>,
+        identifier: &Identifier,
+    ) -> Result<Tabular, IcebergError> {
+        let (_, metadata) = self.load_metadata(identifier).await?;
+        Ok(Tabular::Table(
+            Table::new(identifier.clone(), self.clone(), metadata).await?,
+        ))
+    }
+    async fn create_table(
+        self: Arc<Self>,
+        identifier: Identifier,
+        create_table: CreateTable,
+    ) -> Result<Table, IcebergError> {
+        // The location of a table is determined by its identifier
+        let mut metadata: TableMetadata = create_table.try_into()?;
+        metadata.location = self.table_location(&identifier);
+
+        self.write_metadata(&identifier, 1, &metadata).await?;
+
+        Table::new(identifier, self.clone(), metadata).await
+    }
+    async fn create_view(
+        self: Arc<Self>,
+        _identifier: Identifier,
+        _create_view: CreateView<Option<()>>,
+    ) -> Result<View, IcebergError> {
+        Err(IcebergError::NotSupported(
+            "views in the file catalog".to_owned(),
+        ))
+    }
+    async fn create_materialized_view(
+        self: Arc<Self>,
+        _identifier: Identifier,
+        _create_view: CreateMaterializedView,
+    ) -> Result<MaterializedView, IcebergError> {
+        Err(IcebergError::NotSupported(
+            "materialized views in the file catalog".to_owned(),
+        ))
+    }
+    async fn update_table(self: Arc<Self>, commit: CommitTable) -> Result<Table, IcebergError> {
+        let identifier = commit.identifier;
+        let (version, mut metadata) = match self.cache.get(&identifier) {
+            Some(entry) => entry.value().clone(),
+            None => self.load_metadata(&identifier).await?,
+        };
+        if !check_table_requirements(&commit.requirements, &metadata) {
+            return Err(IcebergError::InvalidFormat(
+                "Table requirements not valid".to_owned(),
+            ));
+        }
+        apply_table_updates(&mut metadata, commit.updates)?;
+
+        self.write_metadata(&identifier, version + 1, &metadata)
+            .await?;
+
+        Table::new(identifier, self.clone(), metadata).await
+    }
+    async fn update_view(self: Arc<Self>, _commit: CommitView) -> Result<View, IcebergError> {
+        Err(IcebergError::NotSupported(
+            "views in the file catalog".to_owned(),
+        ))
+    }
+    async fn update_materialized_view(
+        self: Arc<Self>,
+        _commit: CommitView,
+    ) -> Result<MaterializedView, IcebergError> {
+        Err(IcebergError::NotSupported(
+            "materialized views in the file catalog".to_owned(),
+        ))
+    }
+    async fn register_table(
+        self: Arc<Self>,
+        identifier: Identifier,
+        metadata_location: &str,
+    ) -> Result<Table, IcebergError> {
+        let bytes = self
+            .object_store
+            .get(&strip_prefix(metadata_location).into())
+            .await?
+            .bytes()
+            .await?;
+        let metadata: TableMetadata = serde_json::from_slice(&bytes)?;
+
+        let version = self
+            .current_version(&identifier)
+            .await?
+            .map(|x| x + 1)
+            .unwrap_or(1);
+        self.write_metadata(&identifier, version, &metadata).await?;
+
+        Table::new(identifier, self.clone(), metadata).await
+    }
+    fn object_store(&self, _: Bucket) -> Arc<dyn ObjectStore> {
+        self.object_store.clone()
+    }
+}
@@ -0,0 +1,298 @@
+use std::{
+    io::{self, BufReader},
+    sync::Arc,
+};
+
+use clap::Parser;
+use plugin::FileTargetPlugin;
+use target_iceberg::{
+    catalog::select_streams,
+    error::SingerIcebergError,
+    ingest::ingest,
+    state::{generate_state_at, StateAt},
+};
+use tracing::{debug, info};
+use tracing_subscriber::EnvFilter;
+
+mod catalog;
+mod plugin;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None, rename_all = "kebab-case")]
+struct Args {
+    /// Path to the config file
+    #[arg(long, default_value = "target.json")]
+    config: String,
+    /// Path to the config file
+    #[arg(long)]
+    state: bool,
+    /// Generate the state as of a timestamp (RFC 3339), a snapshot id or a branch
+    #[arg(long)]
+    state_at: Option<StateAt>,
+    /// Mark selected streams in catalog
+    #[arg(long)]
+    catalog: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), SingerIcebergError> {
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        // METRIC lines are logged unless RUST_LOG is set
+        .with_env_filter(
+            EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| EnvFilter::new("error,target_iceberg::metrics=info")),
+        )
+        .init();
+
+    let args = Args::parse();
+
+    let plugin = Arc::new(FileTargetPlugin::new(&args.config).await?);
+
+    if args.state || args.state_at.is_some() {
+        info!("Generating state");
+
+        let state = generate_state_at(plugin.clone(), &args.state_at.unwrap_or_default()).await?;
+
+        let json = serde_json::to_string(&state)?;
+
+        debug!("{}", &json);
+
+        print!("{}", json);
+
+        Ok(())
+    } else if let Some(cat) = args.catalog {
+        info!("Generating catalog");
+
+        let catalog = select_streams(&cat, plugin.clone()).await?;
+
+        let json = serde_json::to_string(&catalog)?;
+
+        debug!("{}", &json);
+
+        print!("{}", json);
+
+        Ok(())
+    } else {
+        info!("Start syncing ...");
+
+        ingest(plugin.clone(), BufReader::new(io::stdin()), io::stdout()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::catalog::FileCatalog;
+    use crate::FileTargetPlugin;
+    use anyhow::{anyhow, Error, Ok};
+    use iceberg_rust::catalog::bucket::Bucket;
+    use iceberg_rust::catalog::identifier::Identifier;
+    use iceberg_rust::catalog::namespace::Namespace;
+    use iceberg_rust::catalog::tabular::Tabular;
+    use iceberg_rust::catalog::Catalog;
+    use std::fs::File;
+    use std::io::{self, BufReader, Write};
+    use std::sync::Arc;
+    use target_iceberg::catalog::select_streams;
+    use target_iceberg::ingest::ingest;
+    use target_iceberg::plugin::TargetPlugin;
+    use target_iceberg::state::generate_state;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_people() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            r#"
+            {
+            "streams": {
+                "people": { "identifier": "public.test.people" }
+            },
+            "warehouse": "/warehouse"
+            }
+        "#
+            .as_bytes(),
+        )?;
+
+        let plugin =
+            Arc::new(FileTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/people/catalog.json", plugin.clone()).await?;
+
+        let input = File::open("../testdata/people/input.txt")?;
+
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        let object_store = plugin.catalog().await?.object_store(Bucket::Local);
+
+        let version_hint = object_store
+            .get(&"warehouse/test/people/metadata/version-hint.text".into())
+            .await?
+            .bytes()
+            .await?;
+
+        let version = std::str::from_utf8(&version_hint)?;
+
+        object_store
+            .head(&format!("warehouse/test/people/metadata/v{}.metadata.json", version).into())
+            .await?;
+
+        // A new catalog only needs the files in the warehouse
+        let catalog = Arc::new(FileCatalog::new("/warehouse", object_store));
+
+        assert_eq!(
+            catalog.list_namespaces(None).await?,
+            vec![Namespace::try_new(&["test".to_owned()])?]
+        );
+
+        assert_eq!(
+            catalog
+                .list_tabulars(&Namespace::try_new(&["test".to_owned()])?)
+                .await?,
+            vec![Identifier::parse("test.people")?]
+        );
+
+        let table = if let Tabular::Table(table) = catalog
+            .load_tabular(&Identifier::parse("test.people")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        assert_eq!(table.metadata().location, "/warehouse/test/people");
+
+        let manifests = table.manifests(None, None).await?;
+
+        assert_eq!(manifests[0].added_rows_count.unwrap(), 100);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_orders() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            r#"
+            {
+            "streams": {
+                "inventory-orders": { "identifier": "public.inventory.orders" },
+                "inventory-customers": { "identifier": "public.inventory.customers" },
+                "inventory-products": { "identifier": "public.inventory.products" }
+            }
+            }
+        "#
+            .as_bytes(),
+        )?;
+
+        let plugin =
+            Arc::new(FileTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/inventory/catalog.json", plugin.clone()).await?;
+
+        let input = File::open("../testdata/inventory/input1.txt")?;
+
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        let input = File::open("../testdata/inventory/input2.txt")?;
+
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        let catalog = plugin.catalog().await?;
+
+        let orders_table = if let Tabular::Table(table) = catalog
+            .clone()
+            .load_tabular(&Identifier::parse("inventory.orders")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        let manifests = orders_table.manifests(None, None).await?;
+
+        assert_eq!(
+            manifests
+                .iter()
+                .map(|x| x.added_rows_count.unwrap() + x.existing_rows_count.unwrap())
+                .sum::<i64>(),
+            4
+        );
+
+        let state = generate_state(plugin.clone()).await?;
+
+        assert_eq!(
+            state["bookmarks"]["inventory-orders"]["version"].to_string(),
+            "1703756002202"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_conflict() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            r#"
+            {
+            "streams": {
+                "people": { "identifier": "public.test.people" }
+            }
+            }
+        "#
+            .as_bytes(),
+        )?;
+
+        let plugin =
+            Arc::new(FileTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/people/catalog.json", plugin.clone()).await?;
+
+        let object_store = plugin.catalog().await?.object_store(Bucket::Local);
+
+        let first = Arc::new(FileCatalog::new("", object_store.clone()));
+        let second = Arc::new(FileCatalog::new("", object_store));
+
+        let identifier = Identifier::parse("test.people")?;
+
+        let Tabular::Table(mut first_table) = first.load_tabular(&identifier).await? else {
+            return Err(anyhow!("Not a table"));
+        };
+        let Tabular::Table(mut second_table) = second.load_tabular(&identifier).await? else {
+            return Err(anyhow!("Not a table"));
+        };
+
+        first_table
+            .new_transaction(None)
+            .update_properties(vec![("first".to_owned(), "true".to_owned())])
+            .commit()
+            .await?;
+
+        // The second catalog would overwrite version 2 of the table
+        assert!(second_table
+            .new_transaction(None)
+            .update_properties(vec![("second".to_owned(), "true".to_owned())])
+            .commit()
+            .await
+            .is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+use std::{collections::HashMap, fs, sync::Arc};
+
+use async_trait::async_trait;
+use iceberg_rust::catalog::Catalog;
+use object_store::ObjectStore;
+use serde::{Deserialize, Serialize};
+use target_iceberg::{
+    error::SingerIcebergError,
+    plugin::{
+        object_store, AuditConfig, BaseConfig, BufferConfig, CommitConfig, MetricsConfig,
+        ObjectStoreConfig, StreamConfig, TargetPlugin,
+    },
+};
+
+use crate::catalog::FileCatalog;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Config {
+    #[serde(flatten)]
+    pub base: BaseConfig,
+    #[serde(flatten)]
+    pub object_store: ObjectStoreConfig,
+    // Directory of the tables, defaults to the bucket
+    pub warehouse: Option<String>,
+}
+
+#[derive(Debug)]
+pub(crate) struct FileTargetPlugin {
+    config: BaseConfig,
+    object_store: Arc<dyn ObjectStore>,
+    catalog: Arc<dyn Catalog>,
+}
+
+impl FileTargetPlugin {
+    pub async fn new(path: &str) -> Result<Self, SingerIcebergError> {
+        let config_json = fs::read_to_string(path)?;
+        let mut config: Config = serde_json::from_str(&config_json)?;
+
+        let (object_store, bucket) =
+            object_store(&config.object_store, config.base.bucket.as_deref())?;
+
+        config.base.bucket = bucket;
+
+        let warehouse = config
+            .warehouse
+            .as_deref()
+            .or(config.base.bucket.as_deref())
+            .unwrap_or("");
+
+        let catalog = Arc::new(FileCatalog::new(warehouse, object_store.clone()));
+
+        Ok(Self {
+            config: config.base,
+            object_store,
+            catalog,
+        })
+    }
+}
+
+#[async_trait]
+impl TargetPlugin for FileTargetPlugin {
+    async fn catalog(&self) -> Result<Arc<dyn Catalog>, SingerIcebergError> {
+        Ok(self.catalog.clone())
+    }
+
+    fn bucket(&self) -> Option<&str> {
+        self.config.bucket.as_deref()
+    }
+
+    fn object_store(&self) -> Arc<dyn ObjectStore> {
+        self.object_store.clone()
+    }
+
+    fn streams(&self) -> &HashMap<String, StreamConfig> {
+        &self.config.streams
+    }
+
+    fn branch(&self) -> &Option<String> {
+        &self.config.branch
+    }
+
+    fn commit(&self) -> &CommitConfig {
+        &self.config.commit
+    }
+
+    fn buffer(&self) -> &BufferConfig {
+        &self.config.buffer
+    }
+
+    fn emit_state(&self) -> bool {
+        self.config.emit_state
+    }
+
+    fn catalog_name(&self) -> Option<&str> {
+        None
+    }
+
+    fn explicit_namespaces(&self) -> bool {
+        true
+    }
+
+    fn assigns_sort_order_ids(&self) -> bool {
+        false
+    }
+
+    fn audit(&self) -> Option<&AuditConfig> {
+        self.config.audit.as_ref()
+    }
+
+    fn state_location(&self) -> Option<&str> {
+        self.config.state_location.as_deref()
+    }
+
+    fn metrics(&self) -> &MetricsConfig {
+        &self.config.metrics
+    }
+}
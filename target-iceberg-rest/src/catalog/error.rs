@@ -0,0 +1,20 @@
+use iceberg_rust::error::Error as IcebergError;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Text(String),
+    #[error("REST catalog returned {0}: {1}")]
+    Response(u16, String),
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    ObjectStore(#[from] object_store::Error),
+}
+
+impl From<Error> for IcebergError {
+    fn from(value: Error) -> Self {
+        IcebergError::InvalidFormat(value.to_string())
+    }
+}
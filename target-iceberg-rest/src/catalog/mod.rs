@@ -0,0 +1,609 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{Arc, Weak},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use dashmap::DashMap;
+use futures::lock::Mutex;
+use iceberg_rust::{
+    catalog::{
+        bucket::Bucket,
+        commit::{CommitTable, CommitView},
+        create::{CreateMaterializedView, CreateTable, CreateView},
+        identifier::Identifier,
+        namespace::Namespace,
+        tabular::Tabular,
+        Catalog,
+    },
+    error::Error as IcebergError,
+    materialized_view::MaterializedView,
+    spec::table_metadata::TableMetadata,
+    table::Table,
+    view::View,
+};
+use object_store::{
+    aws::{AmazonS3Builder, AwsCredential},
+    gcp::{GcpCredential, GoogleCloudStorageBuilder},
+    CredentialProvider, ObjectStore,
+};
+use reqwest::{Client, Method, RequestBuilder, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+use crate::catalog::error::Error;
+
+pub mod error;
+
+static ACCESS_DELEGATION_HEADER: &str = "X-Iceberg-Access-Delegation";
+
+// Tokens and vended credentials are refreshed shortly before they expire
+static TOKEN_EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub enum Authentication {
+    None,
+    // Static bearer token
+    Token(String),
+    // OAuth2 client credentials flow
+    ClientCredentials {
+        client_id: String,
+        client_secret: String,
+        server_uri: String,
+        scope: String,
+    },
+}
+
+#[derive(Debug)]
+pub struct RestCatalog {
+    client: Client,
+    uri: String,
+    authentication: Authentication,
+    token: Mutex<Option<(String, Option<Instant>)>>,
+    object_store: Arc<dyn ObjectStore>,
+    // Object stores created from credentials vended by the catalog, keyed by bucket
+    vended: Arc<DashMap<String, Arc<dyn ObjectStore>>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ConfigResponse {
+    #[serde(default)]
+    defaults: HashMap<String, String>,
+    #[serde(default)]
+    overrides: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct NamespaceBody {
+    namespace: Namespace,
+    #[serde(default)]
+    properties: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListNamespacesResponse {
+    namespaces: Vec<Namespace>,
+}
+
+#[derive(Debug, Serialize)]
+struct UpdateNamespaceRequest {
+    removals: Vec<String>,
+    updates: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListTablesResponse {
+    identifiers: Vec<Identifier>,
+}
+
+// Credentials vended by the catalog for the bucket of a table. They are reloaded with the table
+// shortly before they expire.
+#[derive(Debug)]
+struct VendedCredentials<T> {
+    catalog: Weak<RestCatalog>,
+    identifier: Identifier,
+    parse: CredentialParser<T>,
+    current: Mutex<(Arc<T>, Option<SystemTime>)>,
+}
+
+// Credentials and their expiry from the config of a table
+type CredentialParser<T> = fn(&HashMap<String, String>) -> Option<(T, Option<SystemTime>)>;
+
+#[async_trait]
+impl<T: Debug + Send + Sync> CredentialProvider for VendedCredentials<T> {
+    type Credential = T;
+
+    async fn get_credential(&self) -> object_store::Result<Arc<T>> {
+        let mut current = self.current.lock().await;
+        let expired = current
+            .1
+            .is_some_and(|expires_at| SystemTime::now() + TOKEN_EXPIRY_MARGIN >= expires_at);
+        if expired {
+            let config = match self.catalog.upgrade() {
+                Some(catalog) => catalog
+                    .load_table(&self.identifier)
+                    .await
+                    .map(|response| response.config),
+                None => Err(Error::Text("The REST catalog was dropped.".to_owned())),
+            };
+            let (credential, expires_at) = config
+                .and_then(|config| {
+                    (self.parse)(&config).ok_or(Error::Text(format!(
+                        "The REST catalog didn't vend new credentials for table {}.",
+                        self.identifier
+                    )))
+                })
+                .map_err(|err| object_store::Error::Generic {
+                    store: "REST catalog",
+                    source: Box::new(err),
+                })?;
+            *current = (Arc::new(credential), expires_at);
+        }
+        Ok(current.0.clone())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct LoadTableResponse {
+    metadata: TableMetadata,
+    #[serde(default)]
+    config: HashMap<String, String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct RegisterTableRequest<'a> {
+    name: &'a str,
+    metadata_location: &'a str,
+}
+
+impl RestCatalog {
+    pub async fn new(
+        uri: &str,
+        warehouse: Option<&str>,
+        authentication: Authentication,
+        object_store: Arc<dyn ObjectStore>,
+    ) -> Result<Self, Error> {
+        let mut catalog = RestCatalog {
+            client: Client::new(),
+            uri: uri.trim_end_matches('/').to_owned() + "/v1",
+            authentication,
+            token: Mutex::new(None),
+            object_store,
+            vended: Arc::new(DashMap::new()),
+        };
+
+        // The catalog configuration determines the prefix of all other endpoints
+        let mut request = catalog.request(Method::GET, "/config").await?;
+        if let Some(warehouse) = warehouse {
+            request = request.query(&[("warehouse", warehouse)]);
+        }
+        let config: ConfigResponse = send(request).await?;
+
+        let mut properties = config.defaults;
+        properties.extend(config.overrides);
+
+        if let Some(uri) = properties.get("uri") {
+            catalog.uri = uri.trim_end_matches('/').to_owned() + "/v1";
+        }
+        if let Some(prefix) = properties.get("prefix") {
+            catalog.uri = catalog.uri + "/" + prefix.trim_matches('/');
+        }
+
+        Ok(catalog)
+    }
+
+    async fn request(&self, method: Method, path: &str) -> Result<RequestBuilder, Error> {
+        let request = self.client.request(method, self.uri.clone() + path);
+        Ok(match self.token().await? {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        })
+    }
+
+    async fn token(&self) -> Result<Option<String>, Error> {
+        let (client_id, client_secret, server_uri, scope) = match &self.authentication {
+            Authentication::None => return Ok(None),
+            Authentication::Token(token) => return Ok(Some(token.clone())),
+            Authentication::ClientCredentials {
+                client_id,
+                client_secret,
+                server_uri,
+                scope,
+            } => (client_id, client_secret, server_uri, scope),
+        };
+
+        let mut token = self.token.lock().await;
+
+        if let Some((token, expires_at)) = &*token {
+            if !expires_at.is_some_and(|expires_at| Instant::now() >= expires_at) {
+                return Ok(Some(token.clone()));
+            }
+        }
+
+        let response: TokenResponse = send(self.client.post(server_uri).form(&[
+            ("grant_type", "client_credentials"),
+            ("client_id", client_id),
+            ("client_secret", client_secret),
+            ("scope", scope),
+        ]))
+        .await?;
+
+        let expires_at = response.expires_in.map(|expires_in| {
+            Instant::now() + Duration::from_secs(expires_in).saturating_sub(TOKEN_EXPIRY_MARGIN)
+        });
+
+        *token = Some((response.access_token.clone(), expires_at));
+
+        Ok(Some(response.access_token))
+    }
+
+    async fn exists(&self, path: &str) -> Result<bool, Error> {
+        let response = self.request(Method::HEAD, path).await?.send().await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(false),
+            status if status.is_success() => Ok(true),
+            status => Err(Error::Response(status.as_u16(), path.to_owned())),
+        }
+    }
+
+    async fn load_table(&self, identifier: &Identifier) -> Result<LoadTableResponse, Error> {
+        send(
+            self.request(Method::GET, &table_path(identifier))
+                .await?
+                .header(ACCESS_DELEGATION_HEADER, "vended-credentials"),
+        )
+        .await
+    }
+
+    // Tables are loaded with vended credentials. If the catalog returns storage credentials they
+    // are used for the bucket of the table instead of the configured object store. Every response
+    // with credentials replaces the earlier ones.
+    fn register_credentials(
+        self: &Arc<Self>,
+        identifier: &Identifier,
+        location: &str,
+        config: &HashMap<String, String>,
+    ) -> Result<(), Error> {
+        if config.keys().any(|key| key.starts_with("adls.")) {
+            return Err(Error::Text(format!(
+                "The REST catalog vended ADLS credentials for table {}, which aren't supported.",
+                identifier
+            )));
+        }
+
+        let bucket = Bucket::from_path(location).map_err(|err| Error::Text(err.to_string()))?;
+
+        let object_store: Arc<dyn ObjectStore> = match bucket {
+            Bucket::S3(bucket_name) => {
+                let Some(credentials) = s3_credentials(config) else {
+                    return Ok(());
+                };
+                let mut builder = AmazonS3Builder::new()
+                    .with_bucket_name(bucket_name)
+                    .with_credentials(self.vended_credentials(
+                        identifier,
+                        credentials,
+                        s3_credentials,
+                    ));
+                if let Some(region) = config.get("s3.region").or(config.get("client.region")) {
+                    builder = builder.with_region(region);
+                }
+                if let Some(endpoint) = config.get("s3.endpoint") {
+                    builder = builder
+                        .with_endpoint(endpoint)
+                        .with_allow_http(endpoint.starts_with("http://"));
+                }
+                if let Some(path_style) = config.get("s3.path-style-access") {
+                    builder = builder.with_virtual_hosted_style_request(path_style != "true");
+                }
+                Arc::new(builder.build()?)
+            }
+            Bucket::GCS(bucket_name) => {
+                let Some(credentials) = gcs_credentials(config) else {
+                    return Ok(());
+                };
+                Arc::new(
+                    GoogleCloudStorageBuilder::new()
+                        .with_bucket_name(bucket_name)
+                        .with_credentials(self.vended_credentials(
+                            identifier,
+                            credentials,
+                            gcs_credentials,
+                        ))
+                        .build()?,
+                )
+            }
+            Bucket::Local => return Ok(()),
+        };
+
+        self.vended.insert(bucket.to_string(), object_store);
+
+        Ok(())
+    }
+
+    fn vended_credentials<T: Debug + Send + Sync + 'static>(
+        self: &Arc<Self>,
+        identifier: &Identifier,
+        (credential, expires_at): (T, Option<SystemTime>),
+        parse: CredentialParser<T>,
+    ) -> Arc<dyn CredentialProvider<Credential = T>> {
+        Arc::new(VendedCredentials {
+            catalog: Arc::downgrade(self),
+            identifier: identifier.clone(),
+            parse,
+            current: Mutex::new((Arc::new(credential), expires_at)),
+        })
+    }
+}
+
+fn s3_credentials(config: &HashMap<String, String>) -> Option<(AwsCredential, Option<SystemTime>)> {
+    Some((
+        AwsCredential {
+            key_id: config.get("s3.access-key-id")?.clone(),
+            secret_key: config.get("s3.secret-access-key")?.clone(),
+            token: config.get("s3.session-token").cloned(),
+        },
+        expires_at(config, "s3.session-token-expires-at-ms"),
+    ))
+}
+
+fn gcs_credentials(
+    config: &HashMap<String, String>,
+) -> Option<(GcpCredential, Option<SystemTime>)> {
+    Some((
+        GcpCredential {
+            bearer: config.get("gcs.oauth2.token")?.clone(),
+        },
+        expires_at(config, "gcs.oauth2.token-expires-at"),
+    ))
+}
+
+// Expiry in milliseconds since the epoch
+fn expires_at(config: &HashMap<String, String>, key: &str) -> Option<SystemTime> {
+    config
+        .get(key)
+        .and_then(|x| x.parse::<u64>().ok())
+        .map(|millis| UNIX_EPOCH + Duration::from_millis(millis))
+}
+
+fn namespace_path(namespace: &Namespace) -> String {
+    "/namespaces/".to_owned() + &namespace.url_encode()
+}
+
+fn table_path(identifier: &Identifier) -> String {
+    namespace_path(identifier.namespace())
+        + "/tables/"
+        + &url::form_urlencoded::byte_serialize(identifier.name().as_bytes()).collect::<String>()
+}
+
+async fn send<T: DeserializeOwned>(request: RequestBuilder) -> Result<T, Error> {
+    let response = request.send().await?;
+    let status = response.status();
+    if status.is_success() {
+        Ok(response.json().await?)
+    } else {
+        Err(error_response(status, response.text().await?))
+    }
+}
+
+async fn send_empty(request: RequestBuilder) -> Result<(), Error> {
+    let response = request.send().await?;
+    let status = response.status();
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(error_response(status, response.text().await?))
+    }
+}
+
+// Error responses have the form {"error": {"message": ..., "type": ..., "code": ...}}
+fn error_response(status: StatusCode, body: String) -> Error {
+    let message = serde_json::from_str::<JsonValue>(&body)
+        .ok()
+        .and_then(|x| x["error"]["message"].as_str().map(ToOwned::to_owned))
+        .unwrap_or(body);
+    Error::Response(status.as_u16(), message)
+}
+
+#[async_trait]
+impl Catalog for RestCatalog {
+    async fn create_namespace(
+        &self,
+        namespace: &Namespace,
+        properties: Option<HashMap<String, String>>,
+    ) -> Result<HashMap<String, String>, IcebergError> {
+        let response: NamespaceBody = send(self.request(Method::POST, "/namespaces").await?.json(
+            &NamespaceBody {
+                namespace: namespace.clone(),
+                properties: properties.unwrap_or_default(),
+            },
+        ))
+        .await?;
+        Ok(response.properties)
+    }
+    async fn drop_namespace(&self, namespace: &Namespace) -> Result<(), IcebergError> {
+        Ok(send_empty(
+            self.request(Method::DELETE, &namespace_path(namespace))
+                .await?,
+        )
+        .await?)
+    }
+    async fn load_namespace(
+        &self,
+        namespace: &Namespace,
+    ) -> Result<HashMap<String, String>, IcebergError> {
+        let response: NamespaceBody = send(
+            self.request(Method::GET, &namespace_path(namespace))
+                .await?,
+        )
+        .await?;
+        Ok(response.properties)
+    }
+    async fn update_namespace(
+        &self,
+        namespace: &Namespace,
+        updates: Option<HashMap<String, String>>,
+        removals: Option<Vec<String>>,
+    ) -> Result<(), IcebergError> {
+        send::<JsonValue>(
+            self.request(Method::POST, &(namespace_path(namespace) + "/properties"))
+                .await?
+                .json(&UpdateNamespaceRequest {
+                    removals: removals.unwrap_or_default(),
+                    updates: updates.unwrap_or_default(),
+                }),
+        )
+        .await?;
+        Ok(())
+    }
+    async fn namespace_exists(&self, namespace: &Namespace) -> Result<bool, IcebergError> {
+        Ok(self.exists(&namespace_path(namespace)).await?)
+    }
+    async fn list_tabulars(&self, namespace: &Namespace) -> Result<Vec<Identifier>, IcebergError> {
+        let response: ListTablesResponse = send(
+            self.request(Method::GET, &(namespace_path(namespace) + "/tables"))
+                .await?,
+        )
+        .await?;
+        Ok(response.identifiers)
+    }
+    async fn list_namespaces(&self, parent: Option<&str>) -> Result<Vec<Namespace>, IcebergError> {
+        let mut request = self.request(Method::GET, "/namespaces").await?;
+        if let Some(parent) = parent {
+            let parent =
+                Namespace::try_new(&parent.split('.').map(ToOwned::to_owned).collect::<Vec<_>>())?;
+            request = request.query(&[("parent", parent.url_encode())]);
+        }
+        let response: ListNamespacesResponse = send(request).await?;
+        Ok(response.namespaces)
+    }
+    async fn tabular_exists(&self, identifier: &Identifier) -> Result<bool, IcebergError> {
+        Ok(self.exists(&table_path(identifier)).await?)
+    }
+    async fn drop_table(&self, identifier: &Identifier) -> Result<(), IcebergError> {
+        Ok(send_empty(
+            self.request(Method::DELETE, &table_path(identifier))
+                .await?,
+        )
+        .await?)
+    }
+    async fn drop_view(&self, _identifier: &Identifier) -> Result<(), IcebergError> {
+        Err(IcebergError::NotSupported(
+            "views in the REST catalog".to_owned(),
+        ))
+    }
+    async fn drop_materialized_view(&self, _identifier: &Identifier) -> Result<(), IcebergError> {
+        Err(IcebergError::NotSupported(
+            "materialized views in the REST catalog".to_owned(),
+        ))
+    }
+    async fn load_tabular(
+        self: Arc<Self>,
+        identifier: &Identifier,
+    ) -> Result<Tabular, IcebergError> {
+        let response = self.load_table(identifier).await?;
+        self.register_credentials(identifier, &response.metadata.location, &response.config)?;
+        Ok(Tabular::Table(
+            Table::new(identifier.clone(), self.clone(), response.metadata).await?,
+        ))
+    }
+    async fn create_table(
+        self: Arc<Self>,
+        identifier: Identifier,
+        create_table: CreateTable,
+    ) -> Result<Table, IcebergError> {
+        if !self.namespace_exists(identifier.namespace()).await? {
+            self.create_namespace(identifier.namespace(), None).await?;
+        }
+        let response: LoadTableResponse = send(
+            self.request(
+                Method::POST,
+                &(namespace_path(identifier.namespace()) + "/tables"),
+            )
+            .await?
+            .header(ACCESS_DELEGATION_HEADER, "vended-credentials")
+            .json(&create_table),
+        )
+        .await?;
+        self.register_credentials(&identifier, &response.metadata.location, &response.config)?;
+        Table::new(identifier, self.clone(), response.metadata).await
+    }
+    async fn create_view(
+        self: Arc<Self>,
+        _identifier: Identifier,
+        _create_view: CreateView<Option<()>>,
+    ) -> Result<View, IcebergError> {
+        Err(IcebergError::NotSupported(
+            "views in the REST catalog".to_owned(),
+        ))
+    }
+    async fn create_materialized_view(
+        self: Arc<Self>,
+        _identifier: Identifier,
+        _create_view: CreateMaterializedView,
+    ) -> Result<MaterializedView, IcebergError> {
+        Err(IcebergError::NotSupported(
+            "materialized views in the REST catalog".to_owned(),
+        ))
+    }
+    async fn update_table(self: Arc<Self>, commit: CommitTable) -> Result<Table, IcebergError> {
+        let identifier = commit.identifier.clone();
+        let response: LoadTableResponse = send(
+            self.request(Method::POST, &table_path(&identifier))
+                .await?
+                .json(&commit),
+        )
+        .await?;
+        self.register_credentials(&identifier, &response.metadata.location, &response.config)?;
+        Table::new(identifier, self.clone(), response.metadata).await
+    }
+    async fn update_view(self: Arc<Self>, _commit: CommitView) -> Result<View, IcebergError> {
+        Err(IcebergError::NotSupported(
+            "views in the REST catalog".to_owned(),
+        ))
+    }
+    async fn update_materialized_view(
+        self: Arc<Self>,
+        _commit: CommitView,
+    ) -> Result<MaterializedView, IcebergError> {
+        Err(IcebergError::NotSupported(
+            "materialized views in the REST catalog".to_owned(),
+        ))
+    }
+    async fn register_table(
+        self: Arc<Self>,
+        identifier: Identifier,
+        metadata_location: &str,
+    ) -> Result<Table, IcebergError> {
+        let response: LoadTableResponse = send(
+            self.request(
+                Method::POST,
+                &(namespace_path(identifier.namespace()) + "/register"),
+            )
+            .await?
+            .json(&RegisterTableRequest {
+                name: identifier.name(),
+                metadata_location,
+            }),
+        )
+        .await?;
+        self.register_credentials(&identifier, &response.metadata.location, &response.config)?;
+        Table::new(identifier, self.clone(), response.metadata).await
+    }
+    fn object_store(&self, bucket: Bucket) -> Arc<dyn ObjectStore> {
+        self.vended
+            .get(&bucket.to_string())
+            .map(|x| x.value().clone())
+            .unwrap_or_else(|| self.object_store.clone())
+    }
+}
@@ -0,0 +1,413 @@
+use std::{
+    io::{self, BufReader},
+    sync::Arc,
+};
+
+use clap::Parser;
+use plugin::RestTargetPlugin;
+use target_iceberg::{
+    catalog::select_streams,
+    error::SingerIcebergError,
+    ingest::ingest,
+    state::{generate_state_at, StateAt},
+};
+use tracing::{debug, info};
+use tracing_subscriber::EnvFilter;
+
+mod catalog;
+#[cfg(test)]
+mod mock;
+mod plugin;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None, rename_all = "kebab-case")]
+struct Args {
+    /// Path to the config file
+    #[arg(long, default_value = "target.json")]
+    config: String,
+    /// Path to the config file
+    #[arg(long)]
+    state: bool,
+    /// Generate the state as of a timestamp (RFC 3339), a snapshot id or a branch
+    #[arg(long)]
+    state_at: Option<StateAt>,
+    /// Mark selected streams in catalog
+    #[arg(long)]
+    catalog: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), SingerIcebergError> {
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        // METRIC lines are logged unless RUST_LOG is set
+        .with_env_filter(
+            EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| EnvFilter::new("error,target_iceberg::metrics=info")),
+        )
+        .init();
+
+    let args = Args::parse();
+
+    let plugin = Arc::new(RestTargetPlugin::new(&args.config).await?);
+
+    if args.state || args.state_at.is_some() {
+        info!("Generating state");
+
+        let state = generate_state_at(plugin.clone(), &args.state_at.unwrap_or_default()).await?;
+
+        let json = serde_json::to_string(&state)?;
+
+        debug!("{}", &json);
+
+        print!("{}", json);
+
+        Ok(())
+    } else if let Some(cat) = args.catalog {
+        info!("Generating catalog");
+
+        let catalog = select_streams(&cat, plugin.clone()).await?;
+
+        let json = serde_json::to_string(&catalog)?;
+
+        debug!("{}", &json);
+
+        print!("{}", json);
+
+        Ok(())
+    } else {
+        info!("Start syncing ...");
+
+        ingest(plugin.clone(), BufReader::new(io::stdin()), io::stdout()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mock::MockCatalog;
+    use crate::RestTargetPlugin;
+    use anyhow::{anyhow, Error, Ok};
+    use iceberg_rust::catalog::bucket::Bucket;
+    use iceberg_rust::catalog::identifier::Identifier;
+    use iceberg_rust::catalog::namespace::Namespace;
+    use iceberg_rust::catalog::tabular::Tabular;
+    use std::collections::HashMap;
+    use std::fs::File;
+    use std::io::{self, BufReader, Write};
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use target_iceberg::catalog::select_streams;
+    use target_iceberg::ingest::ingest;
+    use target_iceberg::plugin::TargetPlugin;
+    use target_iceberg::state::generate_state;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_client_credentials() -> Result<(), Error> {
+        let mock = Arc::new(
+            MockCatalog::new("warehouse", "secret-token").with_credential("client", "secret"),
+        );
+        let uri = mock.clone().serve();
+
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            format!(
+                r#"
+            {{
+            "streams": {{
+                "people": {{ "identifier": "public.test.people" }}
+            }},
+            "catalogUri": "{}",
+            "warehouse": "warehouse",
+            "credential": "client:secret"
+            }}
+        "#,
+                uri
+            )
+            .as_bytes(),
+        )?;
+
+        let plugin =
+            Arc::new(RestTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/people/catalog.json", plugin.clone()).await?;
+
+        let input = File::open("../testdata/people/input.txt")?;
+
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        let catalog = plugin.catalog().await?;
+
+        let table = if let Tabular::Table(table) = catalog
+            .load_tabular(&Identifier::parse("test.people")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        let manifests = table.manifests(None, None).await?;
+
+        assert_eq!(manifests[0].added_rows_count.unwrap(), 100);
+
+        // The token is reused until it expires
+        assert_eq!(mock.tokens_issued.load(Ordering::SeqCst), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bearer_token() -> Result<(), Error> {
+        let mock = Arc::new(MockCatalog::new("warehouse", "secret-token"));
+        let uri = mock.clone().serve();
+
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            format!(
+                r#"
+            {{
+            "streams": {{
+                "inventory-orders": {{ "identifier": "public.inventory.orders" }},
+                "inventory-customers": {{ "identifier": "public.inventory.customers" }},
+                "inventory-products": {{ "identifier": "public.inventory.products" }}
+            }},
+            "catalogUri": "{}",
+            "warehouse": "warehouse",
+            "token": "secret-token"
+            }}
+        "#,
+                uri
+            )
+            .as_bytes(),
+        )?;
+
+        let plugin =
+            Arc::new(RestTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/inventory/catalog.json", plugin.clone()).await?;
+
+        let input = File::open("../testdata/inventory/input1.txt")?;
+
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        let input = File::open("../testdata/inventory/input2.txt")?;
+
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        let catalog = plugin.catalog().await?;
+
+        let orders_table = if let Tabular::Table(table) = catalog
+            .clone()
+            .load_tabular(&Identifier::parse("inventory.orders")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        let manifests = orders_table.manifests(None, None).await?;
+
+        assert_eq!(
+            manifests
+                .iter()
+                .map(|x| x.added_rows_count.unwrap() + x.existing_rows_count.unwrap())
+                .sum::<i64>(),
+            4
+        );
+
+        let state = generate_state(plugin.clone()).await?;
+
+        assert_eq!(
+            state["bookmarks"]["inventory-orders"]["version"].to_string(),
+            "1703756002202"
+        );
+
+        // Requests with a wrong token are rejected
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            format!(
+                r#"
+            {{
+            "streams": {{}},
+            "catalogUri": "{}",
+            "warehouse": "warehouse",
+            "token": "wrong-token"
+            }}
+        "#,
+                uri
+            )
+            .as_bytes(),
+        )?;
+
+        assert!(
+            RestTargetPlugin::new(config_path.as_path().to_str().unwrap())
+                .await
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_nested_namespace() -> Result<(), Error> {
+        let mock = Arc::new(MockCatalog::new("warehouse", "secret-token"));
+        let uri = mock.clone().serve();
+
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            format!(
+                r#"
+            {{
+            "streams": {{
+                "inventory-orders": {{
+                    "identifier": "public.bronze.inventory.orders",
+                    "sortBy": [{{ "column": "id" }}]
+                }}
+            }},
+            "catalogUri": "{}",
+            "warehouse": "warehouse",
+            "token": "secret-token"
+            }}
+        "#,
+                uri
+            )
+            .as_bytes(),
+        )?;
+
+        let plugin =
+            Arc::new(RestTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/deletes/catalog.json", plugin.clone()).await?;
+
+        let catalog = plugin.catalog().await?;
+
+        // Missing parent namespaces are created before the table
+        assert!(
+            catalog
+                .namespace_exists(&Namespace::try_new(&["bronze".to_owned()])?)
+                .await?
+        );
+        assert!(
+            catalog
+                .namespace_exists(&Namespace::try_new(&[
+                    "bronze".to_owned(),
+                    "inventory".to_owned()
+                ])?)
+                .await?
+        );
+
+        let input = File::open("../testdata/deletes/input1.txt")?;
+
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        let orders_table = if let Tabular::Table(table) = catalog
+            .clone()
+            .load_tabular(&Identifier::parse("bronze.inventory.orders")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        let manifests = orders_table.manifests(None, None).await?;
+
+        assert!(!manifests.is_empty());
+
+        // The sort order is part of the create request
+        assert_eq!(orders_table.metadata().default_sort_order_id, 1);
+        assert_eq!(orders_table.metadata().sort_orders[&1].fields.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_vended_credentials() -> Result<(), Error> {
+        let mock = Arc::new(
+            MockCatalog::new("warehouse", "secret-token").with_vended_credentials(HashMap::from([
+                ("s3.access-key-id".to_owned(), "vended-key".to_owned()),
+                (
+                    "s3.secret-access-key".to_owned(),
+                    "vended-secret".to_owned(),
+                ),
+                ("s3.session-token".to_owned(), "vended-session".to_owned()),
+                ("s3.region".to_owned(), "us-east-1".to_owned()),
+                (
+                    "s3.session-token-expires-at-ms".to_owned(),
+                    "4102444800000".to_owned(),
+                ),
+            ])),
+        );
+        let uri = mock.clone().serve();
+
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            format!(
+                r#"
+            {{
+            "streams": {{
+                "people": {{ "identifier": "public.test.people" }}
+            }},
+            "bucket": "s3://vended",
+            "catalogUri": "{}",
+            "warehouse": "warehouse",
+            "token": "secret-token"
+            }}
+        "#,
+                uri
+            )
+            .as_bytes(),
+        )?;
+
+        let plugin =
+            Arc::new(RestTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        let catalog = plugin.catalog().await?;
+
+        // Without vended credentials the configured object store is used
+        assert!(catalog
+            .object_store(Bucket::S3("vended"))
+            .to_string()
+            .starts_with("InMemory"));
+
+        select_streams("../testdata/people/catalog.json", plugin.clone()).await?;
+
+        let table = if let Tabular::Table(table) = catalog
+            .load_tabular(&Identifier::parse("test.people")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        assert_eq!(table.metadata().location, "s3://vended/public/test/people");
+
+        assert_eq!(table.object_store().to_string(), "AmazonS3(vended)");
+
+        Ok(())
+    }
+}
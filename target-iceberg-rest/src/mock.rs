@@ -0,0 +1,269 @@
+// Minimal in-memory implementation of the Iceberg REST catalog used by the tests
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    net::TcpListener,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use hyper::{
+    body::to_bytes,
+    header::AUTHORIZATION,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use iceberg_rust::{
+    catalog::{
+        commit::{apply_table_updates, check_table_requirements, CommitTable},
+        create::CreateTable,
+        identifier::Identifier,
+        namespace::Namespace,
+    },
+    spec::table_metadata::TableMetadata,
+};
+use serde_json::{json, Value as JsonValue};
+
+const PREFIX: &str = "test";
+
+#[derive(Debug, Default)]
+pub(crate) struct MockCatalog {
+    warehouse: String,
+    credential: Option<(String, String)>,
+    token: String,
+    vended: HashMap<String, String>,
+    pub(crate) tokens_issued: AtomicUsize,
+    namespaces: Mutex<HashMap<Namespace, HashMap<String, String>>>,
+    tables: Mutex<HashMap<Identifier, TableMetadata>>,
+}
+
+impl MockCatalog {
+    pub(crate) fn new(warehouse: &str, token: &str) -> Self {
+        MockCatalog {
+            warehouse: warehouse.to_owned(),
+            token: token.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn with_credential(mut self, client_id: &str, client_secret: &str) -> Self {
+        self.credential = Some((client_id.to_owned(), client_secret.to_owned()));
+        self
+    }
+
+    pub(crate) fn with_vended_credentials(mut self, config: HashMap<String, String>) -> Self {
+        self.vended = config;
+        self
+    }
+
+    // Start the server on a random port and return its uri
+    pub(crate) fn serve(self: Arc<Self>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let uri = format!("http://{}", listener.local_addr().unwrap());
+        let server = Server::from_tcp(listener)
+            .unwrap()
+            .serve(make_service_fn(move |_| {
+                let catalog = self.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |request| {
+                        let catalog = catalog.clone();
+                        async move { Ok::<_, Infallible>(catalog.handle(request).await) }
+                    }))
+                }
+            }));
+        tokio::spawn(server);
+        uri
+    }
+
+    async fn handle(&self, request: Request<Body>) -> Response<Body> {
+        let method = request.method().clone();
+        let path = request.uri().path().to_owned();
+        let query = request.uri().query().unwrap_or("").to_owned();
+        let authorized = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|x| x.to_str().ok())
+            == Some(&format!("Bearer {}", self.token));
+        let body = to_bytes(request.into_body()).await.unwrap();
+
+        let segments = path
+            .trim_start_matches("/v1/")
+            .split('/')
+            .collect::<Vec<_>>();
+
+        if let (&Method::POST, ["oauth", "tokens"]) = (&method, segments.as_slice()) {
+            return self.issue_token(&String::from_utf8_lossy(&body));
+        }
+
+        if !authorized {
+            return error(StatusCode::UNAUTHORIZED, "Not authorized");
+        }
+
+        match (&method, segments.as_slice()) {
+            (&Method::GET, ["config"]) => {
+                if query != format!("warehouse={}", self.warehouse) {
+                    return error(StatusCode::BAD_REQUEST, "Unknown warehouse");
+                }
+                response(
+                    StatusCode::OK,
+                    json!({"defaults": {}, "overrides": {"prefix": PREFIX}}),
+                )
+            }
+            (_, [PREFIX, rest @ ..]) => self.route(&method, rest, &body),
+            _ => error(StatusCode::NOT_FOUND, "Unknown endpoint"),
+        }
+    }
+
+    fn issue_token(&self, body: &str) -> Response<Body> {
+        let form = body
+            .split('&')
+            .filter_map(|x| x.split_once('='))
+            .collect::<HashMap<_, _>>();
+        let Some((client_id, client_secret)) = &self.credential else {
+            return error(StatusCode::BAD_REQUEST, "Client credentials not supported");
+        };
+        if form.get("grant_type") != Some(&"client_credentials")
+            || form.get("client_id") != Some(&client_id.as_str())
+            || form.get("client_secret") != Some(&client_secret.as_str())
+        {
+            return error(StatusCode::UNAUTHORIZED, "Invalid client credentials");
+        }
+        self.tokens_issued.fetch_add(1, Ordering::SeqCst);
+        response(
+            StatusCode::OK,
+            json!({
+                "access_token": self.token,
+                "token_type": "bearer",
+                "issued_token_type": "urn:ietf:params:oauth:token-type:access_token",
+                "expires_in": 3600
+            }),
+        )
+    }
+
+    fn route(&self, method: &Method, segments: &[&str], body: &[u8]) -> Response<Body> {
+        let mut namespaces = self.namespaces.lock().unwrap();
+        let mut tables = self.tables.lock().unwrap();
+        match (method, segments) {
+            (&Method::GET, ["namespaces"]) => response(
+                StatusCode::OK,
+                json!({ "namespaces": namespaces.keys().collect::<Vec<_>>() }),
+            ),
+            (&Method::POST, ["namespaces"]) => {
+                let request: JsonValue = serde_json::from_slice(body).unwrap();
+                let namespace: Namespace =
+                    serde_json::from_value(request["namespace"].clone()).unwrap();
+                namespaces.insert(namespace, HashMap::new());
+                response(StatusCode::OK, request)
+            }
+            (&Method::HEAD, ["namespaces", namespace]) => {
+                let namespace = Namespace::from_url_encoded(namespace).unwrap();
+                if namespaces.contains_key(&namespace) {
+                    empty(StatusCode::NO_CONTENT)
+                } else {
+                    empty(StatusCode::NOT_FOUND)
+                }
+            }
+            (&Method::GET, ["namespaces", namespace, "tables"]) => {
+                let namespace = Namespace::from_url_encoded(namespace).unwrap();
+                response(
+                    StatusCode::OK,
+                    json!({
+                        "identifiers": tables
+                            .keys()
+                            .filter(|x| x.namespace() == &namespace)
+                            .collect::<Vec<_>>()
+                    }),
+                )
+            }
+            (&Method::POST, ["namespaces", namespace, "tables"]) => {
+                let namespace = Namespace::from_url_encoded(namespace).unwrap();
+                if !namespaces.contains_key(&namespace) {
+                    return error(StatusCode::NOT_FOUND, "Namespace does not exist");
+                }
+                let create: CreateTable = serde_json::from_slice(body).unwrap();
+                let identifier = Identifier::new(&namespace, &create.name);
+                let mut metadata: TableMetadata = create.try_into().unwrap();
+                // Like REST catalogs the write order gets the first id after the unsorted order
+                if let Some(mut sort_order) = metadata
+                    .sort_orders
+                    .remove(&0)
+                    .filter(|sort_order| !sort_order.fields.is_empty())
+                {
+                    sort_order.order_id = 1;
+                    metadata.default_sort_order_id = 1;
+                    metadata.sort_orders.insert(1, sort_order);
+                }
+                metadata.sort_orders.entry(0).or_default();
+                let result = self.load_result(&metadata);
+                tables.insert(identifier, metadata);
+                result
+            }
+            (method, ["namespaces", namespace, "tables", table]) => {
+                let namespace = Namespace::from_url_encoded(namespace).unwrap();
+                let table = url::form_urlencoded::parse(table.as_bytes())
+                    .map(|(name, _)| name)
+                    .next()
+                    .unwrap_or_default();
+                let identifier = Identifier::new(&namespace, &table);
+                let Some(metadata) = tables.get_mut(&identifier) else {
+                    return match *method {
+                        Method::HEAD => empty(StatusCode::NOT_FOUND),
+                        _ => error(StatusCode::NOT_FOUND, "Table does not exist"),
+                    };
+                };
+                match *method {
+                    Method::HEAD => empty(StatusCode::NO_CONTENT),
+                    Method::GET => self.load_result(metadata),
+                    Method::POST => {
+                        let commit: CommitTable = serde_json::from_slice(body).unwrap();
+                        if !check_table_requirements(&commit.requirements, metadata) {
+                            return error(StatusCode::CONFLICT, "Requirements failed");
+                        }
+                        apply_table_updates(metadata, commit.updates).unwrap();
+                        self.load_result(metadata)
+                    }
+                    _ => error(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed"),
+                }
+            }
+            _ => error(StatusCode::NOT_FOUND, "Unknown endpoint"),
+        }
+    }
+
+    fn load_result(&self, metadata: &TableMetadata) -> Response<Body> {
+        response(
+            StatusCode::OK,
+            json!({
+                "metadata-location": format!(
+                    "{}/metadata/{}.metadata.json",
+                    metadata.location, metadata.last_sequence_number
+                ),
+                "metadata": metadata,
+                "config": self.vended,
+            }),
+        )
+    }
+}
+
+fn response(status: StatusCode, body: JsonValue) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn empty(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap()
+}
+
+fn error(status: StatusCode, message: &str) -> Response<Body> {
+    response(
+        status,
+        json!({"error": {"message": message, "type": "MockException", "code": status.as_u16()}}),
+    )
+}
@@ -0,0 +1,155 @@
+use std::{collections::HashMap, fs, sync::Arc};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use iceberg_rust::{catalog::Catalog, error::Error as IcebergError};
+use object_store::ObjectStore;
+use serde::{Deserialize, Serialize};
+use target_iceberg::{
+    error::SingerIcebergError,
+    plugin::{
+        object_store, AuditConfig, BaseConfig, BufferConfig, CommitConfig, MetricsConfig,
+        ObjectStoreConfig, StreamConfig, TargetPlugin,
+    },
+};
+
+use crate::catalog::{Authentication, RestCatalog};
+
+static DEFAULT_SCOPE: &str = "catalog";
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Config {
+    #[serde(flatten)]
+    pub base: BaseConfig,
+    #[serde(flatten)]
+    pub object_store: ObjectStoreConfig,
+    // Base URI of the REST catalog
+    pub catalog_uri: String,
+    // Warehouse requested from the catalog
+    pub warehouse: Option<String>,
+    // Client credentials in the form "client_id:client_secret" used to obtain an OAuth2 token
+    pub credential: Option<String>,
+    // Bearer token used for all requests
+    pub token: Option<String>,
+    // Token endpoint for the client credentials flow, defaults to the catalog's endpoint
+    pub oauth2_server_uri: Option<String>,
+    // Scope requested with the client credentials
+    pub scope: Option<String>,
+}
+
+#[derive(Debug)]
+pub(crate) struct RestTargetPlugin {
+    config: BaseConfig,
+    object_store: Arc<dyn ObjectStore>,
+    catalog: Arc<dyn Catalog>,
+}
+
+impl RestTargetPlugin {
+    pub async fn new(path: &str) -> Result<Self, SingerIcebergError> {
+        let config_json = fs::read_to_string(path)?;
+        let mut config: Config = serde_json::from_str(&config_json)?;
+
+        let (object_store, bucket) =
+            object_store(&config.object_store, config.base.bucket.as_deref())?;
+
+        config.base.bucket = bucket;
+
+        let authentication = match (&config.token, &config.credential) {
+            (Some(token), _) => Authentication::Token(token.clone()),
+            (None, Some(credential)) => {
+                let (client_id, client_secret) =
+                    credential
+                        .split_once(':')
+                        .ok_or(SingerIcebergError::Anyhow(anyhow!(
+                            "The credential has to be of the form \"client_id:client_secret\"."
+                        )))?;
+                Authentication::ClientCredentials {
+                    client_id: client_id.to_owned(),
+                    client_secret: client_secret.to_owned(),
+                    server_uri: config.oauth2_server_uri.clone().unwrap_or(
+                        config.catalog_uri.trim_end_matches('/').to_owned() + "/v1/oauth/tokens",
+                    ),
+                    scope: config.scope.clone().unwrap_or(DEFAULT_SCOPE.to_owned()),
+                }
+            }
+            (None, None) => Authentication::None,
+        };
+
+        let catalog = Arc::new(
+            RestCatalog::new(
+                &config.catalog_uri,
+                config.warehouse.as_deref(),
+                authentication,
+                object_store.clone(),
+            )
+            .await
+            .map_err(IcebergError::from)?,
+        );
+
+        Ok(Self {
+            config: config.base,
+            object_store,
+            catalog,
+        })
+    }
+}
+
+#[async_trait]
+impl TargetPlugin for RestTargetPlugin {
+    async fn catalog(&self) -> Result<Arc<dyn Catalog>, SingerIcebergError> {
+        Ok(self.catalog.clone())
+    }
+
+    fn bucket(&self) -> Option<&str> {
+        self.config.bucket.as_deref()
+    }
+
+    fn object_store(&self) -> Arc<dyn ObjectStore> {
+        self.object_store.clone()
+    }
+
+    fn streams(&self) -> &HashMap<String, StreamConfig> {
+        &self.config.streams
+    }
+
+    fn branch(&self) -> &Option<String> {
+        &self.config.branch
+    }
+
+    fn commit(&self) -> &CommitConfig {
+        &self.config.commit
+    }
+
+    fn buffer(&self) -> &BufferConfig {
+        &self.config.buffer
+    }
+
+    fn emit_state(&self) -> bool {
+        self.config.emit_state
+    }
+
+    fn catalog_name(&self) -> Option<&str> {
+        None
+    }
+
+    fn explicit_namespaces(&self) -> bool {
+        true
+    }
+
+    fn assigns_sort_order_ids(&self) -> bool {
+        true
+    }
+
+    fn audit(&self) -> Option<&AuditConfig> {
+        self.config.audit.as_ref()
+    }
+
+    fn state_location(&self) -> Option<&str> {
+        self.config.state_location.as_deref()
+    }
+
+    fn metrics(&self) -> &MetricsConfig {
+        &self.config.metrics
+    }
+}
@@ -6,7 +6,10 @@ use std::{
 use clap::Parser;
 use plugin::SqlTargetPlugin;
 use target_iceberg::{
-    catalog::select_streams, error::SingerIcebergError, ingest::ingest, state::generate_state,
+    catalog::select_streams,
+    error::SingerIcebergError,
+    ingest::ingest,
+    state::{generate_state_at, StateAt},
 };
 use tracing::{debug, info};
 use tracing_subscriber::EnvFilter;
@@ -22,6 +25,9 @@ struct Args {
     /// Path to the config file
     #[arg(long)]
     state: bool,
+    /// Generate the state as of a timestamp (RFC 3339), a snapshot id or a branch
+    #[arg(long)]
+    state_at: Option<StateAt>,
     /// Mark selected streams in catalog
     #[arg(long)]
     catalog: Option<String>,
@@ -31,23 +37,27 @@ struct Args {
 async fn main() -> Result<(), SingerIcebergError> {
     tracing_subscriber::fmt()
         .with_writer(std::io::stderr)
-        .with_env_filter(EnvFilter::from_default_env())
+        // METRIC lines are logged unless RUST_LOG is set
+        .with_env_filter(
+            EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| EnvFilter::new("error,target_iceberg::metrics=info")),
+        )
         .init();
 
     let args = Args::parse();
 
     let plugin = Arc::new(SqlTargetPlugin::new(&args.config).await?);
 
-    if args.state {
+    if args.state || args.state_at.is_some() {
         info!("Generating state");
 
-        let state = generate_state(plugin.clone()).await?;
+        let state = generate_state_at(plugin.clone(), &args.state_at.unwrap_or_default()).await?;
 
         let json = serde_json::to_string(&state)?;
 
         debug!("{}", &json);
 
-        print!("{}", &json);
+        print!("{}", json);
 
         Ok(())
     } else if let Some(cat) = args.catalog {
@@ -59,13 +69,13 @@ async fn main() -> Result<(), SingerIcebergError> {
 
         debug!("{}", &json);
 
-        print!("{}", &json);
+        print!("{}", json);
 
         Ok(())
     } else {
         info!("Start syncing ...");
 
-        ingest(plugin.clone(), &mut BufReader::new(io::stdin())).await
+        ingest(plugin.clone(), BufReader::new(io::stdin()), io::stdout()).await
     }
 }
 
@@ -73,10 +83,15 @@ async fn main() -> Result<(), SingerIcebergError> {
 mod tests {
     use crate::SqlTargetPlugin;
     use anyhow::{anyhow, Error, Ok};
+    use futures::TryStreamExt;
     use iceberg_rust::catalog::identifier::Identifier;
     use iceberg_rust::catalog::tabular::Tabular;
+    use iceberg_rust::spec::manifest::Content;
+    use iceberg_rust::spec::table_metadata::FormatVersion;
+    use iceberg_rust::spec::types::{PrimitiveType, StructType, Type};
+    use iceberg_rust::spec::values::Value;
     use std::fs::File;
-    use std::io::{BufReader, Write};
+    use std::io::{self, BufReader, Cursor, Write};
     use std::sync::Arc;
     use target_iceberg::catalog::select_streams;
     use target_iceberg::ingest::ingest;
@@ -111,7 +126,193 @@ mod tests {
 
         let input = File::open("../testdata/people/input.txt")?;
 
-        ingest(plugin.clone(), &mut BufReader::new(input)).await?;
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        let catalog = plugin.catalog().await?;
+
+        let table = if let Tabular::Table(table) = catalog
+            .load_tabular(&Identifier::parse("test.people")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        let manifests = table.manifests(None, None).await?;
+
+        assert_eq!(manifests[0].added_rows_count.unwrap(), 100);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_append_without_activate_version() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            r#"
+            {
+            "streams": {
+                "people": { "identifier": "public.test.people" }
+            },
+            "catalogUrl": "sqlite://",
+            "catalogName": "public"
+            }
+        "#
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/people/catalog.json", plugin.clone()).await?;
+
+        // Without ACTIVATE_VERSION messages the second run appends to the rows of the first
+        for _ in 0..2 {
+            let input = File::open("../testdata/people/input.txt")?;
+
+            ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+        }
+
+        let catalog = plugin.catalog().await?;
+
+        let table = if let Tabular::Table(table) = catalog
+            .load_tabular(&Identifier::parse("test.people")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        let manifests = table.manifests(None, None).await?;
+
+        assert_eq!(
+            manifests
+                .iter()
+                .map(|x| x.added_rows_count.unwrap() + x.existing_rows_count.unwrap())
+                .sum::<i64>(),
+            200
+        );
+
+        Ok(())
+    }
+    #[tokio::test]
+    async fn test_buffer() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        let report_path = tempdir.path().join("report.json");
+
+        config_file.write_all(
+            format!(
+                r#"
+            {{
+            "streams": {{
+                "people": {{ "identifier": "public.test.people" }}
+            }},
+            "catalogUrl": "sqlite://",
+            "catalogName": "public",
+            "buffer": {{ "records": 100, "memory": 16384 }},
+            "metrics": {{ "report": "{}" }}
+            }}
+        "#,
+                report_path.display()
+            )
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/people/catalog.json", plugin.clone()).await?;
+
+        // Repeat the records of the people stream to get an input much larger than the buffer
+        let lines = std::fs::read_to_string("../testdata/people/input.txt")?;
+        let lines = lines.lines().collect::<Vec<_>>();
+        let (schema, records, state) = (&lines[..2], &lines[2..102], &lines[102]);
+        let mut input = schema.join("\n");
+        for _ in 0..200 {
+            input.push('\n');
+            input.push_str(&records.join("\n"));
+        }
+        input.push('\n');
+        input.push_str(state);
+
+        ingest(plugin.clone(), Cursor::new(input), io::sink()).await?;
+
+        let catalog = plugin.catalog().await?;
+
+        let table = if let Tabular::Table(table) = catalog
+            .load_tabular(&Identifier::parse("test.people")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        let manifests = table.manifests(None, None).await?;
+
+        assert_eq!(
+            manifests
+                .iter()
+                .map(|x| x.added_rows_count.unwrap() + x.existing_rows_count.unwrap())
+                .sum::<i64>(),
+            20000
+        );
+
+        // The budget covers the queued messages and the records that weren't written yet
+        let report: serde_json::Value = serde_json::from_slice(&std::fs::read(report_path)?)?;
+        assert!(report["buffer"]["peak_records"].as_u64().unwrap() <= 100);
+        assert!(report["buffer"]["peak_memory"].as_u64().unwrap() <= 16384);
+
+        Ok(())
+    }
+    #[tokio::test]
+    async fn test_local_filesystem() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            format!(
+                r#"
+            {{
+            "streams": {{
+                "people": {{ "identifier": "public.test.people" }}
+            }},
+            "catalogUrl": "sqlite://{}/catalog.db?mode=rwc",
+            "catalogName": "public",
+            "localBaseDirectory": "{}/warehouse"
+            }}
+        "#,
+                tempdir.path().display(),
+                tempdir.path().display()
+            )
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/people/catalog.json", plugin.clone()).await?;
+
+        let input = File::open("../testdata/people/input.txt")?;
+
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        drop(plugin);
+
+        // The catalog and the data outlive the target
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
 
         let catalog = plugin.catalog().await?;
 
@@ -124,12 +325,29 @@ mod tests {
             Err(anyhow!("Not a table"))
         }?;
 
+        let warehouse = tempdir.path().canonicalize()?.join("warehouse");
+
+        assert_eq!(
+            table.metadata().location,
+            format!("file://{}/public/test/people", warehouse.display())
+        );
+
         let manifests = table.manifests(None, None).await?;
 
         assert_eq!(manifests[0].added_rows_count.unwrap(), 100);
 
+        let data_files = std::fs::read_dir(warehouse.join("public/test/people/data"))?
+            .filter(|x| {
+                x.as_ref()
+                    .is_ok_and(|x| x.path().extension().is_some_and(|x| x == "parquet"))
+            })
+            .count();
+
+        assert_eq!(data_files, 1);
+
         Ok(())
     }
+
     #[tokio::test]
     async fn test_orders() -> Result<(), Error> {
         let tempdir = tempdir()?;
@@ -159,7 +377,7 @@ mod tests {
 
         let input = File::open("../testdata/inventory/input1.txt")?;
 
-        ingest(plugin.clone(), &mut BufReader::new(input)).await?;
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
 
         let catalog = plugin.catalog().await?;
 
@@ -222,7 +440,7 @@ mod tests {
 
         let input = File::open("../testdata/inventory/input2.txt")?;
 
-        ingest(plugin.clone(), &mut BufReader::new(input)).await?;
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
 
         let orders_table = if let Tabular::Table(table) = catalog
             .clone()
@@ -236,7 +454,13 @@ mod tests {
 
         let manifests = orders_table.manifests(None, None).await?;
 
-        assert_eq!(manifests[0].added_rows_count.unwrap(), 4);
+        assert_eq!(
+            manifests
+                .iter()
+                .map(|x| x.added_rows_count.unwrap() + x.existing_rows_count.unwrap())
+                .sum::<i64>(),
+            4
+        );
 
         Ok(())
     }
@@ -270,7 +494,7 @@ mod tests {
 
         let input = File::open("../testdata/mysql/data.txt")?;
 
-        ingest(plugin.clone(), &mut BufReader::new(input)).await?;
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
 
         let catalog = plugin.catalog().await?;
 
@@ -286,7 +510,13 @@ mod tests {
 
         let manifests = orders_table.manifests(None, None).await?;
 
-        assert_eq!(manifests[0].added_rows_count.unwrap(), 4);
+        assert_eq!(
+            manifests
+                .iter()
+                .map(|x| x.added_rows_count.unwrap() + x.existing_rows_count.unwrap())
+                .sum::<i64>(),
+            4
+        );
 
         let orders_version = orders_table
             .metadata()
@@ -363,7 +593,7 @@ mod tests {
 
         let input = File::open("../testdata/activate_version/input1.txt")?;
 
-        ingest(plugin.clone(), &mut BufReader::new(input)).await?;
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
 
         let catalog = plugin.catalog().await?;
 
@@ -426,7 +656,7 @@ mod tests {
 
         let input = File::open("../testdata/activate_version/input2.txt")?;
 
-        ingest(plugin.clone(), &mut BufReader::new(input)).await?;
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
 
         let orders_table = if let Tabular::Table(table) = catalog
             .clone()
@@ -455,4 +685,2851 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_upsert() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            r#"
+            {
+            "streams": {
+                "inventory-orders": { "identifier": "public.inventory.orders", "replicationMethod": "LOG_BASED", "writeMode": "upsert" }
+            },
+            "catalogUrl": "sqlite://",
+            "catalogName": "public"
+            }
+        "#
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/upsert/catalog.json", plugin.clone()).await?;
+
+        let input = File::open("../testdata/upsert/input1.txt")?;
+
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        let catalog = plugin.catalog().await?;
+
+        let orders_table = if let Tabular::Table(table) = catalog
+            .clone()
+            .load_tabular(&Identifier::parse("inventory.orders")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        let manifests = orders_table.manifests(None, None).await?;
+
+        assert_eq!(manifests[0].added_rows_count.unwrap(), 2);
+
+        let input = File::open("../testdata/upsert/input2.txt")?;
+
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        let orders_table = if let Tabular::Table(table) = catalog
+            .clone()
+            .load_tabular(&Identifier::parse("inventory.orders")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        let manifests = orders_table.manifests(None, None).await?;
+
+        let datafiles = orders_table.datafiles(&manifests, None).await?;
+
+        let (deletes, data): (Vec<_>, Vec<_>) = datafiles
+            .iter()
+            .partition(|x| matches!(x.data_file().content(), Content::EqualityDeletes));
+
+        assert_eq!(
+            data.iter()
+                .map(|x| *x.data_file().record_count())
+                .sum::<i64>(),
+            4
+        );
+
+        assert_eq!(deletes.len(), 1);
+
+        assert_eq!(*deletes[0].data_file().record_count(), 2);
+
+        let id = orders_table
+            .metadata()
+            .current_schema(None)?
+            .fields()
+            .get_name("id")
+            .unwrap()
+            .id;
+
+        assert_eq!(deletes[0].data_file().equality_ids(), &Some(vec![id]));
+
+        let orders_version = orders_table
+            .metadata()
+            .properties
+            .get("singer.bookmark")
+            .expect("Failed to get bookmark");
+
+        assert_eq!(
+            orders_version,
+            r#"{"last_replication_method":"LOG_BASED","lsn":37125990,"version":1703756002202}"#
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_hard_deletes() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            r#"
+            {
+            "streams": {
+                "inventory-orders": { "identifier": "public.inventory.orders", "replicationMethod": "LOG_BASED", "deleteMode": "hard", "deleteType": "position" }
+            },
+            "catalogUrl": "sqlite://",
+            "catalogName": "public"
+            }
+        "#
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/deletes/catalog.json", plugin.clone()).await?;
+
+        let input = File::open("../testdata/deletes/input1.txt")?;
+
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        let input = File::open("../testdata/deletes/input2.txt")?;
+
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        let catalog = plugin.catalog().await?;
+
+        let orders_table = if let Tabular::Table(table) = catalog
+            .clone()
+            .load_tabular(&Identifier::parse("inventory.orders")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        let manifests = orders_table.manifests(None, None).await?;
+
+        let datafiles = orders_table.datafiles(&manifests, None).await?;
+
+        let (deletes, data): (Vec<_>, Vec<_>) = datafiles
+            .iter()
+            .partition(|x| matches!(x.data_file().content(), Content::PositionDeletes));
+
+        // Tombstones are not written as rows
+        assert_eq!(
+            data.iter()
+                .map(|x| *x.data_file().record_count())
+                .sum::<i64>(),
+            3
+        );
+
+        assert_eq!(deletes.len(), 1);
+
+        assert_eq!(*deletes[0].data_file().record_count(), 2);
+
+        let summary = &orders_table
+            .metadata()
+            .current_snapshot(None)?
+            .unwrap()
+            .summary()
+            .other;
+
+        assert_eq!(summary.get("added-position-deletes").unwrap(), "2");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_position_delete_keys() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            r#"
+            {
+            "streams": {
+                "app-events": { "identifier": "public.app.events", "deleteMode": "hard", "deleteType": "position" }
+            },
+            "catalogUrl": "sqlite://",
+            "catalogName": "public",
+            "commit": { "records": 1 }
+            }
+        "#
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/events/catalog.json", plugin.clone()).await?;
+
+        let input = File::open("../testdata/events/input1.txt")?;
+
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        let catalog = plugin.catalog().await?;
+
+        let load_table = || async {
+            if let Tabular::Table(table) = catalog
+                .clone()
+                .load_tabular(&Identifier::parse("app.events")?)
+                .await?
+            {
+                Ok(table)
+            } else {
+                Err(anyhow!("Not a table"))
+            }
+        };
+
+        let events_table = load_table().await?;
+
+        let field_id = events_table
+            .metadata()
+            .current_schema(None)?
+            .fields()
+            .get_name("occurred_at")
+            .unwrap()
+            .id;
+
+        // Every record was committed to its own data file
+        let manifests = events_table.manifests(None, None).await?;
+        let datafiles = events_table.datafiles(&manifests, None).await?;
+
+        assert_eq!(datafiles.len(), 2);
+
+        // The data file of March is excluded by its bounds, so it's never read
+        let march = datafiles
+            .iter()
+            .find(|x| {
+                x.data_file()
+                    .lower_bounds()
+                    .as_ref()
+                    .and_then(|bounds| bounds.get(&field_id))
+                    != Some(&Value::Timestamp(1704067200000000))
+            })
+            .unwrap();
+
+        events_table
+            .object_store()
+            .delete(&march.data_file().file_path().trim_start_matches('/').into())
+            .await?;
+
+        // The tombstone has the key of the January record with another offset
+        let input = File::open("../testdata/events/input2.txt")?;
+
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        let events_table = load_table().await?;
+
+        let manifests = events_table.manifests(None, None).await?;
+
+        let deletes = events_table
+            .datafiles(&manifests, None)
+            .await?
+            .into_iter()
+            .filter(|x| matches!(x.data_file().content(), Content::PositionDeletes))
+            .collect::<Vec<_>>();
+
+        assert_eq!(deletes.len(), 1);
+        assert_eq!(*deletes[0].data_file().record_count(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_partition_spec() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            r#"
+            {
+            "streams": {
+                "inventory-orders": {
+                    "identifier": "public.inventory.orders",
+                    "replicationMethod": "LOG_BASED",
+                    "deleteMode": "hard",
+                    "deleteType": "position",
+                    "partitionBy": [
+                        { "column": "order_date", "transform": "day" },
+                        { "column": "id", "transform": "bucket[4]" },
+                        { "column": "order_date", "transform": "month", "name": "order_month" }
+                    ]
+                }
+            },
+            "catalogUrl": "sqlite://",
+            "catalogName": "public"
+            }
+        "#
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/deletes/catalog.json", plugin.clone()).await?;
+
+        let input = File::open("../testdata/deletes/input1.txt")?;
+
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        let input = File::open("../testdata/deletes/input2.txt")?;
+
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        let catalog = plugin.catalog().await?;
+
+        let orders_table = if let Tabular::Table(table) = catalog
+            .clone()
+            .load_tabular(&Identifier::parse("inventory.orders")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        let partition_fields = orders_table
+            .metadata()
+            .default_partition_spec()?
+            .fields()
+            .iter()
+            .map(|field| (field.name().clone(), *field.field_id()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            partition_fields,
+            vec![
+                ("order_date_day".to_owned(), 1000),
+                ("id_bucket".to_owned(), 1001),
+                ("order_month".to_owned(), 1002)
+            ]
+        );
+
+        let data_files = orders_table
+            .object_store()
+            .list(None)
+            .map_ok(|object| object.location.to_string())
+            .try_collect::<Vec<_>>()
+            .await?
+            .into_iter()
+            .filter(|location| {
+                location.contains("/data/") && !location.ends_with("-deletes.parquet")
+            })
+            .collect::<Vec<_>>();
+
+        // 2016-01-16, 2016-01-17 and 2016-02-19 are in separate partitions
+        assert_eq!(data_files.len(), 3);
+        for (day, month) in [(16816, 552), (16817, 552), (16850, 553)] {
+            assert!(data_files.iter().any(|location| {
+                location.contains(&format!("/order_date_day={}/id_bucket=", day))
+                    && location.contains(&format!("/order_month={}/", month))
+            }));
+        }
+
+        // Every manifest summarizes the values of each partition field
+        let manifests = orders_table.manifests(None, None).await?;
+        assert!(manifests
+            .iter()
+            .all(|manifest| manifest
+                .partitions
+                .as_ref()
+                .is_some_and(|partitions| partitions.len() == 3
+                    && partitions.iter().all(|x| !x.contains_null))));
+
+        // The data files of both deleted orders are found in their partitions
+        let summary = &orders_table
+            .metadata()
+            .current_snapshot(None)?
+            .unwrap()
+            .summary()
+            .other;
+
+        assert_eq!(summary.get("added-position-deletes").unwrap(), "2");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_invalid_partition_spec() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            r#"
+            {
+            "streams": {
+                "inventory-orders": {
+                    "identifier": "public.inventory.orders",
+                    "partitionBy": [{ "column": "order_date", "transform": "weekly" }]
+                }
+            },
+            "catalogUrl": "sqlite://",
+            "catalogName": "public"
+            }
+        "#
+            .as_bytes(),
+        )?;
+
+        let error = SqlTargetPlugin::new(config_path.as_path().to_str().unwrap())
+            .await
+            .unwrap_err();
+
+        assert!(error
+            .to_string()
+            .contains("Unknown partition transform \"weekly\""));
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            r#"
+            {
+            "streams": {
+                "inventory-orders": {
+                    "identifier": "public.inventory.orders",
+                    "partitionBy": [
+                        { "column": "order_date", "transform": "day" },
+                        { "column": "customer", "transform": "identity" }
+                    ]
+                }
+            },
+            "catalogUrl": "sqlite://",
+            "catalogName": "public"
+            }
+        "#
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        let error = select_streams("../testdata/deletes/catalog.json", plugin.clone())
+            .await
+            .unwrap_err();
+
+        assert!(error
+            .to_string()
+            .contains("Partition column customer doesn't exist in the schema."));
+
+        // No table is created for an invalid config
+        assert!(
+            !plugin
+                .catalog()
+                .await?
+                .tabular_exists(&Identifier::parse("inventory.orders")?)
+                .await?
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sort_order() -> Result<(), Error> {
+        use arrow::{array::AsArray, compute::cast, datatypes::DataType, datatypes::Int64Type};
+        use iceberg_rust::spec::sort::{NullOrder, SortDirection};
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            r#"
+            {
+            "streams": {
+                "inventory-orders": {
+                    "identifier": "public.inventory.orders",
+                    "sortBy": [
+                        { "column": "quantity", "direction": "desc" },
+                        { "column": "id" }
+                    ]
+                }
+            },
+            "catalogUrl": "sqlite://",
+            "catalogName": "public"
+            }
+        "#
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/deletes/catalog.json", plugin.clone()).await?;
+
+        let input = File::open("../testdata/deletes/input1.txt")?;
+
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        let catalog = plugin.catalog().await?;
+
+        let orders_table = if let Tabular::Table(table) = catalog
+            .clone()
+            .load_tabular(&Identifier::parse("inventory.orders")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        let metadata = orders_table.metadata();
+
+        assert_eq!(metadata.default_sort_order_id, 1);
+        assert!(metadata.sort_orders[&0].fields.is_empty());
+
+        let sort_order = &metadata.sort_orders[&1];
+
+        assert_eq!(sort_order.fields.len(), 2);
+        assert_eq!(sort_order.fields[0].direction, SortDirection::Descending);
+        assert_eq!(sort_order.fields[0].null_order, NullOrder::Last);
+        assert_eq!(sort_order.fields[1].direction, SortDirection::Ascending);
+        assert_eq!(sort_order.fields[1].null_order, NullOrder::First);
+
+        let object_store = orders_table.object_store();
+
+        let data_files = object_store
+            .list(None)
+            .map_ok(|object| object.location)
+            .try_collect::<Vec<_>>()
+            .await?
+            .into_iter()
+            .filter(|location| location.as_ref().contains("/data/"))
+            .collect::<Vec<_>>();
+
+        assert_eq!(data_files.len(), 1);
+
+        let bytes = object_store.get(&data_files[0]).await?.bytes().await?;
+
+        let ids = ParquetRecordBatchReaderBuilder::try_new(bytes)?
+            .build()?
+            .map(|batch| {
+                let ids = cast(batch?.column_by_name("id").unwrap(), &DataType::Int64)?;
+                Ok(ids.as_primitive::<Int64Type>().values().to_vec())
+            })
+            .collect::<Result<Vec<_>, Error>>()?
+            .concat();
+
+        // Orders with the higher quantity come first
+        assert_eq!(ids, vec![10002, 10003, 10001]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sort_order_existing_table() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let write_config = |sort_by: &str| {
+            std::fs::write(
+                &config_path,
+                format!(
+                    r#"
+            {{
+            "streams": {{
+                "inventory-orders": {{
+                    "identifier": "public.inventory.orders"{}
+                }}
+            }},
+            "catalogUrl": "sqlite://{}/catalog.db?mode=rwc",
+            "catalogName": "public",
+            "localBaseDirectory": "{}/warehouse"
+            }}
+        "#,
+                    sort_by,
+                    tempdir.path().display(),
+                    tempdir.path().display()
+                ),
+            )
+        };
+
+        let load_metadata = |plugin: Arc<SqlTargetPlugin>| async move {
+            let catalog = plugin.catalog().await?;
+            match catalog
+                .load_tabular(&Identifier::parse("inventory.orders")?)
+                .await?
+            {
+                Tabular::Table(table) => Ok(table.metadata().clone()),
+                _ => Err(anyhow!("Not a table")),
+            }
+        };
+
+        write_config("")?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/deletes/catalog.json", plugin.clone()).await?;
+
+        assert_eq!(load_metadata(plugin).await?.default_sort_order_id, 0);
+
+        // The configured sort order becomes the default sort order of the existing table
+        write_config(r#", "sortBy": [{ "column": "quantity", "direction": "desc" }]"#)?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/deletes/catalog.json", plugin.clone()).await?;
+        select_streams("../testdata/deletes/catalog.json", plugin.clone()).await?;
+
+        let metadata = load_metadata(plugin).await?;
+
+        assert_eq!(metadata.default_sort_order_id, 1);
+        assert_eq!(metadata.sort_orders.len(), 2);
+        assert_eq!(metadata.sort_orders[&1].fields.len(), 1);
+
+        // Removing the sort order makes the table unsorted again
+        write_config(r#", "sortBy": []"#)?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/deletes/catalog.json", plugin.clone()).await?;
+
+        let metadata = load_metadata(plugin).await?;
+
+        assert_eq!(metadata.default_sort_order_id, 0);
+        assert_eq!(metadata.sort_orders.len(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_parquet_config() -> Result<(), Error> {
+        use parquet::{
+            basic::{Compression, Encoding},
+            file::reader::{FileReader, SerializedFileReader},
+        };
+
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            r#"
+            {
+            "streams": {
+                "inventory-orders": {
+                    "identifier": "public.inventory.orders",
+                    "parquet": { "compression": "gzip", "compressionLevel": 9, "dictionaryEnabled": false }
+                }
+            },
+            "catalogUrl": "sqlite://",
+            "catalogName": "public"
+            }
+        "#
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/deletes/catalog.json", plugin.clone()).await?;
+
+        let input = File::open("../testdata/deletes/input1.txt")?;
+
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        let catalog = plugin.catalog().await?;
+
+        let orders_table = if let Tabular::Table(table) = catalog
+            .clone()
+            .load_tabular(&Identifier::parse("inventory.orders")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        let object_store = orders_table.object_store();
+
+        let data_files = object_store
+            .list(None)
+            .map_ok(|object| object.location)
+            .try_collect::<Vec<_>>()
+            .await?
+            .into_iter()
+            .filter(|location| location.as_ref().contains("/data/"))
+            .collect::<Vec<_>>();
+
+        assert_eq!(data_files.len(), 1);
+
+        let bytes = object_store.get(&data_files[0]).await?.bytes().await?;
+
+        let reader = SerializedFileReader::new(bytes)?;
+
+        for column in reader.metadata().row_group(0).columns() {
+            assert!(matches!(column.compression(), Compression::GZIP(_)));
+            assert!(!column.encodings().contains(&Encoding::RLE_DICTIONARY));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_table_properties() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            r#"
+            {
+            "streams": {
+                "inventory-orders": {
+                    "identifier": "public.inventory.orders",
+                    "location": "warehouse/orders/",
+                    "properties": {
+                        "owner": "inventory",
+                        "commit.retry.num-retries": "10",
+                        "write.parquet.compression-codec": "snappy"
+                    }
+                }
+            },
+            "catalogUrl": "sqlite://",
+            "catalogName": "public"
+            }
+        "#
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/deletes/catalog.json", plugin.clone()).await?;
+
+        let catalog = plugin.catalog().await?;
+
+        let mut orders_table = if let Tabular::Table(table) = catalog
+            .clone()
+            .load_tabular(&Identifier::parse("inventory.orders")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        let metadata = orders_table.metadata();
+
+        assert_eq!(metadata.location, "/warehouse/orders");
+        assert_eq!(metadata.format_version, FormatVersion::V2);
+        assert_eq!(metadata.properties["owner"], "inventory");
+        assert_eq!(metadata.properties["commit.retry.num-retries"], "10");
+        assert_eq!(
+            metadata.properties["write.parquet.compression-codec"],
+            "snappy"
+        );
+        // The defaults of the parquet writer aren't written to the table
+        assert!(!metadata
+            .properties
+            .contains_key("write.parquet.compression-level"));
+
+        orders_table
+            .new_transaction(None)
+            .update_properties(vec![
+                ("owner".to_owned(), "sales".to_owned()),
+                ("comment".to_owned(), "Orders of the shop".to_owned()),
+            ])
+            .commit()
+            .await?;
+
+        // Selecting the streams again reconciles the configured properties
+        select_streams("../testdata/deletes/catalog.json", plugin.clone()).await?;
+
+        let orders_table = if let Tabular::Table(table) = catalog
+            .clone()
+            .load_tabular(&Identifier::parse("inventory.orders")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        let properties = &orders_table.metadata().properties;
+
+        assert_eq!(properties["owner"], "inventory");
+        assert_eq!(properties["comment"], "Orders of the shop");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_format_version_1() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            r#"
+            {
+            "streams": {
+                "inventory-orders": {
+                    "identifier": "public.inventory.orders",
+                    "formatVersion": 1,
+                    "writeMode": "upsert"
+                }
+            },
+            "catalogUrl": "sqlite://",
+            "catalogName": "public"
+            }
+        "#
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        let error = select_streams("../testdata/deletes/catalog.json", plugin.clone())
+            .await
+            .unwrap_err();
+
+        assert!(error
+            .to_string()
+            .contains("Stream inventory-orders can't use format version 1"));
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            r#"
+            {
+            "streams": {
+                "inventory-orders": {
+                    "identifier": "public.inventory.orders",
+                    "formatVersion": 1,
+                    "properties": { "owner": "inventory" }
+                }
+            },
+            "catalogUrl": "sqlite://",
+            "catalogName": "public"
+            }
+        "#
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/deletes/catalog.json", plugin.clone()).await?;
+
+        let input = File::open("../testdata/deletes/input1.txt")?;
+
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        let catalog = plugin.catalog().await?;
+
+        let orders_table = if let Tabular::Table(table) = catalog
+            .clone()
+            .load_tabular(&Identifier::parse("inventory.orders")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        let metadata = orders_table.metadata();
+
+        assert_eq!(metadata.format_version, FormatVersion::V1);
+        assert_eq!(metadata.properties["owner"], "inventory");
+        assert!(metadata.current_snapshot_id.is_some());
+
+        let manifests = orders_table.manifests(None, None).await?;
+
+        let datafiles = orders_table.datafiles(&manifests, None).await?;
+
+        assert_eq!(
+            datafiles
+                .iter()
+                .map(|x| *x.data_file().record_count())
+                .sum::<i64>(),
+            3
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_commit_conflict() -> Result<(), Error> {
+        use std::io::{pipe, BufRead};
+        use std::time::Duration;
+
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            r#"
+            {
+            "streams": {
+                "inventory-orders": {
+                    "identifier": "public.inventory.orders"
+                }
+            },
+            "commit": { "onState": true, "retries": 2, "retryMinWait": 10 },
+            "catalogUrl": "sqlite://",
+            "catalogName": "public"
+            }
+        "#
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/deletes/catalog.json", plugin.clone()).await?;
+
+        let input = File::open("../testdata/deletes/input1.txt")?;
+
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        let lines = BufReader::new(File::open("../testdata/deletes/input1.txt")?)
+            .lines()
+            .collect::<Result<Vec<_>, _>>()?;
+        let (schema, activate_version) = (&lines[0], &lines[2]);
+
+        let record = |id: i64| {
+            format!(
+                r#"{{"type": "RECORD", "stream": "inventory-orders", "record": {{"id": {}, "order_date": "2016-03-01T00:00:00+00:00", "product_id": 102, "purchaser": 1001, "quantity": 1}}}}"#,
+                id
+            )
+        };
+        let state = |lsn: i64| {
+            format!(
+                r#"{{"type": "STATE", "value": {{"bookmarks": {{"inventory-orders": {{"lsn": {}}}}}}}}}"#,
+                lsn
+            )
+        };
+
+        // The first sync commits a bookmark and then waits for more input
+        let (reader, mut writer) = pipe()?;
+
+        let catalog = plugin.catalog().await?;
+
+        let identifier = Identifier::parse("inventory.orders")?;
+
+        let load_table = || async {
+            match catalog.clone().load_tabular(&identifier).await? {
+                Tabular::Table(table) => Ok(table),
+                _ => Err(anyhow!("Not a table")),
+            }
+        };
+
+        let first = ingest(plugin.clone(), BufReader::new(reader), io::sink());
+
+        let second = async {
+            writeln!(writer, "{}\n{}\n{}", schema, activate_version, state(1))?;
+
+            while !load_table()
+                .await?
+                .metadata()
+                .properties
+                .get("singer.bookmark")
+                .is_some_and(|bookmark| bookmark.contains("\"lsn\":1"))
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+
+            // The second sync appends a snapshot while the first one still holds the old table
+            let input = format!("{}\n{}\n{}\n", schema, activate_version, record(20001));
+
+            ingest(plugin.clone(), Cursor::new(input), io::sink()).await?;
+
+            writeln!(writer, "{}\n{}", record(30001), state(2))?;
+            drop(writer);
+
+            Ok(())
+        };
+
+        let (first, second) = tokio::join!(first, second);
+        first?;
+        second?;
+
+        let orders_table = load_table().await?;
+
+        let metadata = orders_table.metadata();
+
+        assert_eq!(metadata.snapshots.len(), 3);
+        assert!(metadata.properties["singer.bookmark"].contains("\"lsn\":2"));
+
+        let manifests = orders_table.manifests(None, None).await?;
+
+        let datafiles = orders_table.datafiles(&manifests, None).await?;
+
+        // The snapshot of the first sync was committed on top of the second sync
+        assert_eq!(
+            datafiles
+                .iter()
+                .map(|x| *x.data_file().record_count())
+                .sum::<i64>(),
+            5
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_conflict() -> Result<(), Error> {
+        use std::io::{pipe, BufRead};
+        use std::time::Duration;
+
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            r#"
+            {
+            "streams": {
+                "inventory-orders": {
+                    "identifier": "public.inventory.orders"
+                }
+            },
+            "commit": { "onState": true, "retries": 2, "retryMinWait": 10 },
+            "catalogUrl": "sqlite://",
+            "catalogName": "public"
+            }
+        "#
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/deletes/catalog.json", plugin.clone()).await?;
+
+        let input = File::open("../testdata/deletes/input1.txt")?;
+
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        let lines = BufReader::new(File::open("../testdata/deletes/input1.txt")?)
+            .lines()
+            .collect::<Result<Vec<_>, _>>()?;
+        let (schema, activate_version) = (&lines[0], &lines[2]);
+
+        let record = |id: i64| {
+            format!(
+                r#"{{"type": "RECORD", "stream": "inventory-orders", "record": {{"id": {}, "order_date": "2016-03-01T00:00:00+00:00", "product_id": 102, "purchaser": 1001, "quantity": 1}}}}"#,
+                id
+            )
+        };
+        let state =
+            r#"{"type": "STATE", "value": {"bookmarks": {"inventory-orders": {"lsn": 1}}}}"#;
+
+        // The first sync starts a new version, so its first commit rewrites the table
+        let (reader, mut writer) = pipe()?;
+
+        let first = ingest(plugin.clone(), BufReader::new(reader), io::sink());
+
+        let second = async {
+            writeln!(
+                writer,
+                "{}\n{}",
+                schema,
+                activate_version.replace("1703756002202", "1703756002203")
+            )?;
+
+            tokio::time::sleep(Duration::from_millis(500)).await;
+
+            // The second sync appends to the old version while the first one holds the old table
+            let input = format!("{}\n{}\n{}\n", schema, activate_version, record(20001));
+
+            ingest(plugin.clone(), Cursor::new(input), io::sink()).await?;
+
+            writeln!(writer, "{}\n{}", record(30001), state)?;
+            drop(writer);
+
+            Ok(())
+        };
+
+        let (first, second) = tokio::join!(first, second);
+        second?;
+
+        // The rewrite isn't retried, because it would drop the rows of the second sync
+        let error = first.unwrap_err().to_string();
+        assert!(error.contains("the rewrite would drop them"));
+
+        let catalog = plugin.catalog().await?;
+
+        let Tabular::Table(orders_table) = catalog
+            .load_tabular(&Identifier::parse("inventory.orders")?)
+            .await?
+        else {
+            return Err(anyhow!("Not a table"));
+        };
+
+        let manifests = orders_table.manifests(None, None).await?;
+
+        let datafiles = orders_table.datafiles(&manifests, None).await?;
+
+        assert_eq!(orders_table.metadata().snapshots.len(), 2);
+        assert_eq!(
+            datafiles
+                .iter()
+                .map(|x| *x.data_file().record_count())
+                .sum::<i64>(),
+            4
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_nested_namespace() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            r#"
+            {
+            "streams": {
+                "inventory-orders": { "identifier": "public.bronze.\"inventory.v2\".orders" }
+            },
+            "catalogUrl": "sqlite://",
+            "catalogName": "public"
+            }
+        "#
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/deletes/catalog.json", plugin.clone()).await?;
+
+        let input = File::open("../testdata/deletes/input1.txt")?;
+
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        let catalog = plugin.catalog().await?;
+
+        let orders_table = if let Tabular::Table(table) = catalog
+            .clone()
+            .load_tabular(&Identifier::try_new(&[
+                "bronze".to_owned(),
+                "inventory.v2".to_owned(),
+                "orders".to_owned(),
+            ])?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        assert!(orders_table
+            .metadata()
+            .location
+            .ends_with("/bronze/inventory.v2/orders"));
+
+        let manifests = orders_table.manifests(None, None).await?;
+
+        let datafiles = orders_table.datafiles(&manifests, None).await?;
+
+        assert_eq!(
+            datafiles
+                .iter()
+                .map(|x| *x.data_file().record_count())
+                .sum::<i64>(),
+            3
+        );
+
+        let state = generate_state(plugin.clone()).await?;
+
+        assert!(state["bookmarks"].get("inventory-orders").is_some());
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            r#"
+            {
+            "streams": {
+                "inventory-orders": { "identifier": "bronze.inventory.orders" }
+            },
+            "catalogUrl": "sqlite://",
+            "catalogName": "public"
+            }
+        "#
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        let error = select_streams("../testdata/deletes/catalog.json", plugin.clone())
+            .await
+            .unwrap_err();
+
+        assert!(error
+            .to_string()
+            .contains("doesn't match the catalog public"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_audit_table() -> Result<(), Error> {
+        use arrow::json::ArrayWriter;
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+        use std::io::BufRead;
+
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            r#"
+            {
+            "streams": {
+                "inventory-orders": {
+                    "identifier": "public.inventory.orders",
+                    "deleteMode": "hard"
+                }
+            },
+            "audit": { "table": "public.audit.sync_runs" },
+            "catalogUrl": "sqlite://",
+            "catalogName": "public"
+            }
+        "#
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/deletes/catalog.json", plugin.clone()).await?;
+
+        let catalog = plugin.catalog().await?;
+
+        let mut snapshot_ids = Vec::new();
+
+        for input in ["input1.txt", "input2.txt"] {
+            let input = File::open("../testdata/deletes/".to_owned() + input)?;
+
+            ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+            let orders_table = if let Tabular::Table(table) = catalog
+                .clone()
+                .load_tabular(&Identifier::parse("inventory.orders")?)
+                .await?
+            {
+                Ok(table)
+            } else {
+                Err(anyhow!("Not a table"))
+            }?;
+
+            snapshot_ids.push(orders_table.metadata().current_snapshot_id.unwrap());
+        }
+
+        // A run that fails on an invalid record is audited as well
+        let lines = BufReader::new(File::open("../testdata/deletes/input1.txt")?)
+            .lines()
+            .collect::<Result<Vec<_>, _>>()?;
+        let input = format!(
+            "{}\n{}\n{}\n",
+            lines[0],
+            lines[2],
+            r#"{"type": "RECORD", "stream": "inventory-orders", "record": {"id": "invalid"}}"#
+        );
+
+        assert!(ingest(plugin.clone(), Cursor::new(input), io::sink())
+            .await
+            .is_err());
+
+        let audit_table = if let Tabular::Table(table) = catalog
+            .clone()
+            .load_tabular(&Identifier::parse("audit.sync_runs")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        // Every run appends its rows in one snapshot
+        assert_eq!(audit_table.metadata().snapshots.len(), 3);
+
+        let object_store = audit_table.object_store();
+
+        let manifests = audit_table.manifests(None, None).await?;
+
+        let mut rows = Vec::new();
+
+        for data_file in audit_table.datafiles(&manifests, None).await? {
+            let path = data_file.data_file().file_path().trim_start_matches('/');
+            let bytes = object_store.get(&path.into()).await?.bytes().await?;
+
+            let mut writer = ArrayWriter::new(Vec::new());
+            for batch in ParquetRecordBatchReaderBuilder::try_new(bytes)?.build()? {
+                writer.write(&batch?)?;
+            }
+            writer.finish()?;
+
+            rows.extend(serde_json::from_slice::<Vec<serde_json::Value>>(
+                &writer.into_inner(),
+            )?);
+        }
+
+        rows.sort_by_key(|row| row["started_at"].as_str().unwrap_or_default().to_owned());
+
+        assert_eq!(rows.len(), 3);
+
+        for (row, snapshot_id) in rows.iter().zip(&snapshot_ids) {
+            assert_eq!(row["stream"], "inventory-orders");
+            assert_eq!(row["identifier"], "public.inventory.orders");
+            assert_eq!(row["snapshot_id"], *snapshot_id);
+            assert_eq!(row["records_rejected"], 0);
+            assert_eq!(row["activate_version"], 1703756002202i64);
+            assert_eq!(row["target_version"], env!("CARGO_PKG_VERSION"));
+            assert_eq!(row["status"], "succeeded");
+            assert!(row.get("error").is_none());
+            assert!(row["bytes_written"].as_i64().unwrap() > 0);
+            assert!(row["started_at"].as_str() <= row["ended_at"].as_str());
+        }
+
+        assert_ne!(rows[0]["run_id"], rows[1]["run_id"]);
+
+        assert_eq!(rows[0]["records_read"], 3);
+        assert!(rows[0].get("bookmark_before").is_none());
+        assert!(rows[0]["bookmark_after"]
+            .as_str()
+            .unwrap()
+            .contains("37125980"));
+
+        assert_eq!(rows[1]["records_read"], 4);
+        assert_eq!(rows[1]["bookmark_before"], rows[0]["bookmark_after"]);
+        assert!(rows[1]["bookmark_after"]
+            .as_str()
+            .unwrap()
+            .contains("37125990"));
+
+        assert_eq!(rows[2]["status"], "failed");
+        assert!(rows[2]["error"]
+            .as_str()
+            .is_some_and(|error| !error.is_empty()));
+        assert!(rows[2].get("snapshot_id").is_none());
+        assert_eq!(rows[2]["bookmark_before"], rows[1]["bookmark_after"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_summary() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            r#"
+            {
+            "streams": {
+                "inventory-orders": {
+                    "identifier": "public.inventory.orders",
+                    "replicationMethod": "LOG_BASED",
+                    "deleteMode": "hard"
+                }
+            },
+            "catalogUrl": "sqlite://",
+            "catalogName": "public"
+            }
+        "#
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/deletes/catalog.json", plugin.clone()).await?;
+
+        for input in ["input1.txt", "input2.txt"] {
+            let input = File::open("../testdata/deletes/".to_owned() + input)?;
+
+            ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+        }
+
+        let catalog = plugin.catalog().await?;
+
+        let orders_table = if let Tabular::Table(table) = catalog
+            .clone()
+            .load_tabular(&Identifier::parse("inventory.orders")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        let mut snapshots = orders_table
+            .metadata()
+            .snapshots
+            .values()
+            .collect::<Vec<_>>();
+        snapshots.sort_by_key(|snapshot| *snapshot.sequence_number());
+
+        assert_eq!(snapshots.len(), 2);
+
+        let summaries = snapshots
+            .iter()
+            .map(|snapshot| &snapshot.summary().other)
+            .collect::<Vec<_>>();
+
+        for summary in &summaries {
+            assert_eq!(summary["singer.tap-stream-id"], "inventory-orders");
+            assert_eq!(summary["singer.version"], "1703756002202");
+            assert_eq!(summary["singer.replication-method"], "LOG_BASED");
+        }
+
+        // Every run has its own id
+        assert_ne!(summaries[0]["singer.run-id"], summaries[1]["singer.run-id"]);
+
+        assert_eq!(summaries[0]["singer.record-count"], "3");
+        assert_eq!(summaries[1]["singer.record-count"], "4");
+
+        // The table property only keeps the latest bookmark, the summaries keep every bookmark
+        assert!(summaries[0]["singer.bookmark"].contains("37125980"));
+        assert!(summaries[1]["singer.bookmark"].contains("37125990"));
+        assert!(orders_table.metadata().properties["singer.bookmark"].contains("37125990"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_state_rollback() -> Result<(), Error> {
+        use iceberg_rust::catalog::commit::{CommitTable, TableUpdate};
+        use iceberg_rust::spec::snapshot::{SnapshotReference, SnapshotRetention};
+        use target_iceberg::state::{generate_state_at, StateAt};
+
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            r#"
+            {
+            "streams": {
+                "inventory-orders": {
+                    "identifier": "public.inventory.orders",
+                    "deleteMode": "hard"
+                }
+            },
+            "catalogUrl": "sqlite://",
+            "catalogName": "public"
+            }
+        "#
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/deletes/catalog.json", plugin.clone()).await?;
+
+        let catalog = plugin.catalog().await?;
+
+        let load_orders = || async {
+            if let Tabular::Table(table) = catalog
+                .clone()
+                .load_tabular(&Identifier::parse("inventory.orders")?)
+                .await?
+            {
+                Ok(table)
+            } else {
+                Err(anyhow!("Not a table"))
+            }
+        };
+
+        let mut snapshots = Vec::new();
+
+        for input in ["input1.txt", "input2.txt"] {
+            let input = File::open("../testdata/deletes/".to_owned() + input)?;
+
+            ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+            let metadata = load_orders().await?.metadata().clone();
+            let snapshot = metadata.current_snapshot(None)?.unwrap();
+            snapshots.push((*snapshot.snapshot_id(), *snapshot.timestamp_ms()));
+
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        let lsn = |state: &serde_json::Value| state["bookmarks"]["inventory-orders"]["lsn"].clone();
+
+        assert_eq!(lsn(&generate_state(plugin.clone()).await?), 37125990);
+
+        // Earlier points in the history of the table
+        let state = generate_state_at(plugin.clone(), &StateAt::Snapshot(snapshots[0].0)).await?;
+        assert_eq!(lsn(&state), 37125980);
+
+        let state = generate_state_at(plugin.clone(), &StateAt::Timestamp(snapshots[0].1)).await?;
+        assert_eq!(lsn(&state), 37125980);
+
+        let state =
+            generate_state_at(plugin.clone(), &StateAt::Timestamp(snapshots[0].1 - 1)).await?;
+        assert!(state["bookmarks"]["inventory-orders"].is_null());
+
+        assert!(
+            generate_state_at(plugin.clone(), &StateAt::Branch("missing".to_owned()))
+                .await
+                .is_err()
+        );
+
+        // Roll the table back to the first snapshot
+        let orders_table = load_orders().await?;
+        catalog
+            .clone()
+            .update_table(CommitTable {
+                identifier: orders_table.identifier().clone(),
+                requirements: Vec::new(),
+                updates: vec![TableUpdate::SetSnapshotRef {
+                    ref_name: "main".to_owned(),
+                    snapshot_reference: SnapshotReference {
+                        snapshot_id: snapshots[0].0,
+                        retention: SnapshotRetention::default(),
+                    },
+                }],
+            })
+            .await?;
+
+        // The property still has the bookmark of the second snapshot
+        assert!(load_orders().await?.metadata().properties["singer.bookmark"].contains("37125990"));
+
+        assert_eq!(lsn(&generate_state(plugin.clone()).await?), 37125980);
+
+        // A bookmark without new records belongs to the snapshot the table was rolled back to
+        let lines = std::fs::read_to_string("../testdata/deletes/input2.txt")?;
+        let lines = lines.lines().collect::<Vec<_>>();
+        let input = [
+            lines[0],
+            lines[2],
+            r#"{"type": "STATE", "value": {"bookmarks": {"inventory-orders": {"lsn": 37125985}}}}"#,
+        ]
+        .join("\n");
+
+        ingest(plugin.clone(), Cursor::new(input), io::sink()).await?;
+
+        assert_eq!(lsn(&generate_state(plugin.clone()).await?), 37125985);
+
+        // Syncing again continues from the bookmark of the first snapshot
+        let input = File::open("../testdata/deletes/input2.txt")?;
+
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        assert_eq!(lsn(&generate_state(plugin.clone()).await?), 37125990);
+
+        let orders_table = load_orders().await?;
+        let snapshot = orders_table.metadata().current_snapshot(None)?.unwrap();
+
+        assert_eq!(*snapshot.parent_snapshot_id(), Some(snapshots[0].0));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_state_round_trip() -> Result<(), Error> {
+        use iceberg_rust::catalog::commit::{CommitTable, TableUpdate};
+        use std::collections::HashMap;
+
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            r#"
+            {
+            "streams": {
+                "inventory-orders": { "identifier": "public.inventory.orders" }
+            },
+            "catalogUrl": "sqlite://",
+            "catalogName": "public"
+            }
+        "#
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/deletes/catalog.json", plugin.clone()).await?;
+
+        // Without a sync the state has no bookmarks
+        let state = generate_state(plugin.clone()).await?;
+
+        assert_eq!(
+            state,
+            serde_json::json!({ "currently_syncing": null, "bookmarks": {} })
+        );
+
+        // The tap is ahead of the target for the orders and keeps the bookmark of another stream
+        let input = std::fs::read_to_string("../testdata/deletes/input1.txt")?
+            + r#"{"type": "STATE", "value": {"bookmarks": {"inventory-orders": {"lsn": 37125999}, "inventory-customers": {"lsn": 42}}, "currently_syncing": "inventory-customers", "tap_version": "1.2.0"}}"#
+            + "\n";
+
+        let output_path = tempdir.path().join("state.jsonl");
+
+        ingest(
+            plugin.clone(),
+            Cursor::new(input),
+            File::create(&output_path)?,
+        )
+        .await?;
+
+        let state = generate_state(plugin.clone()).await?;
+
+        assert_eq!(state["currently_syncing"], "inventory-customers");
+        assert_eq!(state["tap_version"], "1.2.0");
+        assert_eq!(state["bookmarks"]["inventory-customers"]["lsn"], 42);
+        assert_eq!(state["bookmarks"]["inventory-orders"]["lsn"], 37125999);
+
+        // Emitted states keep the keys of the last STATE message as well
+        let output = std::fs::read_to_string(&output_path)?;
+        let emitted = serde_json::from_str::<serde_json::Value>(output.lines().last().unwrap())?;
+
+        assert_eq!(emitted["value"], state);
+
+        // Malformed states are rejected with an error
+        for value in [r#"[]"#, r#"{"bookmarks": []}"#] {
+            let input =
+                format!("{}\n", r#"{"type": "STATE", "value": VALUE}"#).replace("VALUE", value);
+
+            assert!(ingest(plugin.clone(), Cursor::new(input), io::sink())
+                .await
+                .is_err());
+        }
+
+        let catalog = plugin.catalog().await?;
+
+        let orders_table = if let Tabular::Table(table) = catalog
+            .clone()
+            .load_tabular(&Identifier::parse("inventory.orders")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        catalog
+            .update_table(CommitTable {
+                identifier: orders_table.identifier().clone(),
+                requirements: Vec::new(),
+                updates: vec![TableUpdate::SetProperties {
+                    updates: HashMap::from([(
+                        "singer.bookmark".to_owned(),
+                        "{\"lsn\": ".to_owned(),
+                    )]),
+                }],
+            })
+            .await?;
+
+        let error = generate_state(plugin.clone()).await.unwrap_err();
+
+        assert!(error
+            .to_string()
+            .contains("Bookmark of stream inventory-orders is not valid JSON"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_local_state() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            format!(
+                r#"
+            {{
+            "streams": {{
+                "inventory-orders": {{ "identifier": "public.inventory.orders" }}
+            }},
+            "catalogUrl": "sqlite://{}/catalog.db?mode=rwc",
+            "catalogName": "public",
+            "localBaseDirectory": "{}/warehouse"
+            }}
+        "#,
+                tempdir.path().display(),
+                tempdir.path().display()
+            )
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/deletes/catalog.json", plugin.clone()).await?;
+
+        let input = std::fs::read_to_string("../testdata/deletes/input1.txt")?
+            + r#"{"type": "STATE", "value": {"bookmarks": {}, "tap_version": "1.2.0"}}"#
+            + "\n";
+
+        ingest(plugin.clone(), Cursor::new(input), io::sink()).await?;
+
+        // The state is kept in the configured object store and read by the next run
+        assert!(tempdir.path().join("warehouse/singer/state.json").exists());
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        let state = generate_state(plugin.clone()).await?;
+
+        assert_eq!(state["tap_version"], "1.2.0");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_metrics_report() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let report_path = tempdir.path().join("report.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            format!(
+                r#"
+            {{
+            "streams": {{
+                "inventory-orders": {{ "identifier": "public.inventory.orders", "onInvalidRecord": "skip" }}
+            }},
+            "catalogUrl": "sqlite://",
+            "catalogName": "public",
+            "metrics": {{ "interval": 1, "report": "{}" }}
+            }}
+        "#,
+                report_path.to_str().unwrap()
+            )
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/deletes/catalog.json", plugin.clone()).await?;
+
+        let input = std::fs::read_to_string("../testdata/deletes/input1.txt")?
+            + r#"{"type": "RECORD", "stream": "inventory-orders", "record": {"id": "10004"}}"#
+            + "\n";
+
+        ingest(plugin.clone(), Cursor::new(input), io::sink()).await?;
+
+        let report =
+            serde_json::from_str::<serde_json::Value>(&std::fs::read_to_string(&report_path)?)?;
+
+        assert_eq!(report["status"], "succeeded");
+        assert_eq!(report["error"], serde_json::Value::Null);
+        assert_eq!(report["records_read"], 4);
+
+        let orders = &report["streams"]["inventory-orders"];
+
+        assert_eq!(orders["records_read"], 4);
+        assert_eq!(orders["records_valid"], 3);
+        assert_eq!(orders["records_rejected"], 1);
+        assert_eq!(orders["commits"], 1);
+        assert!(orders["files_written"].as_u64().unwrap() >= 1);
+        assert!(orders["bytes_written"].as_u64().unwrap() > 0);
+
+        // Failed runs are reported as well
+        let input = r#"{"type": "RECORD", "stream": "inventory-orders", "record": {"id": 10005}}"#;
+
+        assert!(ingest(plugin.clone(), Cursor::new(input), io::sink())
+            .await
+            .is_err());
+
+        let report =
+            serde_json::from_str::<serde_json::Value>(&std::fs::read_to_string(&report_path)?)?;
+
+        assert_eq!(report["status"], "failed");
+        assert!(report["error"].is_string());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_soft_deletes() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            r#"
+            {
+            "streams": {
+                "inventory-orders": { "identifier": "public.inventory.orders", "replicationMethod": "LOG_BASED", "deleteMode": "soft" }
+            },
+            "catalogUrl": "sqlite://",
+            "catalogName": "public"
+            }
+        "#
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/deletes/catalog.json", plugin.clone()).await?;
+
+        let input = File::open("../testdata/deletes/input1.txt")?;
+
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        let input = File::open("../testdata/deletes/input2.txt")?;
+
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        let catalog = plugin.catalog().await?;
+
+        let orders_table = if let Tabular::Table(table) = catalog
+            .clone()
+            .load_tabular(&Identifier::parse("inventory.orders")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        let manifests = orders_table.manifests(None, None).await?;
+
+        let datafiles = orders_table.datafiles(&manifests, None).await?;
+
+        let (deletes, data): (Vec<_>, Vec<_>) = datafiles
+            .iter()
+            .partition(|x| matches!(x.data_file().content(), Content::EqualityDeletes));
+
+        // The three tombstones are kept, the insert of 10004 is deleted within the same commit
+        assert_eq!(
+            data.iter()
+                .map(|x| *x.data_file().record_count())
+                .sum::<i64>(),
+            6
+        );
+
+        assert_eq!(
+            deletes
+                .iter()
+                .map(|x| *x.data_file().record_count())
+                .sum::<i64>(),
+            3
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_checkpoints() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            r#"
+            {
+            "streams": {
+                "inventory-orders": { "identifier": "public.inventory.orders", "replicationMethod": "LOG_BASED" }
+            },
+            "commit": { "records": 2 },
+            "catalogUrl": "sqlite://",
+            "catalogName": "public"
+            }
+        "#
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/upsert/catalog.json", plugin.clone()).await?;
+
+        let input = File::open("../testdata/upsert/input1.txt")?;
+
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        let catalog = plugin.catalog().await?;
+
+        let orders_table = if let Tabular::Table(table) = catalog
+            .clone()
+            .load_tabular(&Identifier::parse("inventory.orders")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        // One commit after two records and one at the end of the input
+        let mut snapshots = orders_table
+            .metadata()
+            .snapshots
+            .values()
+            .collect::<Vec<_>>();
+        snapshots.sort_by_key(|x| *x.sequence_number());
+
+        assert_eq!(snapshots.len(), 2);
+
+        let manifests = orders_table
+            .manifests(None, Some(*snapshots[0].snapshot_id()))
+            .await?;
+
+        assert_eq!(
+            manifests
+                .iter()
+                .map(|x| x.added_rows_count.unwrap() + x.existing_rows_count.unwrap())
+                .sum::<i64>(),
+            2
+        );
+
+        let manifests = orders_table.manifests(None, None).await?;
+
+        assert_eq!(
+            manifests
+                .iter()
+                .map(|x| x.added_rows_count.unwrap() + x.existing_rows_count.unwrap())
+                .sum::<i64>(),
+            3
+        );
+
+        // The second commit merges its file into the manifest of the first one, the file of the
+        // first commit is an existing entry of the merged manifest
+        assert_eq!(manifests.len(), 1);
+        assert_eq!(manifests[0].added_files_count, Some(1));
+        assert_eq!(manifests[0].added_rows_count, Some(1));
+        assert_eq!(manifests[0].existing_files_count, Some(1));
+        assert_eq!(manifests[0].existing_rows_count, Some(2));
+        assert_eq!(
+            manifests[0].min_sequence_number,
+            *snapshots[0].sequence_number()
+        );
+
+        let orders_version = orders_table
+            .metadata()
+            .properties
+            .get("singer.bookmark")
+            .expect("Failed to get bookmark");
+
+        assert_eq!(
+            orders_version,
+            r#"{"last_replication_method":"LOG_BASED","lsn":37125980,"version":1703756002202}"#
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_emit_state() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        for emit_state in [true, false] {
+            let mut config_file = File::create(config_path.clone())?;
+
+            config_file.write_all(
+                format!(
+                    r#"
+            {{
+            "streams": {{
+                "inventory-orders": {{ "identifier": "public.inventory.orders", "replicationMethod": "LOG_BASED" }}
+            }},
+            "commit": {{ "records": 2 }},
+            "emitState": {},
+            "catalogUrl": "sqlite://",
+            "catalogName": "public"
+            }}
+        "#,
+                    emit_state
+                )
+                .as_bytes(),
+            )?;
+
+            let plugin =
+                Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+            select_streams("../testdata/upsert/catalog.json", plugin.clone()).await?;
+
+            let input = File::open("../testdata/upsert/input1.txt")?;
+
+            let output_path = tempdir.path().join("state.jsonl");
+
+            ingest(
+                plugin.clone(),
+                BufReader::new(input),
+                File::create(&output_path)?,
+            )
+            .await?;
+
+            let output = std::fs::read_to_string(&output_path)?;
+
+            if !emit_state {
+                assert!(output.is_empty());
+                continue;
+            }
+
+            // One state after each of the two commits
+            let states = output
+                .lines()
+                .map(serde_json::from_str::<serde_json::Value>)
+                .collect::<Result<Vec<_>, _>>()?;
+
+            assert_eq!(states.len(), 2);
+            assert_eq!(states[0]["type"], "STATE");
+            assert_eq!(
+                states[0]["value"]["bookmarks"]["inventory-orders"]["lsn"],
+                37125976
+            );
+            assert_eq!(
+                states[1]["value"]["bookmarks"]["inventory-orders"]["lsn"],
+                37125980
+            );
+        }
+
+        Ok(())
+    }
+    #[tokio::test]
+    async fn test_schema_evolution() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            r#"
+            {
+            "streams": {
+                "inventory-orders": { "identifier": "public.inventory.orders", "replicationMethod": "LOG_BASED" }
+            },
+            "catalogUrl": "sqlite://",
+            "catalogName": "public"
+            }
+        "#
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/evolution/catalog.json", plugin.clone()).await?;
+
+        let catalog = plugin.catalog().await?;
+
+        let orders_table = if let Tabular::Table(table) = catalog
+            .clone()
+            .load_tabular(&Identifier::parse("inventory.orders")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        let old_schema = orders_table.metadata().current_schema(None)?.clone();
+
+        assert!(old_schema.fields().get_name("quantity").unwrap().required);
+
+        let input = File::open("../testdata/evolution/input.txt")?;
+
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        let orders_table = if let Tabular::Table(table) = catalog
+            .clone()
+            .load_tabular(&Identifier::parse("inventory.orders")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        let schema = orders_table.metadata().current_schema(None)?;
+
+        assert_ne!(schema.schema_id(), old_schema.schema_id());
+
+        assert!(!schema.fields().get_name("quantity").unwrap().required);
+
+        let note = schema.fields().get_name("note").unwrap();
+
+        assert!(!note.required);
+        assert!(old_schema.fields().iter().all(|x| x.id < note.id));
+
+        // The records before the second SCHEMA message are committed with the old schema
+        let mut snapshots = orders_table
+            .metadata()
+            .snapshots
+            .values()
+            .collect::<Vec<_>>();
+        snapshots.sort_by_key(|x| *x.sequence_number());
+
+        assert_eq!(snapshots.len(), 2);
+
+        assert_eq!(snapshots[0].schema_id(), &Some(*old_schema.schema_id()));
+        assert_eq!(snapshots[1].schema_id(), &Some(*schema.schema_id()));
+
+        let manifests = orders_table.manifests(None, None).await?;
+
+        assert_eq!(
+            manifests
+                .iter()
+                .map(|x| x.added_rows_count.unwrap() + x.existing_rows_count.unwrap())
+                .sum::<i64>(),
+            4
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_nested() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            r#"
+            {
+            "streams": {
+                "shop-orders": { "identifier": "public.shop.orders", "maxNestingDepth": 2 }
+            },
+            "catalogUrl": "sqlite://",
+            "catalogName": "public"
+            }
+        "#
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/nested/catalog.json", plugin.clone()).await?;
+
+        let input = File::open("../testdata/nested/input.txt")?;
+
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        let catalog = plugin.catalog().await?;
+
+        let orders_table = if let Tabular::Table(table) = catalog
+            .clone()
+            .load_tabular(&Identifier::parse("shop.orders")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        let schema = orders_table.metadata().current_schema(None)?;
+
+        let field_type = |fields: &StructType, name: &str| {
+            fields
+                .get_name(name)
+                .map(|x| x.field_type.clone())
+                .ok_or(anyhow!("Field {} doesn't exist", name))
+        };
+
+        let Type::Struct(customer) = field_type(schema.fields(), "customer")? else {
+            return Err(anyhow!("Customer is not a struct"));
+        };
+        let Type::Struct(address) = field_type(&customer, "address")? else {
+            return Err(anyhow!("Address is not a struct"));
+        };
+        // Objects beyond the nesting depth are stored as JSON
+        assert_eq!(
+            field_type(&address, "geo")?,
+            Type::Primitive(PrimitiveType::String)
+        );
+
+        let Type::List(tags) = field_type(schema.fields(), "tags")? else {
+            return Err(anyhow!("Tags is not a list"));
+        };
+        assert_eq!(*tags.element, Type::Primitive(PrimitiveType::String));
+
+        let Type::List(items) = field_type(schema.fields(), "items")? else {
+            return Err(anyhow!("Items is not a list"));
+        };
+        let Type::Struct(item) = *items.element else {
+            return Err(anyhow!("Item is not a struct"));
+        };
+        assert_eq!(
+            field_type(&item, "quantity")?,
+            Type::Primitive(PrimitiveType::Long)
+        );
+
+        // Recursive references are stored as JSON
+        let Type::Struct(category) = field_type(schema.fields(), "category")? else {
+            return Err(anyhow!("Category is not a struct"));
+        };
+        assert_eq!(
+            field_type(&category, "parent")?,
+            Type::Primitive(PrimitiveType::String)
+        );
+
+        let manifests = orders_table.manifests(None, None).await?;
+
+        assert_eq!(
+            manifests
+                .iter()
+                .map(|x| x.added_rows_count.unwrap() + x.existing_rows_count.unwrap())
+                .sum::<i64>(),
+            3
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_numeric() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            r#"
+            {
+            "streams": {
+                "shop-payments": { "identifier": "public.shop.payments" }
+            },
+            "catalogUrl": "sqlite://",
+            "catalogName": "public"
+            }
+        "#
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/numeric/catalog.json", plugin.clone()).await?;
+
+        let input = File::open("../testdata/numeric/input.txt")?;
+
+        ingest(plugin.clone(), BufReader::new(input), io::sink()).await?;
+
+        let catalog = plugin.catalog().await?;
+
+        let payments_table = if let Tabular::Table(table) = catalog
+            .clone()
+            .load_tabular(&Identifier::parse("shop.payments")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        let schema = payments_table.metadata().current_schema(None)?;
+
+        let field_type = |name: &str| {
+            schema
+                .fields()
+                .get_name(name)
+                .map(|x| x.field_type.clone())
+                .ok_or(anyhow!("Field {} doesn't exist", name))
+        };
+
+        assert_eq!(field_type("id")?, Type::Primitive(PrimitiveType::Int));
+        assert_eq!(
+            field_type("account_id")?,
+            Type::Primitive(PrimitiveType::Long)
+        );
+        // Integers without hints use 64 bits
+        assert_eq!(
+            field_type("quantity")?,
+            Type::Primitive(PrimitiveType::Long)
+        );
+        assert_eq!(
+            field_type("price")?,
+            Type::Primitive(PrimitiveType::Decimal {
+                precision: 10,
+                scale: 2
+            })
+        );
+        assert_eq!(
+            field_type("amount")?,
+            Type::Primitive(PrimitiveType::Decimal {
+                precision: 12,
+                scale: 4
+            })
+        );
+        assert_eq!(field_type("ratio")?, Type::Primitive(PrimitiveType::Double));
+        assert_eq!(
+            field_type("counter")?,
+            Type::Primitive(PrimitiveType::Decimal {
+                precision: 20,
+                scale: 0
+            })
+        );
+        // The SCHEMA message without hints doesn't widen the column
+        assert_eq!(
+            field_type("installments")?,
+            Type::Primitive(PrimitiveType::Int)
+        );
+
+        let manifests = payments_table.manifests(None, None).await?;
+
+        let datafiles = payments_table.datafiles(&manifests, None).await?;
+
+        assert_eq!(
+            datafiles
+                .iter()
+                .map(|x| *x.data_file().record_count())
+                .sum::<i64>(),
+            2
+        );
+
+        let account_id = schema.fields().get_name("account_id").unwrap().id;
+
+        assert_eq!(
+            datafiles[0]
+                .data_file()
+                .upper_bounds()
+                .as_ref()
+                .and_then(|x| x.get(&account_id)),
+            Some(&Value::LongInt(9007199254740993))
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_decimal_widening() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            r#"
+            {
+            "streams": {
+                "shop-payments": { "identifier": "public.shop.payments" }
+            },
+            "catalogUrl": "sqlite://",
+            "catalogName": "public"
+            }
+        "#
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/numeric/catalog.json", plugin.clone()).await?;
+
+        // The amount is a 64 bit integer that doesn't fit into the numeric(12,4) column
+        let input = [
+            r#"{"type": "SCHEMA", "stream": "shop-payments", "schema": {"type": "object", "properties": {"id": {"type": ["integer"], "minimum": -2147483648, "maximum": 2147483647}, "amount": {"type": ["null", "integer"], "minimum": -9223372036854775808, "maximum": 9223372036854775807}}}, "key_properties": ["id"]}"#,
+            r#"{"type": "RECORD", "stream": "shop-payments", "record": {"id": 1, "amount": 9007199254740993}}"#,
+            r#"{"type": "STATE", "value": {"bookmarks": {"shop-payments": {"version": 1}}}}"#,
+        ]
+        .join("\n");
+
+        ingest(plugin.clone(), Cursor::new(input), io::sink()).await?;
+
+        let catalog = plugin.catalog().await?;
+
+        let payments_table = if let Tabular::Table(table) = catalog
+            .clone()
+            .load_tabular(&Identifier::parse("shop.payments")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        let schema = payments_table.metadata().current_schema(None)?;
+
+        // The column keeps its scale and gets the digits of a 64 bit integer
+        assert_eq!(
+            schema.fields().get_name("amount").unwrap().field_type,
+            Type::Primitive(PrimitiveType::Decimal {
+                precision: 23,
+                scale: 4
+            })
+        );
+
+        let manifests = payments_table.manifests(None, None).await?;
+
+        assert_eq!(
+            manifests
+                .iter()
+                .map(|x| x.added_rows_count.unwrap() + x.existing_rows_count.unwrap())
+                .sum::<i64>(),
+            1
+        );
+
+        Ok(())
+    }
+
+    // People records where the index of two records is a number instead of a string
+    fn invalid_people_input() -> Result<String, Error> {
+        Ok(std::fs::read_to_string("../testdata/people/input.txt")?
+            .replace(r#""Index": "3""#, r#""Index": 3"#)
+            .replace(r#""Index": "50""#, r#""Index": 50"#))
+    }
+
+    async fn people_plugin(
+        tempdir: &tempfile::TempDir,
+        on_invalid_record: &str,
+    ) -> Result<Arc<SqlTargetPlugin>, Error> {
+        let config_path = tempdir.path().join("config.json");
+
+        let mut config_file = File::create(config_path.clone())?;
+
+        config_file.write_all(
+            format!(
+                r#"
+            {{
+            "streams": {{
+                "people": {{ "identifier": "public.test.people", "onInvalidRecord": {} }}
+            }},
+            "catalogUrl": "sqlite://",
+            "catalogName": "public"
+            }}
+        "#,
+                on_invalid_record
+            )
+            .as_bytes(),
+        )?;
+
+        let plugin = Arc::new(SqlTargetPlugin::new(config_path.as_path().to_str().unwrap()).await?);
+
+        select_streams("../testdata/people/catalog.json", plugin.clone()).await?;
+
+        Ok(plugin)
+    }
+
+    #[tokio::test]
+    async fn test_skip_invalid_records() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        // Invalid records abort the sync by default
+        let plugin = people_plugin(&tempdir, r#""fail""#).await?;
+
+        assert!(
+            ingest(plugin, Cursor::new(invalid_people_input()?), io::sink())
+                .await
+                .is_err()
+        );
+
+        let plugin = people_plugin(&tempdir, r#""skip""#).await?;
+
+        ingest(
+            plugin.clone(),
+            Cursor::new(invalid_people_input()?),
+            io::sink(),
+        )
+        .await?;
+
+        let catalog = plugin.catalog().await?;
+
+        let table = if let Tabular::Table(table) = catalog
+            .load_tabular(&Identifier::parse("test.people")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        let manifests = table.manifests(None, None).await?;
+
+        assert_eq!(manifests[0].added_rows_count.unwrap(), 98);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dead_letter_table() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        let plugin = people_plugin(
+            &tempdir,
+            r#"{ "deadLetter": { "table": "public.test.people_rejected" } }"#,
+        )
+        .await?;
+
+        ingest(
+            plugin.clone(),
+            Cursor::new(invalid_people_input()?),
+            io::sink(),
+        )
+        .await?;
+
+        let catalog = plugin.catalog().await?;
+
+        let table = if let Tabular::Table(table) = catalog
+            .clone()
+            .load_tabular(&Identifier::parse("test.people")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        let manifests = table.manifests(None, None).await?;
+
+        assert_eq!(manifests[0].added_rows_count.unwrap(), 98);
+
+        let table = if let Tabular::Table(table) = catalog
+            .load_tabular(&Identifier::parse("test.people_rejected")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        let schema = table.metadata().current_schema(None)?;
+
+        assert!(schema.fields().get_name("stream").is_some());
+        assert!(schema.fields().get_name("record").is_some());
+        assert!(schema.fields().get_name("errors").is_some());
+
+        let manifests = table.manifests(None, None).await?;
+
+        assert_eq!(manifests[0].added_rows_count.unwrap(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dead_letter_jsonl() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        let plugin = people_plugin(
+            &tempdir,
+            r#"{ "deadLetter": { "jsonl": "rejected/people" } }"#,
+        )
+        .await?;
+
+        ingest(
+            plugin.clone(),
+            Cursor::new(invalid_people_input()?),
+            io::sink(),
+        )
+        .await?;
+
+        let catalog = plugin.catalog().await?;
+
+        let table = if let Tabular::Table(table) = catalog
+            .load_tabular(&Identifier::parse("test.people")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        let object_store = table.object_store();
+
+        let files = object_store
+            .list(Some(&"rejected/people".into()))
+            .try_collect::<Vec<_>>()
+            .await?;
+
+        assert_eq!(files.len(), 1);
+
+        let text = object_store.get(&files[0].location).await?.bytes().await?;
+
+        let dead_letters = std::str::from_utf8(&text)?
+            .lines()
+            .map(serde_json::from_str::<serde_json::Value>)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        assert_eq!(dead_letters.len(), 2);
+        assert_eq!(dead_letters[0]["stream"], "people");
+        assert_eq!(dead_letters[0]["record"]["Index"], 3);
+        assert_eq!(dead_letters[1]["record"]["Index"], 50);
+        assert!(dead_letters[0]["errors"][0]
+            .as_str()
+            .is_some_and(|error| error.contains("3")));
+
+        Ok(())
+    }
+
+    // Write the first half of the people records to a gzipped JSONL file and the second half to a
+    // parquet file with the given type for the "Index" column
+    fn people_batch_files(
+        dir: &std::path::Path,
+        index_type: arrow::datatypes::DataType,
+    ) -> Result<String, Error> {
+        use arrow::datatypes::{DataType, Field, Schema};
+        use flate2::{write::GzEncoder, Compression};
+        use parquet::arrow::ArrowWriter;
+
+        let lines = std::fs::read_to_string("../testdata/people/input.txt")?;
+        let lines = lines.lines().collect::<Vec<_>>();
+        let records = lines[2..102]
+            .iter()
+            .map(|line| Ok(serde_json::from_str::<serde_json::Value>(line)?["record"].clone()))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let jsonl_path = dir.join("people-1.jsonl.gz");
+        let mut encoder = GzEncoder::new(File::create(&jsonl_path)?, Compression::default());
+        for record in &records[..50] {
+            writeln!(encoder, "{}", record)?;
+        }
+        encoder.finish()?;
+
+        let schema = Arc::new(Schema::new(
+            records[0]
+                .as_object()
+                .unwrap()
+                .keys()
+                .map(|name| {
+                    let data_type = if name == "Index" {
+                        index_type.clone()
+                    } else {
+                        DataType::Utf8
+                    };
+                    Field::new(name, data_type, true)
+                })
+                .collect::<Vec<_>>(),
+        ));
+        let records = records[50..]
+            .iter()
+            .map(|record| {
+                let mut record = record.clone();
+                if index_type != DataType::Utf8 {
+                    record["Index"] = record["Index"].as_str().unwrap().parse::<i64>()?.into();
+                }
+                Ok(record)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let mut decoder = arrow::json::ReaderBuilder::new(schema.clone()).build_decoder()?;
+        decoder.serialize(&records)?;
+        let batch = decoder.flush()?.unwrap();
+
+        let parquet_path = dir.join("people-2.parquet");
+        let mut writer = ArrowWriter::try_new(File::create(&parquet_path)?, schema, None)?;
+        writer.write(&batch)?;
+        writer.close()?;
+
+        Ok([
+            lines[0].to_owned(),
+            lines[1].to_owned(),
+            format!(
+                r#"{{"type": "BATCH", "stream": "people", "encoding": {{"format": "jsonl", "compression": "gzip"}}, "manifest": ["file://{}"]}}"#,
+                jsonl_path.display()
+            ),
+            format!(
+                r#"{{"type": "BATCH", "stream": "people", "encoding": {{"format": "parquet", "compression": "none"}}, "manifest": ["{}"]}}"#,
+                parquet_path.display()
+            ),
+            lines[102].to_owned(),
+        ]
+        .join("\n"))
+    }
+
+    #[tokio::test]
+    async fn test_batch() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        let plugin = people_plugin(&tempdir, r#""fail""#).await?;
+
+        let input = people_batch_files(tempdir.path(), arrow::datatypes::DataType::Utf8)?;
+
+        ingest(plugin.clone(), Cursor::new(input), io::sink()).await?;
+
+        let catalog = plugin.catalog().await?;
+
+        let table = if let Tabular::Table(table) = catalog
+            .load_tabular(&Identifier::parse("test.people")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        let manifests = table.manifests(None, None).await?;
+
+        assert_eq!(manifests[0].added_rows_count.unwrap(), 100);
+
+        // The records of the JSONL file are written to a new file, the parquet file is added
+        let data_files = table.datafiles(&manifests, None).await?;
+
+        assert_eq!(data_files.len(), 2);
+        assert_eq!(
+            data_files
+                .iter()
+                .map(|x| *x.data_file().record_count())
+                .collect::<Vec<_>>(),
+            vec![50, 50]
+        );
+
+        assert!(table
+            .metadata()
+            .properties
+            .contains_key("schema.name-mapping.default"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_batch_schema_mismatch() -> Result<(), Error> {
+        let tempdir = tempdir()?;
+
+        let plugin = people_plugin(&tempdir, r#""fail""#).await?;
+
+        let input = people_batch_files(tempdir.path(), arrow::datatypes::DataType::Int64)?;
+
+        let error = ingest(plugin.clone(), Cursor::new(input), io::sink())
+            .await
+            .unwrap_err()
+            .to_string();
+
+        assert!(error.contains("Column Index of batch file"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_batch_field_ids() -> Result<(), Error> {
+        use arrow::{
+            array::{Array, AsArray},
+            datatypes::{Field, Schema},
+        };
+        use parquet::arrow::{
+            arrow_reader::ParquetRecordBatchReaderBuilder, ArrowWriter, PARQUET_FIELD_ID_META_KEY,
+        };
+
+        let tempdir = tempdir()?;
+
+        let plugin = people_plugin(&tempdir, r#""fail""#).await?;
+
+        let input = people_batch_files(tempdir.path(), arrow::datatypes::DataType::Utf8)?;
+
+        // Rewrite the parquet file with the columns in reverse order and field ids of another table
+        let parquet_path = tempdir.path().join("people-2.parquet");
+        let batch = ParquetRecordBatchReaderBuilder::try_new(File::open(&parquet_path)?)?
+            .build()?
+            .next()
+            .unwrap()?;
+        let fields = batch
+            .schema()
+            .fields()
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, field)| {
+                Field::new(field.name(), field.data_type().clone(), true).with_metadata(
+                    std::collections::HashMap::from([(
+                        PARQUET_FIELD_ID_META_KEY.to_owned(),
+                        (100 + i).to_string(),
+                    )]),
+                )
+            })
+            .collect::<Vec<_>>();
+        let columns = (0..batch.num_columns())
+            .rev()
+            .map(|i| batch.column(i).clone())
+            .collect::<Vec<_>>();
+        let schema = Arc::new(Schema::new(fields));
+        let mut writer = ArrowWriter::try_new(File::create(&parquet_path)?, schema.clone(), None)?;
+        writer.write(&arrow::record_batch::RecordBatch::try_new(schema, columns)?)?;
+        writer.close()?;
+
+        ingest(plugin.clone(), Cursor::new(input), io::sink()).await?;
+
+        let catalog = plugin.catalog().await?;
+
+        let table = if let Tabular::Table(table) = catalog
+            .load_tabular(&Identifier::parse("test.people")?)
+            .await?
+        {
+            Ok(table)
+        } else {
+            Err(anyhow!("Not a table"))
+        }?;
+
+        // The batches of the parquet file are written together with the records of the JSONL file
+        let manifests = table.manifests(None, None).await?;
+        let data_files = table.datafiles(&manifests, None).await?;
+
+        assert_eq!(data_files.len(), 1);
+        assert_eq!(*data_files[0].data_file().record_count(), 100);
+
+        let bytes = table
+            .object_store()
+            .get(
+                &data_files[0]
+                    .data_file()
+                    .file_path()
+                    .trim_start_matches('/')
+                    .into(),
+            )
+            .await?
+            .bytes()
+            .await?;
+        let indexes = ParquetRecordBatchReaderBuilder::try_new(bytes)?
+            .build()?
+            .map(|batch| Ok(batch?.column_by_name("Index").unwrap().clone()))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        assert_eq!(
+            indexes
+                .iter()
+                .map(|x| x.len() - x.null_count())
+                .sum::<usize>(),
+            100
+        );
+        assert_eq!(
+            indexes[indexes.len() - 1].as_string::<i32>().value(49),
+            "100"
+        );
+
+        Ok(())
+    }
 }
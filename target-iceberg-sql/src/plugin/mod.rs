@@ -1,15 +1,16 @@
 use std::{collections::HashMap, fs, sync::Arc};
 
-use anyhow::anyhow;
 use async_trait::async_trait;
-use dashtool_common::ObjectStoreConfig;
 use iceberg_rust::{catalog::Catalog, error::Error as IcebergError};
 use iceberg_sql_catalog::SqlCatalog;
-use object_store::{aws::AmazonS3Builder, memory::InMemory, ObjectStore};
+use object_store::ObjectStore;
 use serde::{Deserialize, Serialize};
 use target_iceberg::{
     error::SingerIcebergError,
-    plugin::{BaseConfig, StreamConfig, TargetPlugin},
+    plugin::{
+        object_store, AuditConfig, BaseConfig, BufferConfig, CommitConfig, MetricsConfig,
+        ObjectStoreConfig, StreamConfig, TargetPlugin,
+    },
 };
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -26,6 +27,8 @@ pub struct Config {
 #[derive(Debug)]
 pub(crate) struct SqlTargetPlugin {
     config: BaseConfig,
+    object_store: Arc<dyn ObjectStore>,
+    catalog_name: String,
     catalog: Arc<dyn Catalog>,
 }
 
@@ -34,50 +37,25 @@ impl SqlTargetPlugin {
         let config_json = fs::read_to_string(path)?;
         let mut config: Config = serde_json::from_str(&config_json)?;
 
-        let mut full_bucket_name = config.base.bucket.clone();
-        let object_store: Arc<dyn ObjectStore> = match &config.object_store {
-            ObjectStoreConfig::Memory => Arc::new(InMemory::new()),
-            ObjectStoreConfig::S3(s3_config) => {
-                let bucket_name = config
-                    .base
-                    .bucket
-                    .as_deref()
-                    .ok_or(SingerIcebergError::Anyhow(anyhow!("No bucket specified.")))?
-                    .trim_start_matches("s3://");
-
-                full_bucket_name = Some("s3://".to_owned() + bucket_name);
-
-                let mut builder = AmazonS3Builder::new()
-                    .with_region(&s3_config.aws_region)
-                    .with_bucket_name(bucket_name)
-                    .with_access_key_id(&s3_config.aws_access_key_id)
-                    .with_secret_access_key(s3_config.aws_secret_access_key.as_ref().ok_or(
-                        SingerIcebergError::Anyhow(anyhow!("No aws secret access key given.")),
-                    )?);
-
-                if let Some(endpoint) = &s3_config.aws_endpoint {
-                    builder = builder.with_endpoint(endpoint);
-                }
-
-                if let Some(allow_http) = &s3_config.aws_allow_http {
-                    builder =
-                        builder.with_allow_http(allow_http.parse().map_err(anyhow::Error::msg)?);
-                }
-
-                Arc::new(builder.build()?)
-            }
-        };
-
-        config.base.bucket = full_bucket_name;
+        let (object_store, bucket) =
+            object_store(&config.object_store, config.base.bucket.as_deref())?;
+
+        config.base.bucket = bucket;
 
         let catalog = Arc::new(
-            SqlCatalog::new(&config.catalog_url, &config.catalog_name, object_store)
-                .await
-                .map_err(IcebergError::from)?,
+            SqlCatalog::new(
+                &config.catalog_url,
+                &config.catalog_name,
+                object_store.clone(),
+            )
+            .await
+            .map_err(IcebergError::from)?,
         );
 
         Ok(Self {
             config: config.base,
+            object_store,
+            catalog_name: config.catalog_name,
             catalog,
         })
     }
@@ -93,6 +71,10 @@ impl TargetPlugin for SqlTargetPlugin {
         self.config.bucket.as_deref()
     }
 
+    fn object_store(&self) -> Arc<dyn ObjectStore> {
+        self.object_store.clone()
+    }
+
     fn streams(&self) -> &HashMap<String, StreamConfig> {
         &self.config.streams
     }
@@ -100,4 +82,41 @@ impl TargetPlugin for SqlTargetPlugin {
     fn branch(&self) -> &Option<String> {
         &self.config.branch
     }
+
+    fn commit(&self) -> &CommitConfig {
+        &self.config.commit
+    }
+
+    fn buffer(&self) -> &BufferConfig {
+        &self.config.buffer
+    }
+
+    fn emit_state(&self) -> bool {
+        self.config.emit_state
+    }
+
+    fn catalog_name(&self) -> Option<&str> {
+        Some(&self.catalog_name)
+    }
+
+    // Namespaces of the sql catalog only exist through their tables
+    fn explicit_namespaces(&self) -> bool {
+        false
+    }
+
+    fn assigns_sort_order_ids(&self) -> bool {
+        false
+    }
+
+    fn audit(&self) -> Option<&AuditConfig> {
+        self.config.audit.as_ref()
+    }
+
+    fn state_location(&self) -> Option<&str> {
+        self.config.state_location.as_deref()
+    }
+
+    fn metrics(&self) -> &MetricsConfig {
+        &self.config.metrics
+    }
 }
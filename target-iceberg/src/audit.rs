@@ -0,0 +1,129 @@
+use std::{iter, sync::Arc};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures::stream;
+use serde_json::{json, Value as JsonValue};
+use uuid::Uuid;
+
+use crate::{
+    catalog::load_or_create_table,
+    commit::{commit_snapshot, CommitRetry, SnapshotProperties},
+    error::SingerIcebergError,
+    ingest::{records_to_batch, SINGER_RUN_ID},
+    plugin::{AuditConfig, ParquetConfig, TargetPlugin},
+    schema::{current_schema, iceberg_to_arrow},
+    write::write_parquet_partitioned,
+};
+
+// What a stream wrote during one run of the target
+#[derive(Debug, Clone)]
+pub(crate) struct StreamRun {
+    pub(crate) stream: String,
+    pub(crate) identifier: String,
+    // Last snapshot that was committed during the run
+    pub(crate) snapshot_id: Option<i64>,
+    pub(crate) records_valid: usize,
+    pub(crate) records_rejected: usize,
+    pub(crate) bytes_written: i64,
+    pub(crate) started_at: DateTime<Utc>,
+    pub(crate) ended_at: DateTime<Utc>,
+    pub(crate) bookmark_before: Option<String>,
+    pub(crate) bookmark_after: Option<String>,
+    pub(crate) activate_version: Option<i64>,
+    // Error the stream failed with
+    pub(crate) error: Option<String>,
+}
+
+impl StreamRun {
+    fn to_json(&self, run_id: &Uuid) -> JsonValue {
+        json!({
+            "run_id": run_id.to_string(),
+            "stream": self.stream,
+            "identifier": self.identifier,
+            "snapshot_id": self.snapshot_id,
+            "records_read": self.records_valid + self.records_rejected,
+            "records_valid": self.records_valid,
+            "records_rejected": self.records_rejected,
+            "bytes_written": self.bytes_written,
+            "started_at": self.started_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+            "ended_at": self.ended_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+            "bookmark_before": self.bookmark_before,
+            "bookmark_after": self.bookmark_after,
+            "activate_version": self.activate_version,
+            "target_version": env!("CARGO_PKG_VERSION"),
+            "status": if self.error.is_none() { "succeeded" } else { "failed" },
+            "error": self.error,
+        })
+    }
+}
+
+// Append a row for every stream of the run to the audit table in a single snapshot
+pub(crate) async fn write_audit(
+    config: &AuditConfig,
+    plugin: &dyn TargetPlugin,
+    run_id: &Uuid,
+    runs: &[StreamRun],
+) -> Result<(), SingerIcebergError> {
+    if runs.is_empty() {
+        return Ok(());
+    }
+
+    let mut table = load_or_create_table(plugin, &config.table, &audit_schema()).await?;
+
+    let records = runs
+        .iter()
+        .map(|run| run.to_json(run_id))
+        .collect::<Vec<_>>();
+
+    let arrow_schema = Arc::new(iceberg_to_arrow(
+        current_schema(table.metadata())?.fields(),
+    )?);
+
+    let files = write_parquet_partitioned(
+        table.metadata(),
+        stream::iter(iter::once(records_to_batch(&records, arrow_schema))),
+        table.object_store(),
+        &ParquetConfig::default(),
+    )
+    .await?;
+
+    let retry = CommitRetry::new(plugin.commit(), &table.metadata().properties)?;
+
+    commit_snapshot(
+        &mut table,
+        plugin.branch().as_deref(),
+        files,
+        Vec::new(),
+        false,
+        SnapshotProperties {
+            table: Vec::new(),
+            summary: vec![(SINGER_RUN_ID.to_owned(), run_id.to_string())],
+        },
+        &retry,
+    )
+    .await
+}
+
+fn audit_schema() -> JsonValue {
+    json!({
+        "type": "object",
+        "properties": {
+            "run_id": { "type": "string" },
+            "stream": { "type": "string" },
+            "identifier": { "type": "string" },
+            "snapshot_id": { "type": ["null", "integer"] },
+            "records_read": { "type": "integer" },
+            "records_valid": { "type": "integer" },
+            "records_rejected": { "type": "integer" },
+            "bytes_written": { "type": "integer" },
+            "started_at": { "type": "string", "format": "date-time" },
+            "ended_at": { "type": "string", "format": "date-time" },
+            "bookmark_before": { "type": ["null", "string"] },
+            "bookmark_after": { "type": ["null", "string"] },
+            "activate_version": { "type": ["null", "integer"] },
+            "target_version": { "type": "string" },
+            "status": { "type": "string" },
+            "error": { "type": ["null", "string"] }
+        }
+    })
+}
@@ -0,0 +1,267 @@
+use std::{
+    io::{BufRead, BufReader, Read},
+    iter,
+    sync::Arc,
+};
+
+use anyhow::anyhow;
+use arrow::{
+    array::{new_null_array, Array, ArrayRef, AsArray, ListArray, MapArray, StructArray},
+    compute::cast,
+    datatypes::{DataType, Schema as ArrowSchema},
+    record_batch::RecordBatch,
+};
+use flate2::read::MultiGzDecoder;
+use iceberg_rust::{
+    spec::{
+        manifest::DataFile,
+        types::{StructType, Type},
+        util::strip_prefix,
+        values::{Struct, Value},
+    },
+    table::Table,
+};
+use object_store::{local::LocalFileSystem, path::Path, ObjectStore};
+use parquet::{arrow::PARQUET_FIELD_ID_META_KEY, file::metadata::ParquetMetaData};
+use serde_json::{json, Value as JsonValue};
+use uuid::Uuid;
+
+use crate::{
+    error::SingerIcebergError, messages::BatchCompression, schema::current_schema,
+    write::parquet_to_datafile,
+};
+
+pub(crate) static NAME_MAPPING: &str = "schema.name-mapping.default";
+
+// Resolve a file of a BATCH message. Files are either local or stored in the bucket of the tables.
+pub(crate) fn batch_file(
+    url: &str,
+    bucket: Option<&str>,
+    object_store: Arc<dyn ObjectStore>,
+) -> Result<(Arc<dyn ObjectStore>, Path), SingerIcebergError> {
+    let local = url
+        .strip_prefix("file://")
+        .or((!url.contains("://")).then_some(url));
+    if let Some(local) = local {
+        let path = Path::from_filesystem_path(local).map_err(object_store::Error::from)?;
+        return Ok((Arc::new(LocalFileSystem::new()), path));
+    }
+    match bucket {
+        Some(bucket) if url.starts_with(&(bucket.trim_end_matches('/').to_owned() + "/")) => {
+            Ok((object_store, strip_prefix(url).into()))
+        }
+        _ => Err(SingerIcebergError::Anyhow(anyhow!(
+            "Batch file {} is neither local nor in the bucket.",
+            url
+        ))),
+    }
+}
+
+pub(crate) async fn read_jsonl(
+    object_store: &Arc<dyn ObjectStore>,
+    path: &Path,
+    compression: Option<BatchCompression>,
+) -> Result<Vec<JsonValue>, SingerIcebergError> {
+    let bytes = object_store.get(path).await?.bytes().await?;
+    let reader: Box<dyn Read> = match compression {
+        Some(BatchCompression::Gzip) => Box::new(MultiGzDecoder::new(&bytes[..])),
+        _ => Box::new(&bytes[..]),
+    };
+    BufReader::new(reader)
+        .lines()
+        .filter(|line| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
+
+// Every column of a parquet file has to exist in the table with the same type and required
+// columns can't be missing
+pub(crate) fn check_parquet_schema(
+    file_schema: &ArrowSchema,
+    table_schema: &ArrowSchema,
+    url: &str,
+) -> Result<(), SingerIcebergError> {
+    for field in file_schema.fields() {
+        let table_field = table_schema.field_with_name(field.name()).map_err(|_| {
+            SingerIcebergError::Anyhow(anyhow!(
+                "Column {} of batch file {} doesn't exist in the table.",
+                field.name(),
+                url
+            ))
+        })?;
+        if !compatible(field.data_type(), table_field.data_type()) {
+            return Err(SingerIcebergError::Anyhow(anyhow!(
+                "Column {} of batch file {} has type {} instead of {}.",
+                field.name(),
+                url,
+                field.data_type(),
+                table_field.data_type()
+            )));
+        }
+    }
+    for field in table_schema.fields() {
+        if !field.is_nullable() && file_schema.field_with_name(field.name()).is_err() {
+            return Err(SingerIcebergError::Anyhow(anyhow!(
+                "Required column {} is missing in batch file {}.",
+                field.name(),
+                url
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn compatible(file: &DataType, table: &DataType) -> bool {
+    match (file, table) {
+        // The time zone of timestamps is always UTC
+        (DataType::Timestamp(file_unit, file_tz), DataType::Timestamp(table_unit, table_tz)) => {
+            file_unit == table_unit && file_tz.is_some() == table_tz.is_some()
+        }
+        (DataType::List(file), DataType::List(table)) => {
+            compatible(file.data_type(), table.data_type())
+        }
+        (DataType::Struct(file), DataType::Struct(table)) => file.iter().all(|file| {
+            table
+                .iter()
+                .find(|table| table.name() == file.name())
+                .is_some_and(|table| compatible(file.data_type(), table.data_type()))
+        }),
+        (DataType::Map(file, _), DataType::Map(table, _)) => {
+            compatible(file.data_type(), table.data_type())
+        }
+        (file, table) => file == table,
+    }
+}
+
+// Bring a batch of a parquet file into the arrow schema of the table. Columns are matched by name,
+// missing columns are null and the types are cast to the types of the table.
+pub(crate) fn project_batch(
+    batch: &RecordBatch,
+    schema: &Arc<ArrowSchema>,
+) -> Result<RecordBatch, SingerIcebergError> {
+    let columns = schema
+        .fields()
+        .iter()
+        .map(|field| match batch.column_by_name(field.name()) {
+            Some(column) => project_array(column, field.data_type()),
+            None => Ok(new_null_array(field.data_type(), batch.num_rows())),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+fn project_array(array: &ArrayRef, data_type: &DataType) -> Result<ArrayRef, SingerIcebergError> {
+    match (array.data_type(), data_type) {
+        (DataType::Struct(_), DataType::Struct(fields)) => {
+            let array = array.as_struct();
+            let columns = fields
+                .iter()
+                .map(|field| match array.column_by_name(field.name()) {
+                    Some(column) => project_array(column, field.data_type()),
+                    None => Ok(new_null_array(field.data_type(), array.len())),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Arc::new(StructArray::try_new(
+                fields.clone(),
+                columns,
+                array.nulls().cloned(),
+            )?))
+        }
+        (DataType::List(_), DataType::List(field)) => {
+            let array = array.as_list::<i32>();
+            Ok(Arc::new(ListArray::try_new(
+                field.clone(),
+                array.offsets().clone(),
+                project_array(array.values(), field.data_type())?,
+                array.nulls().cloned(),
+            )?))
+        }
+        (DataType::Map(_, _), DataType::Map(field, ordered)) => {
+            let array = array.as_map();
+            let entries: ArrayRef = Arc::new(array.entries().clone());
+            Ok(Arc::new(MapArray::try_new(
+                field.clone(),
+                array.offsets().clone(),
+                project_array(&entries, field.data_type())?
+                    .as_struct()
+                    .clone(),
+                array.nulls().cloned(),
+                *ordered,
+            )?))
+        }
+        (_, data_type) => Ok(cast(array, data_type)?),
+    }
+}
+
+// Files without field ids are read with the name mapping of the table. Files with other field ids
+// can't be added as they are.
+pub(crate) fn matching_field_ids(file_schema: &ArrowSchema, table_schema: &ArrowSchema) -> bool {
+    file_schema.fields().iter().all(
+        |field| match field.metadata().get(PARQUET_FIELD_ID_META_KEY) {
+            None => true,
+            Some(id) => table_schema
+                .field_with_name(field.name())
+                .is_ok_and(|table| table.metadata().get(PARQUET_FIELD_ID_META_KEY) == Some(id)),
+        },
+    )
+}
+
+// Copy a parquet file to the data directory of an unpartitioned table
+pub(crate) async fn add_parquet_file(
+    table: &Table,
+    object_store: &Arc<dyn ObjectStore>,
+    path: &Path,
+    metadata: &ParquetMetaData,
+) -> Result<DataFile, SingerIcebergError> {
+    let file_path =
+        table.metadata().location.to_string() + "/data/" + &Uuid::new_v4().to_string() + ".parquet";
+
+    let bytes = object_store.get(path).await?.bytes().await?;
+
+    let table_store = table.object_store();
+
+    table_store
+        .put(&strip_prefix(&file_path).into(), bytes)
+        .await?;
+
+    parquet_to_datafile(
+        file_path,
+        metadata.file_metadata().num_rows(),
+        metadata.row_groups(),
+        current_schema(table.metadata())?,
+        &iter::empty::<(String, Option<Value>)>().collect::<Struct>(),
+        &table_store,
+    )
+    .await
+}
+
+// Mapping from the column names to the field ids of the schema
+pub(crate) fn name_mapping(fields: &StructType) -> JsonValue {
+    JsonValue::Array(
+        fields
+            .iter()
+            .map(|field| mapped_field(field.id, &field.name, &field.field_type))
+            .collect(),
+    )
+}
+
+fn mapped_field(id: i32, name: &str, field_type: &Type) -> JsonValue {
+    let mut mapped = json!({ "field-id": id, "names": [name] });
+    let nested = match field_type {
+        Type::Struct(fields) => Some(name_mapping(fields)),
+        Type::List(list) => Some(json!([mapped_field(
+            list.element_id,
+            "element",
+            &list.element
+        )])),
+        Type::Map(map) => Some(json!([
+            mapped_field(map.key_id, "key", &map.key),
+            mapped_field(map.value_id, "value", &map.value)
+        ])),
+        Type::Primitive(_) => None,
+    };
+    if let Some(nested) = nested {
+        mapped["fields"] = nested;
+    }
+    mapped
+}
@@ -0,0 +1,224 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use tokio::sync::{watch, OwnedSemaphorePermit, Semaphore};
+
+use crate::plugin::BufferConfig;
+
+pub static DEFAULT_BUFFER_MEMORY: usize = 64 * 1024 * 1024;
+
+// Limits the messages that were read from the input but not yet written by their stream. The
+// size of a message is the length of its JSON text. Streams keep the reservations of their
+// buffered records and release them when the reader has to wait for the budget.
+#[derive(Debug)]
+pub(crate) struct Budget {
+    records: Option<(usize, Arc<Semaphore>)>,
+    memory: (usize, Arc<Semaphore>),
+    pressure: watch::Sender<bool>,
+    buffered_records: AtomicUsize,
+    buffered_memory: AtomicUsize,
+    peak_records: AtomicUsize,
+    peak_memory: AtomicUsize,
+}
+
+// Budget of a single message that is returned when the reservation is dropped
+#[derive(Debug)]
+pub(crate) struct Reservation {
+    budget: Arc<Budget>,
+    size: usize,
+    _permits: (Option<OwnedSemaphorePermit>, OwnedSemaphorePermit),
+}
+
+impl Budget {
+    pub(crate) fn new(config: &BufferConfig) -> Arc<Self> {
+        let memory = config.memory.unwrap_or(DEFAULT_BUFFER_MEMORY).max(1);
+        Arc::new(Budget {
+            records: config
+                .records
+                .map(|records| (records.max(1), Arc::new(Semaphore::new(records.max(1))))),
+            memory: (memory, Arc::new(Semaphore::new(memory))),
+            pressure: watch::Sender::new(false),
+            buffered_records: AtomicUsize::new(0),
+            buffered_memory: AtomicUsize::new(0),
+            peak_records: AtomicUsize::new(0),
+            peak_memory: AtomicUsize::new(0),
+        })
+    }
+
+    // Wait until the budget allows another message of the given size. Messages that are larger
+    // than the whole budget wait for all other messages to be processed.
+    pub(crate) async fn reserve(self: &Arc<Self>, size: usize) -> Reservation {
+        let record_permit = match &self.records {
+            Some((_, semaphore)) => Some(self.acquire(semaphore, 1).await),
+            None => None,
+        };
+        let (limit, semaphore) = &self.memory;
+        let permits = u32::try_from(size.clamp(1, *limit)).unwrap_or(u32::MAX);
+        let memory_permit = self.acquire(semaphore, permits).await;
+
+        let records = self.buffered_records.fetch_add(1, Ordering::SeqCst) + 1;
+        self.peak_records.fetch_max(records, Ordering::SeqCst);
+        let memory = self.buffered_memory.fetch_add(size, Ordering::SeqCst) + size;
+        self.peak_memory.fetch_max(memory, Ordering::SeqCst);
+
+        Reservation {
+            budget: self.clone(),
+            size,
+            _permits: (record_permit, memory_permit),
+        }
+    }
+
+    // The budget is under pressure while the reader waits for permits
+    async fn acquire(&self, semaphore: &Arc<Semaphore>, permits: u32) -> OwnedSemaphorePermit {
+        if let Ok(permit) = semaphore.clone().try_acquire_many_owned(permits) {
+            return permit;
+        }
+        self.pressure.send_replace(true);
+        let permit = semaphore
+            .clone()
+            .acquire_many_owned(permits)
+            .await
+            .expect("Semaphore is never closed");
+        self.pressure.send_replace(false);
+        permit
+    }
+
+    pub(crate) fn pressure(&self) -> watch::Receiver<bool> {
+        self.pressure.subscribe()
+    }
+
+    pub(crate) fn peak_records(&self) -> usize {
+        self.peak_records.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn peak_memory(&self) -> usize {
+        self.peak_memory.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.budget.buffered_records.fetch_sub(1, Ordering::SeqCst);
+        self.budget
+            .buffered_memory
+            .fetch_sub(self.size, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use futures::{channel::mpsc::unbounded, StreamExt};
+
+    use crate::plugin::BufferConfig;
+
+    use super::Budget;
+
+    // A fast producer and a slow consumer, like reading stdin while writing parquet files
+    async fn produce(config: BufferConfig, lines: usize, line_size: usize) -> Arc<Budget> {
+        let budget = Budget::new(&config);
+        let (sender, mut reciever) = unbounded();
+
+        let producer = {
+            let budget = budget.clone();
+            tokio::spawn(async move {
+                for i in 0..lines {
+                    let reservation = budget.reserve(line_size).await;
+                    sender.unbounded_send((i, reservation)).unwrap();
+                }
+            })
+        };
+
+        let mut received = 0;
+        while let Some((i, reservation)) = reciever.next().await {
+            assert_eq!(i, received);
+            received += 1;
+            if received % 100 == 0 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            drop(reservation);
+        }
+        producer.await.unwrap();
+
+        assert_eq!(received, lines);
+
+        budget
+    }
+
+    #[tokio::test]
+    async fn test_memory_budget() {
+        let budget = produce(
+            BufferConfig {
+                records: None,
+                memory: Some(100_000),
+            },
+            20_000,
+            1_000,
+        )
+        .await;
+
+        assert!(budget.peak_memory() <= 100_000);
+        assert!(budget.peak_records() <= 100);
+    }
+
+    #[tokio::test]
+    async fn test_record_budget() {
+        let budget = produce(
+            BufferConfig {
+                records: Some(50),
+                memory: None,
+            },
+            20_000,
+            1_000,
+        )
+        .await;
+
+        assert!(budget.peak_records() <= 50);
+        assert!(budget.peak_memory() <= 50_000);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_message() {
+        let budget = produce(
+            BufferConfig {
+                records: None,
+                memory: Some(1_000),
+            },
+            100,
+            5_000,
+        )
+        .await;
+
+        // Messages larger than the budget are processed one at a time
+        assert_eq!(budget.peak_records(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_pressure() {
+        let budget = Budget::new(&BufferConfig {
+            records: Some(1),
+            memory: None,
+        });
+        let mut pressure = budget.pressure();
+
+        let held = budget.reserve(10).await;
+        assert!(!*pressure.borrow_and_update());
+
+        let waiting = {
+            let budget = budget.clone();
+            tokio::spawn(async move { budget.reserve(10).await })
+        };
+
+        // The reader waits until the held reservation is released
+        pressure.wait_for(|x| *x).await.unwrap();
+        drop(held);
+        let reservation = waiting.await.unwrap();
+        assert!(!*pressure.borrow());
+
+        drop(reservation);
+        assert_eq!(budget.peak_records(), 1);
+    }
+}
@@ -1,14 +1,52 @@
-use std::{fs, sync::Arc};
+use std::{collections::HashMap, fs, sync::Arc};
 
 use anyhow::anyhow;
 use futures::{stream, StreamExt, TryStreamExt};
-use iceberg_rust::spec::partition::{PartitionField, PartitionSpec, Transform};
-use iceberg_rust::table::Table;
-use iceberg_rust::{catalog::identifier::Identifier, spec::schema::Schema};
+use iceberg_rust::{
+    catalog::{
+        bucket::Bucket, create::CreateTable, identifier::Identifier, tabular::Tabular, Catalog,
+    },
+    error::Error as IcebergError,
+    spec::{
+        schema::Schema,
+        sort::DEFAULT_SORT_ORDER_ID,
+        table_metadata::{FormatVersion, TableMetadata},
+        util::strip_prefix,
+    },
+    table::Table,
+};
+use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
-use singer::catalog::{Catalog as SingerCatalog, Metadata, Stream as SingerStream};
+use singer::catalog::Metadata;
+use tracing::warn;
+use uuid::Uuid;
 
-use crate::{error::SingerIcebergError, plugin::TargetPlugin, schema::schema_to_arrow};
+use crate::{
+    commit::{commit_properties, commit_sort_order, CommitRetry},
+    error::SingerIcebergError,
+    identifier::{create_namespaces, identifier_segments, resolve_identifier},
+    partition::partition_spec,
+    plugin::{TableFormatVersion, TargetPlugin, WriteMode},
+    schema::{schema_to_iceberg, DEFAULT_MAX_NESTING_DEPTH},
+    sort::{default_sort_order, sort_order},
+};
+
+// Singer catalog that keeps the original JSON schema of the streams
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SingerCatalog {
+    pub streams: Vec<SingerStream>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SingerStream {
+    pub stream: String,
+    pub tap_stream_id: String,
+    pub schema: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub table_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Vec<Metadata>>,
+}
 
 pub async fn select_streams(
     path: &str,
@@ -20,127 +58,332 @@ pub async fn select_streams(
 
     let catalog: SingerCatalog = serde_json::from_str(&json)?;
 
-    let streams = stream::iter(catalog.streams.into_iter())
-        .filter_map(|stream| async move {
+    // The identifiers, schemas, partition specs, sort orders and format versions of all selected
+    // streams are checked before any table is created
+    let selected = catalog
+        .streams
+        .into_iter()
+        .filter_map(|stream| {
             let config = streams.get(&stream.tap_stream_id)?;
             Some((stream, config))
         })
-        .then(|(mut stream, config)| {
-            let plugin = plugin.clone();
-            async move {
-                if stream.metadata.is_some() {
-                    if let Some(vec) = stream.metadata.as_mut() {
-                        let opt = vec.iter_mut().find(|x| x.breadcrumb.is_empty());
-                        if opt.is_some() {
-                            if let Some(metadata) = opt {
-                                if let Value::Object(metadata) = &mut metadata.metadata {
-                                    metadata.insert("selected".to_string(), Value::Bool(true));
-                                    metadata.insert(
-                                        "replication-method".to_owned(),
-                                        Value::String(config.replication_method.to_string()),
-                                    );
+        .map(|(stream, config)| {
+            let ident = resolve_identifier(&config.identifier, plugin.catalog_name())?;
+            // Tables are stored under all names of their identifier including the catalog
+            let path = identifier_segments(&config.identifier)?.join("/");
+            let fields = schema_to_iceberg(
+                &stream.schema,
+                &stream
+                    .metadata
+                    .as_deref()
+                    .map(sql_datatypes)
+                    .unwrap_or_default(),
+                config
+                    .max_nesting_depth
+                    .unwrap_or(DEFAULT_MAX_NESTING_DEPTH),
+            )?;
+            if config.format_version == Some(TableFormatVersion::V1)
+                && (config.write_mode == WriteMode::Upsert || config.delete_mode.is_some())
+            {
+                return Err(SingerIcebergError::Anyhow(anyhow!(
+                    "Stream {} can't use format version 1, because writeMode upsert and deleteMode require delete files.",
+                    stream.tap_stream_id
+                )));
+            }
+            let partition_spec = config
+                .partition_by
+                .as_deref()
+                .map(|partition_by| partition_spec(partition_by, &fields))
+                .transpose()
+                .map_err(|err| {
+                    anyhow!(
+                        "Invalid partitionBy of stream {}: {}",
+                        stream.tap_stream_id,
+                        err
+                    )
+                })?;
+            let sort_order = config
+                .sort_by
+                .as_deref()
+                .map(|sort_by| sort_order(sort_by, &fields))
+                .transpose()
+                .map_err(|err| {
+                    anyhow!("Invalid sortBy of stream {}: {}", stream.tap_stream_id, err)
+                })?;
+            Ok((stream, config, ident, path, fields, partition_spec, sort_order))
+        })
+        .collect::<Result<Vec<_>, SingerIcebergError>>()?;
+
+    let streams = stream::iter(selected)
+        .then(
+            |(mut stream, config, ident, path, fields, partition_spec, sort_order)| {
+                let plugin = plugin.clone();
+                async move {
+                    if stream.metadata.is_some() {
+                        if let Some(vec) = stream.metadata.as_mut() {
+                            let opt = vec.iter_mut().find(|x| x.breadcrumb.is_empty());
+                            if opt.is_some() {
+                                if let Some(metadata) = opt {
+                                    if let Value::Object(metadata) = &mut metadata.metadata {
+                                        metadata.insert("selected".to_string(), Value::Bool(true));
+                                        metadata.insert(
+                                            "replication-method".to_owned(),
+                                            Value::String(config.replication_method.to_string()),
+                                        );
+                                    }
                                 }
+                            } else {
+                                vec.push(Metadata {
+                                    metadata: Value::Object(Map::from_iter(vec![
+                                        ("selected".to_string(), Value::Bool(true)),
+                                        (
+                                            "replication-method".to_owned(),
+                                            Value::String(config.replication_method.to_string()),
+                                        ),
+                                    ])),
+                                    breadcrumb: vec![],
+                                })
                             }
+                        }
+                    } else {
+                        stream.metadata = Some(vec![Metadata {
+                            metadata: Value::Object(Map::from_iter(vec![
+                                ("selected".to_string(), Value::Bool(true)),
+                                (
+                                    "replication-method".to_owned(),
+                                    Value::String(config.replication_method.to_string()),
+                                ),
+                            ])),
+                            breadcrumb: vec![],
+                        }])
+                    };
+
+                    let catalog = plugin.catalog().await?;
+
+                    if !catalog.tabular_exists(&ident).await? {
+                        if plugin.explicit_namespaces() {
+                            create_namespaces(catalog.as_ref(), ident.namespace()).await?;
+                        }
+
+                        let schema = Schema::builder()
+                            .with_fields(fields)
+                            .build()
+                            .map_err(iceberg_rust::spec::error::Error::from)?;
+
+                        let location =
+                            table_location(plugin.bucket(), &path, config.location.as_deref());
+
+                        let mut builder = Table::builder();
+                        builder
+                            .with_name(ident.name())
+                            .with_location(&location)
+                            .with_schema(schema);
+
+                        for (key, value) in &config.properties {
+                            builder.with_property((key.clone(), value.clone()));
+                        }
+
+                        if let Some(partition_spec) = partition_spec {
+                            builder.with_partition_spec(partition_spec);
+                        }
+
+                        let sorted = sort_order.is_some();
+                        if let Some(sort_order) = sort_order {
+                            builder.with_sort_order(sort_order);
+                        }
+
+                        let create = builder.create().map_err(IcebergError::from)?;
+
+                        let format_version =
+                            config.format_version.unwrap_or(TableFormatVersion::V2);
+
+                        if format_version == TableFormatVersion::V1
+                            || sorted && !plugin.assigns_sort_order_ids()
+                        {
+                            register_new_table(ident, create, format_version, catalog).await?;
                         } else {
-                            vec.push(Metadata {
-                                metadata: Value::Object(Map::from_iter(vec![
-                                    ("selected".to_string(), Value::Bool(true)),
-                                    (
-                                        "replication-method".to_owned(),
-                                        Value::String(config.replication_method.to_string()),
-                                    ),
-                                ])),
-                                breadcrumb: vec![],
-                            })
+                            catalog.create_table(ident, create).await?;
                         }
-                    }
-                } else {
-                    stream.metadata = Some(vec![Metadata {
-                        metadata: Value::Object(Map::from_iter(vec![
-                            ("selected".to_string(), Value::Bool(true)),
-                            (
-                                "replication-method".to_owned(),
-                                Value::String(config.replication_method.to_string()),
-                            ),
-                        ])),
-                        breadcrumb: vec![],
-                    }])
-                };
-
-                let ident = Identifier::try_new(
-                    &config
-                        .identifier
-                        .split('.')
-                        .collect::<Vec<_>>()
-                        .into_iter()
-                        .rev()
-                        .take(2)
-                        .rev()
-                        .map(ToOwned::to_owned)
-                        .collect::<Vec<_>>(),
-                )?;
-
-                let catalog = plugin.catalog().await?;
-
-                if !catalog.tabular_exists(&ident).await? {
-                    let arrow_schema = schema_to_arrow(&stream.schema)?;
-
-                    let schema = Schema::builder()
-                        .with_fields((&arrow_schema).try_into()?)
-                        .build()
-                        .map_err(iceberg_rust::spec::error::Error::from)?;
-
-                    let base_path = plugin
-                        .bucket()
-                        .unwrap_or("")
-                        .trim_end_matches("/")
-                        .to_string()
-                        + "/"
-                        + &config.identifier.replace(".", "/");
-
-                    let mut builder = Table::builder();
-                    builder.with_name(ident.name());
-
-                    if let Some(columns) = &config.partition_by {
-                        builder.with_partition_spec(
-                            PartitionSpec::builder()
-                                .with_fields(
-                                    columns
-                                        .iter()
-                                        .enumerate()
-                                        .map(|(i, (column, transform))| {
-                                            let field = schema.fields().get_name(column).ok_or(
-                                                SingerIcebergError::Anyhow(anyhow!(
-                                                    "Field {} doesn't exist in schema.",
-                                                    column
-                                                )),
-                                            )?;
-                                            let transform =
-                                                serde_json::from_str::<Transform>(transform)?;
-                                            Ok::<_, SingerIcebergError>(PartitionField::new(
-                                                field.id,
-                                                1000 + i as i32,
-                                                column,
-                                                transform,
-                                            ))
-                                        })
-                                        .collect::<Result<_, _>>()?,
-                                )
-                                .build()
-                                .map_err(iceberg_rust::spec::error::Error::from)?,
-                        );
-                    }
+                    } else {
+                        let Tabular::Table(mut table) = catalog.load_tabular(&ident).await? else {
+                            return Err(SingerIcebergError::Unknown);
+                        };
 
-                    builder.with_location(&base_path).with_schema(schema);
+                        let metadata = table.metadata();
 
-                    builder.build(ident.namespace(), catalog).await?;
-                }
+                        if let Some(format_version) = config.format_version {
+                            if metadata.format_version != FormatVersion::from(format_version) {
+                                warn!(
+                                    "Table {} keeps its format version, it can't be changed to {}.",
+                                    config.identifier,
+                                    u8::from(format_version)
+                                );
+                            }
+                        }
+                        if config.location.is_some() {
+                            let location =
+                                table_location(plugin.bucket(), &path, config.location.as_deref());
+                            if metadata.location != location {
+                                warn!(
+                                    "Table {} keeps its location {}, it can't be moved to {}.",
+                                    config.identifier, metadata.location, location
+                                );
+                            }
+                        }
 
-                Ok::<_, SingerIcebergError>(stream)
-            }
-        })
+                        // Only the configured properties are reconciled, other properties like the
+                        // bookmark stay untouched
+                        let updates = config
+                            .properties
+                            .iter()
+                            .filter(|(key, value)| metadata.properties.get(*key) != Some(*value))
+                            .map(|(key, value)| (key.clone(), value.clone()))
+                            .collect::<Vec<_>>();
+
+                        // The sort order of existing tables is changed if it differs from the
+                        // configured one
+                        let sort_order = sort_order.filter(|sort_order| {
+                            default_sort_order(metadata)
+                                .is_none_or(|current| current.fields != sort_order.fields)
+                        });
+
+                        let retry = CommitRetry::new(plugin.commit(), &metadata.properties)?;
+
+                        if !updates.is_empty() {
+                            commit_properties(
+                                &mut table,
+                                plugin.branch().as_deref(),
+                                updates,
+                                &retry,
+                            )
+                            .await?;
+                        }
+
+                        if let Some(sort_order) = sort_order {
+                            commit_sort_order(&mut table, sort_order, &retry).await?;
+                        }
+                    }
+
+                    Ok::<_, SingerIcebergError>(stream)
+                }
+            },
+        )
         .try_collect::<Vec<SingerStream>>()
         .await?;
 
     Ok(SingerCatalog { streams })
 }
+
+// Load the table of an identifier that isn't a stream, for example of dead letters, or create it
+// with the given JSON schema in the bucket
+pub(crate) async fn load_or_create_table(
+    plugin: &dyn TargetPlugin,
+    identifier: &str,
+    schema: &Value,
+) -> Result<Table, SingerIcebergError> {
+    let ident = resolve_identifier(identifier, plugin.catalog_name())?;
+
+    let catalog = plugin.catalog().await?;
+
+    if catalog.tabular_exists(&ident).await? {
+        return match catalog.clone().load_tabular(&ident).await? {
+            Tabular::Table(table) => Ok(table),
+            _ => Err(SingerIcebergError::Unknown),
+        };
+    }
+
+    let schema = Schema::builder()
+        .with_fields(schema_to_iceberg(
+            schema,
+            &HashMap::new(),
+            DEFAULT_MAX_NESTING_DEPTH,
+        )?)
+        .build()
+        .map_err(iceberg_rust::spec::error::Error::from)?;
+
+    if plugin.explicit_namespaces() {
+        create_namespaces(catalog.as_ref(), ident.namespace()).await?;
+    }
+
+    let location = table_location(
+        plugin.bucket(),
+        &identifier_segments(identifier)?.join("/"),
+        None,
+    );
+
+    let mut builder = Table::builder();
+    builder
+        .with_name(ident.name())
+        .with_location(&location)
+        .with_schema(schema);
+    Ok(builder.build(ident.namespace(), catalog).await?)
+}
+
+// Tables are stored under the path of their identifier in the bucket unless a location is
+// configured. Locations without a scheme are paths in the bucket.
+fn table_location(bucket: Option<&str>, path: &str, location: Option<&str>) -> String {
+    match location {
+        Some(location) if location.contains("://") => location.trim_end_matches('/').to_owned(),
+        _ => {
+            bucket.unwrap_or("").trim_end_matches('/').to_string()
+                + "/"
+                + &location
+                    .map(|location| location.trim_matches('/').to_owned())
+                    .unwrap_or(path.to_owned())
+        }
+    }
+}
+
+// The sql and file catalogs create tables with format version 2 and keep the sort order under the
+// id of the unsorted order, so these tables are registered from a metadata file
+async fn register_new_table(
+    ident: Identifier,
+    create: CreateTable,
+    format_version: TableFormatVersion,
+    catalog: Arc<dyn Catalog>,
+) -> Result<Table, SingerIcebergError> {
+    let mut metadata: TableMetadata = create.try_into()?;
+    metadata.format_version = format_version.into();
+
+    if let Some(sort_order) = metadata
+        .sort_orders
+        .remove(&DEFAULT_SORT_ORDER_ID)
+        .filter(|sort_order| !sort_order.fields.is_empty())
+    {
+        metadata.default_sort_order_id = sort_order.order_id;
+        metadata.sort_orders.insert(sort_order.order_id, sort_order);
+    }
+    metadata
+        .sort_orders
+        .entry(DEFAULT_SORT_ORDER_ID)
+        .or_default();
+
+    let metadata_location = metadata.location.clone()
+        + "/metadata/00000-"
+        + &Uuid::new_v4().to_string()
+        + ".metadata.json";
+
+    catalog
+        .object_store(Bucket::from_path(&metadata_location)?)
+        .put(
+            &strip_prefix(&metadata_location).into(),
+            serde_json::to_vec(&metadata)?.into(),
+        )
+        .await?;
+
+    Ok(catalog.register_table(ident, &metadata_location).await?)
+}
+
+// The sql-datatype metadata of the top level columns
+fn sql_datatypes(metadata: &[Metadata]) -> HashMap<String, String> {
+    metadata
+        .iter()
+        .filter_map(|x| match x.breadcrumb.as_slice() {
+            [properties, name] if properties == "properties" => Some((
+                name.clone(),
+                x.metadata.get("sql-datatype")?.as_str()?.to_owned(),
+            )),
+            _ => None,
+        })
+        .collect()
+}
@@ -0,0 +1,750 @@
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::anyhow;
+use apache_avro::{types::Value as AvroValue, Reader as AvroReader, Schema as AvroSchema};
+use iceberg_rust::{
+    catalog::{
+        commit::{CommitTable, TableRequirement, TableUpdate},
+        tabular::Tabular,
+    },
+    error::Error as IcebergError,
+    spec::{
+        error::Error as SpecError,
+        manifest::{Content, DataFile, ManifestEntry, ManifestWriter, Status},
+        manifest_list::ManifestListEntry,
+        schema::Schema,
+        snapshot::{
+            generate_snapshot_id, Operation, SnapshotBuilder, SnapshotReference, SnapshotRetention,
+            Summary,
+        },
+        sort::SortOrder,
+        table_metadata::{FormatVersion, TableMetadata},
+        util::strip_prefix,
+        values::Struct,
+    },
+    table::Table,
+};
+use tokio::time::sleep;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{
+    delete::{avro_field, data_file_paths},
+    error::SingerIcebergError,
+    partition::{avro_partition_struct, partition_summaries, partition_value_schema},
+    plugin::CommitConfig,
+    schema::current_schema,
+    state::SINGER_SNAPSHOT_ID,
+    write::setting,
+};
+
+static RETRIES: &str = "commit.retry.num-retries";
+static MIN_WAIT: &str = "commit.retry.min-wait-ms";
+static MAX_WAIT: &str = "commit.retry.max-wait-ms";
+
+static DEFAULT_RETRIES: u32 = 4;
+static DEFAULT_MIN_WAIT: u64 = 100;
+static DEFAULT_MAX_WAIT: u64 = 60_000;
+
+static MANIFEST_TARGET_SIZE: &str = "commit.manifest.target-size-bytes";
+static DEFAULT_MANIFEST_TARGET_SIZE: i64 = 8 * 1024 * 1024;
+
+// iceberg-rust types the content of manifest list entries with the content of data files. The
+// manifest list only knows data (0) and deletes (1) manifests, a deletes manifest holds position
+// and equality delete files alike.
+const DATA_MANIFEST: Content = Content::Data;
+const DELETES_MANIFEST: Content = Content::PositionDeletes;
+
+// Commits are optimistic. A commit that fails because the table was changed concurrently is
+// retried on top of the reloaded table with exponential backoff.
+#[derive(Debug, Clone)]
+pub(crate) struct CommitRetry {
+    retries: u32,
+    min_wait: Duration,
+    max_wait: Duration,
+}
+
+impl CommitRetry {
+    pub(crate) fn new(
+        config: &CommitConfig,
+        table_properties: &HashMap<String, String>,
+    ) -> Result<Self, SingerIcebergError> {
+        Ok(Self {
+            retries: setting(config.retries, table_properties, RETRIES)?.unwrap_or(DEFAULT_RETRIES),
+            min_wait: Duration::from_millis(
+                setting(config.retry_min_wait, table_properties, MIN_WAIT)?
+                    .unwrap_or(DEFAULT_MIN_WAIT),
+            ),
+            max_wait: Duration::from_millis(
+                setting(config.retry_max_wait, table_properties, MAX_WAIT)?
+                    .unwrap_or(DEFAULT_MAX_WAIT),
+            ),
+        })
+    }
+
+    // Reload the table after a failed commit and wait before the next attempt. The error of the
+    // commit is returned if the table wasn't changed concurrently or the retries are used up.
+    async fn rebase(
+        &self,
+        table: &mut Table,
+        attempt: &mut u32,
+        error: IcebergError,
+    ) -> Result<(), SingerIcebergError> {
+        if *attempt >= self.retries {
+            return Err(error.into());
+        }
+        let Tabular::Table(reloaded) = table.catalog().load_tabular(table.identifier()).await?
+        else {
+            return Err(error.into());
+        };
+        if reloaded.metadata() == table.metadata() {
+            return Err(error.into());
+        }
+
+        let wait = self
+            .min_wait
+            .saturating_mul(2u32.saturating_pow(*attempt))
+            .min(self.max_wait);
+        *attempt += 1;
+
+        warn!(
+            "Table {} was changed concurrently, retry {} of {} in {} ms",
+            table.identifier(),
+            attempt,
+            self.retries,
+            wait.as_millis()
+        );
+
+        sleep(wait).await;
+
+        *table = reloaded;
+
+        Ok(())
+    }
+}
+
+// Table properties that are set together with a new snapshot and additional entries of the
+// snapshot summary. Properties are overwritten by later commits and are stored with the id of
+// their snapshot, the summary stays with its snapshot.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SnapshotProperties {
+    pub(crate) table: Vec<(String, String)>,
+    pub(crate) summary: Vec<(String, String)>,
+}
+
+// Commit new data files together with delete files as a new snapshot. The delete files only
+// apply to rows that were committed in earlier snapshots, because they share the sequence number
+// of the new data. If `replace` is set, the new snapshot only contains the new files.
+pub(crate) async fn commit_snapshot(
+    table: &mut Table,
+    branch: Option<&str>,
+    data_files: Vec<DataFile>,
+    delete_files: Vec<DataFile>,
+    replace: bool,
+    properties: SnapshotProperties,
+    retry: &CommitRetry,
+) -> Result<(), SingerIcebergError> {
+    if let (FormatVersion::V1, false) = (&table.metadata().format_version, delete_files.is_empty())
+    {
+        return Err(SingerIcebergError::Anyhow(anyhow!(
+            "Table {} has format version 1, delete files require format version 2.",
+            table.identifier()
+        )));
+    }
+
+    let position_deletes = delete_files
+        .iter()
+        .any(|file| matches!(file.content(), Content::PositionDeletes));
+
+    let schema_id = table.metadata().current_schema_id;
+
+    // Data files of the snapshot the commit was prepared on
+    let mut previous_files = None;
+
+    let mut attempt = 0;
+
+    loop {
+        let commit = snapshot_commit(
+            table,
+            branch,
+            data_files.clone(),
+            delete_files.clone(),
+            replace,
+            properties.clone(),
+        )
+        .await?;
+
+        let error = match table.catalog().update_table(commit).await {
+            Ok(updated) => {
+                *table = updated;
+                return Ok(());
+            }
+            Err(error) => error,
+        };
+
+        // Appends stay valid on top of any concurrent change of the data. Rewrites only stay valid
+        // if no data files were added concurrently, because the rewrite would drop them. Position
+        // deletes only stay valid if the data files they refer to weren't removed concurrently.
+        if (replace || position_deletes) && previous_files.is_none() {
+            previous_files = Some(data_file_paths(table, branch).await?);
+        }
+
+        retry.rebase(table, &mut attempt, error).await?;
+
+        // The new files were written with the schema the commit was prepared on
+        if table.metadata().current_schema_id != schema_id {
+            return Err(SingerIcebergError::Anyhow(anyhow!(
+                "Schema of table {} was changed concurrently, the new files no longer match it.",
+                table.identifier()
+            )));
+        }
+
+        if let Some(previous_files) = &previous_files {
+            let current_files = data_file_paths(table, branch).await?;
+            if replace && !current_files.is_subset(previous_files) {
+                return Err(SingerIcebergError::Anyhow(anyhow!(
+                    "Data files were added to table {} concurrently, the rewrite would drop them.",
+                    table.identifier()
+                )));
+            }
+            if position_deletes && !previous_files.is_subset(&current_files) {
+                return Err(SingerIcebergError::Anyhow(anyhow!(
+                    "Data files of table {} were removed concurrently, the position deletes are no longer valid.",
+                    table.identifier()
+                )));
+            }
+        }
+    }
+}
+
+// Requirements of commits that were prepared on the current state of the table: the current
+// schema and the snapshot of the branch
+fn table_requirements(
+    table: &Table,
+    branch: Option<&str>,
+) -> Result<Vec<TableRequirement>, SingerIcebergError> {
+    let metadata = table.metadata();
+
+    let mut requirements = vec![TableRequirement::AssertCurrentSchemaId {
+        current_schema_id: metadata.current_schema_id,
+    }];
+    if let Some(snapshot) = metadata.current_snapshot(branch)? {
+        requirements.push(TableRequirement::AssertRefSnapshotId {
+            r#ref: branch.unwrap_or("main").to_owned(),
+            snapshot_id: *snapshot.snapshot_id(),
+        });
+    }
+
+    Ok(requirements)
+}
+
+// Write the manifests and the manifest list of a new snapshot on top of the current snapshot
+async fn snapshot_commit(
+    table: &Table,
+    branch: Option<&str>,
+    data_files: Vec<DataFile>,
+    delete_files: Vec<DataFile>,
+    replace: bool,
+    properties: SnapshotProperties,
+) -> Result<CommitTable, SingerIcebergError> {
+    let metadata = table.metadata();
+
+    let object_store = table.object_store();
+    let schema = current_schema(metadata)?;
+    let old_snapshot = metadata.current_snapshot(branch)?;
+
+    let snapshot_id = generate_snapshot_id();
+    let sequence_number = old_snapshot
+        .map(|x| *x.sequence_number())
+        .unwrap_or_default()
+        .max(metadata.last_sequence_number)
+        + 1;
+
+    let manifest_list_location = metadata.location.to_string()
+        + "/metadata/snap-"
+        + &snapshot_id.to_string()
+        + "-"
+        + &Uuid::new_v4().to_string()
+        + ".avro";
+
+    let mut old_manifests = match old_snapshot.filter(|_| !replace) {
+        Some(snapshot) => snapshot
+            .manifests(metadata, object_store.clone())
+            .await?
+            .collect::<Result<Vec<_>, SpecError>>()?,
+        None => Vec::new(),
+    };
+
+    let manifest_schema = manifest_schema(metadata)?;
+    let manifest_target_size = setting(None, &metadata.properties, MANIFEST_TARGET_SIZE)?
+        .unwrap_or(DEFAULT_MANIFEST_TARGET_SIZE);
+
+    let mut manifests = Vec::new();
+
+    let mut summary = HashMap::from_iter(properties.summary);
+
+    let delete_files_empty = delete_files.is_empty();
+
+    for (i, (deletes, files)) in [(false, data_files), (true, delete_files)]
+        .into_iter()
+        .enumerate()
+    {
+        if files.is_empty() {
+            continue;
+        }
+        summary.insert(
+            if deletes {
+                "added-delete-files"
+            } else {
+                "added-data-files"
+            }
+            .to_owned(),
+            files.len().to_string(),
+        );
+        for file in &files {
+            let records_key = match file.content() {
+                Content::Data => "added-records",
+                Content::PositionDeletes => "added-position-deletes",
+                Content::EqualityDeletes => "added-equality-deletes",
+            };
+            let records = summary
+                .get(records_key)
+                .and_then(|x| x.parse::<i64>().ok())
+                .unwrap_or_default()
+                + file.record_count();
+            summary.insert(records_key.to_owned(), records.to_string());
+        }
+
+        let manifest_location = manifest_list_location.trim_end_matches(".avro").to_owned()
+            + "-m"
+            + &i.to_string()
+            + ".avro";
+
+        // The files are added to the newest small manifest of the same kind, so the manifest list
+        // only grows once that manifest reaches the target size
+        let mut merge = None;
+        if let Some(i) = old_manifests.iter().position(|manifest| {
+            manifest.partition_spec_id == metadata.default_spec_id
+                && (manifest.content == DELETES_MANIFEST) == deletes
+                && manifest.manifest_length < manifest_target_size
+        }) {
+            if let Some(entries) =
+                manifest_entries(table, &old_manifests[i], &manifest_schema).await?
+            {
+                merge = Some((old_manifests.remove(i), entries));
+            }
+        }
+
+        manifests.push(
+            write_manifest(
+                table,
+                branch,
+                manifest_location,
+                files,
+                merge,
+                snapshot_id,
+                sequence_number,
+            )
+            .await?,
+        );
+    }
+
+    // New manifests come first in the manifest list
+    manifests.extend(old_manifests);
+
+    let manifest_list_schema = ManifestListEntry::schema(&metadata.format_version)?;
+    let mut manifest_list_writer = apache_avro::Writer::new(&manifest_list_schema, Vec::new());
+    for manifest in manifests {
+        manifest_list_writer
+            .append_ser(manifest)
+            .map_err(IcebergError::from)?;
+    }
+    let manifest_list_bytes = manifest_list_writer
+        .into_inner()
+        .map_err(IcebergError::from)?;
+
+    object_store
+        .put(
+            &strip_prefix(&manifest_list_location).into(),
+            manifest_list_bytes.into(),
+        )
+        .await?;
+
+    let mut snapshot_builder = SnapshotBuilder::default();
+    snapshot_builder
+        .with_snapshot_id(snapshot_id)
+        .with_sequence_number(sequence_number)
+        .with_manifest_list(manifest_list_location)
+        .with_schema_id(*schema.schema_id())
+        .with_summary(Summary {
+            operation: if replace || !delete_files_empty {
+                Operation::Overwrite
+            } else {
+                Operation::Append
+            },
+            other: summary,
+        });
+    if let Some(old_snapshot) = old_snapshot {
+        snapshot_builder.with_parent_snapshot_id(*old_snapshot.snapshot_id());
+    }
+    let snapshot = snapshot_builder.build().map_err(SpecError::from)?;
+
+    let ref_name = branch.unwrap_or("main").to_owned();
+
+    let mut updates = vec![
+        TableUpdate::AddSnapshot { snapshot },
+        TableUpdate::SetSnapshotRef {
+            ref_name: ref_name.clone(),
+            snapshot_reference: SnapshotReference {
+                snapshot_id,
+                retention: SnapshotRetention::default(),
+            },
+        },
+    ];
+    if !properties.table.is_empty() {
+        updates.push(TableUpdate::SetProperties {
+            updates: HashMap::from_iter(
+                properties
+                    .table
+                    .into_iter()
+                    .chain([(SINGER_SNAPSHOT_ID.to_owned(), snapshot_id.to_string())]),
+            ),
+        });
+    }
+
+    Ok(CommitTable {
+        identifier: table.identifier().clone(),
+        requirements: table_requirements(table, branch)?,
+        updates,
+    })
+}
+
+// Add a new schema to the table and make it the current schema. The schema is derived from the
+// current schema, so it can't be committed after a concurrent schema change.
+pub(crate) async fn commit_schema(
+    table: &mut Table,
+    schema: Schema,
+    last_column_id: i32,
+    retry: &CommitRetry,
+) -> Result<(), SingerIcebergError> {
+    let current_schema_id = table.metadata().current_schema_id;
+    let last_assigned_field_id = table.metadata().last_column_id;
+    let schema_id = *schema.schema_id();
+
+    let mut attempt = 0;
+
+    loop {
+        let commit = CommitTable {
+            identifier: table.identifier().clone(),
+            requirements: vec![
+                TableRequirement::AssertCurrentSchemaId { current_schema_id },
+                TableRequirement::AssertLastAssignedFieldId {
+                    last_assigned_field_id,
+                },
+            ],
+            updates: vec![
+                TableUpdate::AddSchema {
+                    schema: schema.clone(),
+                    last_column_id: Some(last_column_id),
+                },
+                TableUpdate::SetCurrentSchema { schema_id },
+            ],
+        };
+
+        let error = match table.catalog().update_table(commit).await {
+            Ok(updated) => {
+                *table = updated;
+                return Ok(());
+            }
+            Err(error) => error,
+        };
+
+        retry.rebase(table, &mut attempt, error).await?;
+
+        let metadata = table.metadata();
+        if metadata.current_schema_id != current_schema_id
+            || metadata.last_column_id != last_assigned_field_id
+        {
+            return Err(SingerIcebergError::Anyhow(anyhow!(
+                "Schema of table {} was changed concurrently.",
+                table.identifier()
+            )));
+        }
+    }
+}
+
+// Set table properties, other properties of the table stay untouched
+pub(crate) async fn commit_properties(
+    table: &mut Table,
+    branch: Option<&str>,
+    properties: Vec<(String, String)>,
+    retry: &CommitRetry,
+) -> Result<(), SingerIcebergError> {
+    let mut attempt = 0;
+
+    loop {
+        let commit = CommitTable {
+            identifier: table.identifier().clone(),
+            requirements: table_requirements(table, branch)?,
+            updates: vec![TableUpdate::SetProperties {
+                updates: HashMap::from_iter(properties.clone()),
+            }],
+        };
+
+        match table.catalog().update_table(commit).await {
+            Ok(updated) => {
+                *table = updated;
+                return Ok(());
+            }
+            Err(error) => retry.rebase(table, &mut attempt, error).await?,
+        }
+    }
+}
+
+// Make the sort order the default sort order of the table. An equal sort order of the table is
+// reused, otherwise the sort order is added with the next free id.
+pub(crate) async fn commit_sort_order(
+    table: &mut Table,
+    sort_order: SortOrder,
+    retry: &CommitRetry,
+) -> Result<(), SingerIcebergError> {
+    let mut attempt = 0;
+
+    loop {
+        let metadata = table.metadata();
+
+        let existing = metadata
+            .sort_orders
+            .values()
+            .find(|existing| existing.fields == sort_order.fields)
+            .map(|existing| existing.order_id);
+
+        if existing == Some(metadata.default_sort_order_id) {
+            return Ok(());
+        }
+
+        let sort_order_id = existing.unwrap_or_else(|| {
+            metadata
+                .sort_orders
+                .keys()
+                .max()
+                .copied()
+                .unwrap_or_default()
+                + 1
+        });
+
+        let mut updates = Vec::new();
+        if existing.is_none() {
+            updates.push(TableUpdate::AddSortOrder {
+                sort_order: SortOrder {
+                    order_id: sort_order_id,
+                    fields: sort_order.fields.clone(),
+                },
+            });
+        }
+        updates.push(TableUpdate::SetDefaultSortOrder { sort_order_id });
+
+        let commit = CommitTable {
+            identifier: table.identifier().clone(),
+            requirements: vec![TableRequirement::AssertDefaultSortOrderId {
+                default_sort_order_id: metadata.default_sort_order_id,
+            }],
+            updates,
+        };
+
+        match table.catalog().update_table(commit).await {
+            Ok(updated) => {
+                *table = updated;
+                return Ok(());
+            }
+            Err(error) => retry.rebase(table, &mut attempt, error).await?,
+        }
+    }
+}
+
+// Turn a file with full rows into an equality delete file on the given field ids
+pub(crate) fn equality_delete_file(
+    file: DataFile,
+    equality_ids: &[i32],
+) -> Result<DataFile, SingerIcebergError> {
+    Ok(DataFile::builder()
+        .with_content(Content::EqualityDeletes)
+        .with_file_path(file.file_path().clone())
+        .with_file_format(file.file_format().clone())
+        .with_partition(file.partition().clone())
+        .with_record_count(*file.record_count())
+        .with_file_size_in_bytes(*file.file_size_in_bytes())
+        .with_column_sizes(file.column_sizes().clone())
+        .with_value_counts(file.value_counts().clone())
+        .with_null_value_counts(file.null_value_counts().clone())
+        .with_nan_value_counts(file.nan_value_counts().clone())
+        .with_distinct_counts(file.distinct_counts().clone())
+        .with_lower_bounds(file.lower_bounds().clone())
+        .with_upper_bounds(file.upper_bounds().clone())
+        .with_equality_ids(Some(equality_ids.to_vec()))
+        .build()
+        .map_err(SpecError::from)?)
+}
+
+fn manifest_schema(metadata: &TableMetadata) -> Result<AvroSchema, SingerIcebergError> {
+    Ok(ManifestEntry::schema(
+        &partition_value_schema(
+            metadata.default_partition_spec()?,
+            current_schema(metadata)?,
+        )?,
+        &metadata.format_version,
+    )?)
+}
+
+// Live entries of an old manifest as existing entries of a new manifest. They keep the snapshot id
+// and the sequence numbers that they inherited from the old manifest. Returns None if the manifest
+// was written with a different schema.
+async fn manifest_entries(
+    table: &Table,
+    manifest: &ManifestListEntry,
+    schema: &AvroSchema,
+) -> Result<Option<Vec<AvroValue>>, SingerIcebergError> {
+    let bytes = table
+        .object_store()
+        .get(&strip_prefix(&manifest.manifest_path).into())
+        .await?
+        .bytes()
+        .await?;
+
+    let reader = AvroReader::new(&bytes[..]).map_err(IcebergError::from)?;
+    if reader.writer_schema() != schema {
+        return Ok(None);
+    }
+
+    let mut entries = Vec::new();
+    for entry in reader {
+        let AvroValue::Record(mut entry) = entry.map_err(IcebergError::from)? else {
+            continue;
+        };
+        // Deleted entries (status 2) only belong to the snapshot that deleted them
+        if matches!(avro_field(&entry, "status"), Some(AvroValue::Int(2))) {
+            continue;
+        }
+        for (name, value) in entry.iter_mut() {
+            let inherited = match name.as_str() {
+                "status" => {
+                    *value = AvroValue::Int(0);
+                    continue;
+                }
+                "snapshot_id" => manifest.added_snapshot_id,
+                "sequence_number" | "file_sequence_number" => manifest.sequence_number,
+                _ => continue,
+            };
+            if let AvroValue::Union(_, inner) = value {
+                if let AvroValue::Null = **inner {
+                    *value = AvroValue::Union(1, Box::new(AvroValue::Long(inherited)));
+                }
+            }
+        }
+        entries.push(AvroValue::Record(entry));
+    }
+
+    Ok(Some(entries))
+}
+
+// Write a manifest with the files as added entries and the entries of an old manifest that is
+// merged into it as existing entries
+async fn write_manifest(
+    table: &Table,
+    branch: Option<&str>,
+    location: String,
+    files: Vec<DataFile>,
+    merge: Option<(ManifestListEntry, Vec<AvroValue>)>,
+    snapshot_id: i64,
+    sequence_number: i64,
+) -> Result<ManifestListEntry, SingerIcebergError> {
+    let metadata = table.metadata();
+    let object_store = table.object_store();
+    let spec = metadata.default_partition_spec()?;
+    let schema = current_schema(metadata)?;
+
+    let deletes = files
+        .iter()
+        .any(|file| !matches!(file.content(), Content::Data));
+
+    let manifest_schema = manifest_schema(metadata)?;
+
+    let mut manifest_writer = ManifestWriter::new(Vec::new(), &manifest_schema, metadata, branch)?;
+    manifest_writer
+        .add_user_metadata(
+            "content".to_owned(),
+            if deletes { "deletes" } else { "data" },
+        )
+        .map_err(IcebergError::from)?;
+
+    let added_files_count = files.len() as i32;
+    let mut added_rows_count = 0;
+    let mut existing_files_count = 0;
+    let mut existing_rows_count = 0;
+    let mut min_sequence_number = sequence_number;
+    let mut partitions: Vec<Struct> = Vec::new();
+
+    if let Some((manifest, entries)) = merge {
+        min_sequence_number = min_sequence_number.min(manifest.min_sequence_number);
+        for entry in entries {
+            if let AvroValue::Record(fields) = &entry {
+                if let Some(AvroValue::Record(data_file)) = avro_field(fields, "data_file") {
+                    if let Some(AvroValue::Long(records)) = avro_field(data_file, "record_count") {
+                        existing_rows_count += records;
+                    }
+                    if let Some(partition) = avro_field(data_file, "partition") {
+                        partitions.push(avro_partition_struct(partition, spec, schema)?);
+                    }
+                }
+            }
+            existing_files_count += 1;
+            manifest_writer.append(entry).map_err(IcebergError::from)?;
+        }
+    }
+
+    for file in files {
+        added_rows_count += file.record_count();
+        partitions.push(file.partition().clone());
+        let entry = ManifestEntry::builder()
+            .with_format_version(metadata.format_version.clone())
+            .with_status(Status::Added)
+            .with_snapshot_id(Some(snapshot_id))
+            .with_sequence_number(Some(sequence_number))
+            .with_data_file(file)
+            .build()
+            .map_err(SpecError::from)?;
+        manifest_writer
+            .append_ser(entry)
+            .map_err(IcebergError::from)?;
+    }
+
+    let manifest_bytes = manifest_writer.into_inner()?;
+    let manifest_length = manifest_bytes.len() as i64;
+
+    object_store
+        .put(&strip_prefix(&location).into(), manifest_bytes.into())
+        .await?;
+
+    Ok(ManifestListEntry {
+        format_version: metadata.format_version.clone(),
+        manifest_path: location,
+        manifest_length,
+        partition_spec_id: metadata.default_spec_id,
+        content: if deletes {
+            DELETES_MANIFEST
+        } else {
+            DATA_MANIFEST
+        },
+        sequence_number,
+        min_sequence_number,
+        added_snapshot_id: snapshot_id,
+        added_files_count: Some(added_files_count),
+        existing_files_count: Some(existing_files_count),
+        deleted_files_count: Some(0),
+        added_rows_count: Some(added_rows_count),
+        existing_rows_count: Some(existing_rows_count),
+        deleted_rows_count: Some(0),
+        partitions: Some(partition_summaries(spec, schema, partitions.iter())?),
+        key_metadata: None,
+    })
+}
@@ -0,0 +1,147 @@
+use std::{iter, sync::Arc};
+
+use futures::stream;
+use iceberg_rust::{spec::util::strip_prefix, table::Table};
+use object_store::{path::Path, ObjectStore};
+use serde_json::{json, Value as JsonValue};
+use uuid::Uuid;
+
+use crate::{
+    catalog::load_or_create_table,
+    commit::{commit_snapshot, CommitRetry, SnapshotProperties},
+    error::SingerIcebergError,
+    ingest::records_to_batch,
+    plugin::{DeadLetter, ParquetConfig, TargetPlugin},
+    schema::{current_schema, iceberg_to_arrow},
+    write::write_parquet_partitioned,
+};
+
+// Destination of the records of a stream that failed the schema validation
+pub(crate) enum DeadLetterQueue {
+    Table {
+        table: Box<Table>,
+        branch: Option<String>,
+        retry: CommitRetry,
+    },
+    Jsonl {
+        object_store: Arc<dyn ObjectStore>,
+        location: String,
+    },
+}
+
+impl DeadLetterQueue {
+    // The dead-letter table is created next to the table of the stream if it doesn't exist
+    pub(crate) async fn new(
+        config: &DeadLetter,
+        plugin: &dyn TargetPlugin,
+        stream_table: &Table,
+    ) -> Result<Self, SingerIcebergError> {
+        let bucket = plugin.bucket();
+        match config {
+            DeadLetter::Table(identifier) => {
+                let table = load_or_create_table(plugin, identifier, &dead_letter_schema()).await?;
+
+                Ok(DeadLetterQueue::Table {
+                    retry: CommitRetry::new(plugin.commit(), &table.metadata().properties)?,
+                    table: Box::new(table),
+                    branch: plugin.branch().clone(),
+                })
+            }
+            DeadLetter::Jsonl(directory) => Ok(DeadLetterQueue::Jsonl {
+                object_store: stream_table.object_store(),
+                location: location(bucket, directory),
+            }),
+        }
+    }
+
+    // Write the rejected records in a single file, for tables as a new snapshot
+    pub(crate) async fn write(
+        &mut self,
+        records: Vec<JsonValue>,
+    ) -> Result<(), SingerIcebergError> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        match self {
+            DeadLetterQueue::Table {
+                table,
+                branch,
+                retry,
+            } => {
+                // The original record is stored as JSON text
+                let records = records
+                    .into_iter()
+                    .map(|mut record| {
+                        if let Some(value) = record.get_mut("record") {
+                            *value = JsonValue::String(value.to_string());
+                        }
+                        record
+                    })
+                    .collect::<Vec<_>>();
+
+                let arrow_schema = Arc::new(iceberg_to_arrow(
+                    current_schema(table.metadata())?.fields(),
+                )?);
+
+                let files = write_parquet_partitioned(
+                    table.metadata(),
+                    stream::iter(iter::once(records_to_batch(&records, arrow_schema))),
+                    table.object_store(),
+                    &ParquetConfig::default(),
+                )
+                .await?;
+
+                commit_snapshot(
+                    table,
+                    branch.as_deref(),
+                    files,
+                    Vec::new(),
+                    false,
+                    SnapshotProperties::default(),
+                    retry,
+                )
+                .await
+            }
+            DeadLetterQueue::Jsonl {
+                object_store,
+                location,
+            } => {
+                let mut bytes = Vec::new();
+                for record in records {
+                    serde_json::to_writer(&mut bytes, &record)?;
+                    bytes.push(b'\n');
+                }
+
+                let path: Path =
+                    strip_prefix(&format!("{}/{}.jsonl", location, Uuid::new_v4())).into();
+
+                object_store.put(&path, bytes.into()).await?;
+
+                Ok(())
+            }
+        }
+    }
+}
+
+pub(crate) fn dead_letter(stream: &str, record: JsonValue, errors: Vec<String>) -> JsonValue {
+    json!({
+        "stream": stream,
+        "record": record,
+        "errors": errors,
+    })
+}
+
+fn dead_letter_schema() -> JsonValue {
+    json!({
+        "type": "object",
+        "properties": {
+            "stream": { "type": "string" },
+            "record": { "type": "string" },
+            "errors": { "type": "array", "items": { "type": "string" } }
+        }
+    })
+}
+
+pub(crate) fn location(bucket: Option<&str>, path: &str) -> String {
+    bucket.unwrap_or("").trim_end_matches('/').to_string() + "/" + path.trim_matches('/')
+}
@@ -0,0 +1,400 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    iter,
+    sync::Arc,
+};
+
+use anyhow::anyhow;
+use apache_avro::{types::Value as AvroValue, Reader as AvroReader};
+use arrow::{
+    array::{new_null_array, Int64Array, StringArray},
+    compute::cast,
+    datatypes::{DataType, Field, Schema as ArrowSchema},
+    json::ArrayWriter,
+    record_batch::RecordBatch,
+    row::{RowConverter, SortField},
+};
+use futures::TryStreamExt;
+use iceberg_rust::{
+    error::Error as IcebergError,
+    spec::{
+        error::Error as SpecError,
+        manifest::{Content, DataFile, FileFormat},
+        manifest_list::ManifestListEntry,
+        types::Type,
+        util::strip_prefix,
+        values::{Struct, Value},
+    },
+    table::Table,
+};
+use parquet::arrow::{
+    async_reader::ParquetObjectReader, ArrowWriter, ParquetRecordBatchStreamBuilder,
+    ProjectionMask, PARQUET_FIELD_ID_META_KEY,
+};
+use serde_json::{Map, Value as JsonValue};
+use uuid::Uuid;
+
+use crate::{
+    error::SingerIcebergError,
+    partition::{avro_partition_struct, primitive_column},
+    plugin::ParquetConfig,
+    schema::current_schema,
+    write::writer_properties,
+};
+
+pub(crate) static SDC_DELETED_AT: &str = "_sdc_deleted_at";
+
+// Reserved field ids of the position delete schema
+static FILE_PATH_FIELD_ID: &str = "2147483546";
+static POS_FIELD_ID: &str = "2147483545";
+
+pub(crate) fn is_deleted(record: &JsonValue) -> bool {
+    record
+        .get(SDC_DELETED_AT)
+        .is_some_and(|deleted_at| !deleted_at.is_null())
+}
+
+pub(crate) fn record_key(record: &JsonValue, key_properties: &[String]) -> String {
+    key_properties
+        .iter()
+        .map(|key| record.get(key).map(ToString::to_string).unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join("\u{1F}")
+}
+
+// Scan the data files of the current snapshot for rows with the keys of the deleted records and
+// write one position delete file for every data file that contains such rows. Data files whose
+// column bounds exclude all keys aren't read. Keys are compared as typed arrow values, so keys
+// that are written differently in JSON, like timestamps with offsets, still match.
+pub(crate) async fn position_delete_files(
+    table: &Table,
+    branch: Option<&str>,
+    deletes: &RecordBatch,
+    key_properties: &[String],
+    parquet: &ParquetConfig,
+) -> Result<Vec<DataFile>, SingerIcebergError> {
+    let metadata = table.metadata();
+    let object_store = table.object_store();
+    let schema = current_schema(metadata)?;
+
+    let key_fields = key_properties
+        .iter()
+        .map(|key| {
+            let field = schema.fields().get_name(key).ok_or(anyhow!(
+                "Key property {} doesn't exist in table schema.",
+                key
+            ))?;
+            let column = deletes.column_by_name(key).ok_or(anyhow!(
+                "Key property {} is missing in the deleted records.",
+                key
+            ))?;
+            Ok((field, column.data_type().clone()))
+        })
+        .collect::<Result<Vec<_>, SingerIcebergError>>()?;
+
+    let converter = RowConverter::new(
+        key_fields
+            .iter()
+            .map(|(_, data_type)| SortField::new(data_type.clone()))
+            .collect(),
+    )?;
+
+    let key_columns = |batch: &RecordBatch| {
+        key_fields
+            .iter()
+            .map(
+                |(field, data_type)| match batch.column_by_name(&field.name) {
+                    Some(column) => Ok(cast(column, data_type)?),
+                    None => Ok(new_null_array(data_type, batch.num_rows())),
+                },
+            )
+            .collect::<Result<Vec<_>, SingerIcebergError>>()
+    };
+
+    let keys = converter.convert_columns(&key_columns(deletes)?)?;
+    let keys = keys
+        .iter()
+        .map(|row| row.as_ref().to_vec())
+        .collect::<HashSet<_>>();
+
+    // Range of the deleted keys of every key column
+    let key_ranges = key_fields
+        .iter()
+        .filter_map(|(field, _)| {
+            let Type::Primitive(primitive) = &field.field_type else {
+                return None;
+            };
+            let values = primitive_column(deletes.column_by_name(&field.name)?, primitive)?;
+            let values = values.into_iter().collect::<Option<Vec<_>>>()?;
+            let min = values
+                .iter()
+                .min_by(|x, y| x.partial_cmp(y).unwrap_or(Ordering::Equal))?;
+            let max = values
+                .iter()
+                .max_by(|x, y| x.partial_cmp(y).unwrap_or(Ordering::Equal))?;
+            Some((field, min.clone(), max.clone()))
+        })
+        .collect::<Vec<_>>();
+
+    let data_files = data_files(table, &data_manifests(table, branch).await?).await?;
+
+    let schema = Arc::new(ArrowSchema::new(vec![
+        Field::new("file_path", DataType::Utf8, false).with_metadata(HashMap::from_iter(vec![(
+            PARQUET_FIELD_ID_META_KEY.to_owned(),
+            FILE_PATH_FIELD_ID.to_owned(),
+        )])),
+        Field::new("pos", DataType::Int64, false).with_metadata(HashMap::from_iter(vec![(
+            PARQUET_FIELD_ID_META_KEY.to_owned(),
+            POS_FIELD_ID.to_owned(),
+        )])),
+    ]));
+
+    let properties = writer_properties(parquet, &metadata.properties)?;
+
+    let mut delete_files = Vec::new();
+
+    for data_file in data_files {
+        let excluded = key_ranges.iter().any(|(field, min, max)| {
+            let bound = |bounds: &HashMap<i32, Vec<u8>>| {
+                bounds
+                    .get(&field.id)
+                    .and_then(|bytes| Value::try_from_bytes(bytes, &field.field_type).ok())
+            };
+            bound(&data_file.lower_bounds).is_some_and(|lower| lower > *max)
+                || bound(&data_file.upper_bounds).is_some_and(|upper| upper < *min)
+        });
+        if excluded {
+            continue;
+        }
+
+        let file_path = data_file.file_path;
+
+        let object_meta = object_store.head(&strip_prefix(&file_path).into()).await?;
+        let builder = ParquetRecordBatchStreamBuilder::new(ParquetObjectReader::new(
+            object_store.clone(),
+            object_meta,
+        ))
+        .await?;
+
+        let columns = key_properties
+            .iter()
+            .filter_map(|key| builder.schema().index_of(key).ok())
+            .collect::<Vec<_>>();
+        let mask = ProjectionMask::roots(builder.parquet_schema(), columns);
+
+        let mut batches = builder.with_projection(mask).build()?;
+
+        let mut positions = Vec::new();
+        let mut offset = 0;
+
+        while let Some(batch) = batches.try_next().await? {
+            let rows = converter.convert_columns(&key_columns(&batch)?)?;
+            for (i, row) in rows.iter().enumerate() {
+                if keys.contains(row.as_ref()) {
+                    positions.push(offset + i as i64);
+                }
+            }
+            offset += batch.num_rows() as i64;
+        }
+
+        if positions.is_empty() {
+            continue;
+        }
+
+        let record_count = positions.len() as i64;
+
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(StringArray::from(vec![file_path.as_str(); positions.len()])),
+                Arc::new(Int64Array::from(positions)),
+            ],
+        )?;
+
+        let mut writer =
+            ArrowWriter::try_new(Vec::new(), schema.clone(), Some(properties.clone()))?;
+        writer.write(&batch)?;
+        let bytes = writer.into_inner()?;
+
+        let delete_file_path = metadata.location.to_string()
+            + "/data/"
+            + &Uuid::new_v4().to_string()
+            + "-deletes.parquet";
+        let file_size_in_bytes = bytes.len() as i64;
+
+        object_store
+            .put(&strip_prefix(&delete_file_path).into(), bytes.into())
+            .await?;
+
+        delete_files.push(
+            DataFile::builder()
+                .with_content(Content::PositionDeletes)
+                .with_file_path(delete_file_path)
+                .with_file_format(FileFormat::Parquet)
+                .with_partition(data_file.partition)
+                .with_record_count(record_count)
+                .with_file_size_in_bytes(file_size_in_bytes)
+                .with_column_sizes(None)
+                .with_value_counts(None)
+                .with_null_value_counts(None)
+                .with_nan_value_counts(None)
+                .with_distinct_counts(None)
+                .with_lower_bounds(None)
+                .with_upper_bounds(None)
+                .build()
+                .map_err(SpecError::from)?,
+        );
+    }
+
+    Ok(delete_files)
+}
+
+// Paths of the live data files of the current snapshot
+pub(crate) async fn data_file_paths(
+    table: &Table,
+    branch: Option<&str>,
+) -> Result<HashSet<String>, SingerIcebergError> {
+    Ok(data_files(table, &data_manifests(table, branch).await?)
+        .await?
+        .into_iter()
+        .map(|data_file| data_file.file_path)
+        .collect())
+}
+
+// Manifests of the data files of the current snapshot
+async fn data_manifests(
+    table: &Table,
+    branch: Option<&str>,
+) -> Result<Vec<ManifestListEntry>, SingerIcebergError> {
+    let metadata = table.metadata();
+
+    match metadata.current_snapshot(branch)? {
+        Some(snapshot) => Ok(snapshot
+            .manifests(metadata, table.object_store())
+            .await?
+            .filter(|manifest| {
+                manifest
+                    .as_ref()
+                    .map(|x| matches!(x.content, Content::Data))
+                    .unwrap_or(true)
+            })
+            .collect::<Result<Vec<_>, SpecError>>()?),
+        None => Ok(Vec::new()),
+    }
+}
+
+// Live data file of a manifest with the serialized bounds of its columns
+struct LiveDataFile {
+    file_path: String,
+    partition: Struct,
+    lower_bounds: HashMap<i32, Vec<u8>>,
+    upper_bounds: HashMap<i32, Vec<u8>>,
+}
+
+// Live data files of the manifests with their partition values. The manifests are read directly,
+// because iceberg-rust expects partition values that are named after their source columns.
+async fn data_files(
+    table: &Table,
+    manifests: &[ManifestListEntry],
+) -> Result<Vec<LiveDataFile>, SingerIcebergError> {
+    let metadata = table.metadata();
+    let object_store = table.object_store();
+    let schema = current_schema(metadata)?;
+
+    let mut data_files = Vec::new();
+
+    for manifest in manifests {
+        let spec = metadata
+            .partition_specs
+            .get(&manifest.partition_spec_id)
+            .ok_or(anyhow!(
+                "Partition spec {} doesn't exist.",
+                manifest.partition_spec_id
+            ))?;
+
+        let bytes = object_store
+            .get(&strip_prefix(&manifest.manifest_path).into())
+            .await?
+            .bytes()
+            .await?;
+
+        for entry in AvroReader::new(&bytes[..]).map_err(IcebergError::from)? {
+            let AvroValue::Record(entry) = entry.map_err(IcebergError::from)? else {
+                continue;
+            };
+            // Skip deleted entries (status 2) and delete files (content 1 and 2)
+            if matches!(avro_field(&entry, "status"), Some(AvroValue::Int(2))) {
+                continue;
+            }
+            let Some(AvroValue::Record(data_file)) = avro_field(&entry, "data_file") else {
+                continue;
+            };
+            if !matches!(
+                avro_field(data_file, "content"),
+                None | Some(AvroValue::Int(0))
+            ) {
+                continue;
+            }
+            let Some(AvroValue::String(file_path)) = avro_field(data_file, "file_path") else {
+                continue;
+            };
+            let partition = match avro_field(data_file, "partition") {
+                Some(partition) => avro_partition_struct(partition, spec, schema)?,
+                None => Struct::from_iter(iter::empty::<(String, Option<Value>)>()),
+            };
+            data_files.push(LiveDataFile {
+                file_path: file_path.clone(),
+                partition,
+                lower_bounds: avro_bounds(data_file, "lower_bounds"),
+                upper_bounds: avro_bounds(data_file, "upper_bounds"),
+            });
+        }
+    }
+
+    Ok(data_files)
+}
+
+// Bounds are stored as a list of key value records with the field id and the serialized value
+fn avro_bounds(data_file: &[(String, AvroValue)], name: &str) -> HashMap<i32, Vec<u8>> {
+    let bounds = match avro_field(data_file, name) {
+        Some(AvroValue::Union(_, bounds)) => bounds.as_ref(),
+        Some(bounds) => bounds,
+        None => return HashMap::new(),
+    };
+    let AvroValue::Array(entries) = bounds else {
+        return HashMap::new();
+    };
+    entries
+        .iter()
+        .filter_map(|entry| {
+            let AvroValue::Record(entry) = entry else {
+                return None;
+            };
+            match (avro_field(entry, "key"), avro_field(entry, "value")) {
+                (Some(AvroValue::Int(key)), Some(AvroValue::Bytes(value))) => {
+                    Some((*key, value.clone()))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+pub(crate) fn avro_field<'a>(
+    record: &'a [(String, AvroValue)],
+    name: &str,
+) -> Option<&'a AvroValue> {
+    record
+        .iter()
+        .find(|(field, _)| field == name)
+        .map(|(_, value)| value)
+}
+
+pub(crate) fn batch_to_json(batch: &RecordBatch) -> Result<Vec<JsonValue>, SingerIcebergError> {
+    let mut writer = ArrayWriter::new(Vec::new());
+    writer.write(batch)?;
+    writer.finish()?;
+    let rows: Vec<Map<String, JsonValue>> = serde_json::from_slice(&writer.into_inner())?;
+    Ok(rows.into_iter().map(JsonValue::Object).collect())
+}
@@ -15,6 +15,8 @@ pub enum SingerIcebergError {
     #[error(transparent)]
     IcebergSpec(#[from] iceberg_rust::spec::error::Error),
     #[error(transparent)]
+    Parquet(#[from] parquet::errors::ParquetError),
+    #[error(transparent)]
     FuturesChannel(#[from] futures::channel::mpsc::SendError),
     #[error(transparent)]
     ObjectStore(#[from] object_store::Error),
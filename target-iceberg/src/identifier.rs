@@ -0,0 +1,163 @@
+use anyhow::anyhow;
+use iceberg_rust::catalog::{identifier::Identifier, namespace::Namespace, Catalog};
+
+use crate::error::SingerIcebergError;
+
+// Resolve the identifier of a stream to the table in the catalog. Identifiers consist of the
+// catalog, one or more namespaces and the table, e.g. `bronze.sales.inventory.orders`. The catalog
+// has to match the name of the catalog if the plugin has one. Catalogs without a name also accept
+// identifiers of a namespace and a table like `inventory.orders`.
+pub(crate) fn resolve_identifier(
+    identifier: &str,
+    catalog_name: Option<&str>,
+) -> Result<Identifier, SingerIcebergError> {
+    let segments = identifier_segments(identifier)?;
+
+    if catalog_name.is_none() && segments.len() == 2 {
+        return Ok(Identifier::try_new(&segments)?);
+    }
+
+    let (catalog, names) = segments
+        .split_first()
+        .ok_or(SingerIcebergError::Anyhow(anyhow!(
+            "Identifier {} is empty.",
+            identifier
+        )))?;
+    if names.len() < 2 {
+        return Err(SingerIcebergError::Anyhow(anyhow!(
+            "Identifier {} has to consist of the catalog, at least one namespace and the table.",
+            identifier
+        )));
+    }
+    if let Some(catalog_name) = catalog_name {
+        if catalog != catalog_name {
+            return Err(SingerIcebergError::Anyhow(anyhow!(
+                "Catalog {} of identifier {} doesn't match the catalog {}.",
+                catalog,
+                identifier,
+                catalog_name
+            )));
+        }
+    }
+
+    Ok(Identifier::try_new(names)?)
+}
+
+// Split an identifier into its names. Names that contain dots are quoted with double quotes,
+// double quotes inside quoted names are escaped by doubling them.
+pub(crate) fn identifier_segments(identifier: &str) -> Result<Vec<String>, SingerIcebergError> {
+    let mut segments = Vec::new();
+    let mut chars = identifier.chars().peekable();
+
+    loop {
+        let mut segment = String::new();
+
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next() {
+                    Some('"') if chars.next_if_eq(&'"').is_some() => segment.push('"'),
+                    Some('"') => break,
+                    Some(c) => segment.push(c),
+                    None => {
+                        return Err(SingerIcebergError::Anyhow(anyhow!(
+                            "Identifier {} has an unterminated quote.",
+                            identifier
+                        )))
+                    }
+                }
+            }
+            if chars.peek().is_some_and(|c| *c != '.') {
+                return Err(SingerIcebergError::Anyhow(anyhow!(
+                    "Identifier {} has characters after a quoted name.",
+                    identifier
+                )));
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != '.') {
+                if c == '"' {
+                    return Err(SingerIcebergError::Anyhow(anyhow!(
+                        "Identifier {} has a quote inside a name, only whole names can be quoted.",
+                        identifier
+                    )));
+                }
+                segment.push(c);
+            }
+        }
+
+        if segment.is_empty() {
+            return Err(SingerIcebergError::Anyhow(anyhow!(
+                "Identifier {} has an empty name.",
+                identifier
+            )));
+        }
+        segments.push(segment);
+
+        if chars.next().is_none() {
+            return Ok(segments);
+        }
+    }
+}
+
+// Create the namespace and its parents if they don't exist
+pub(crate) async fn create_namespaces(
+    catalog: &dyn Catalog,
+    namespace: &Namespace,
+) -> Result<(), SingerIcebergError> {
+    for depth in 1..=namespace.len() {
+        let namespace = Namespace::try_new(&namespace[..depth])?;
+        if !catalog.namespace_exists(&namespace).await? {
+            catalog.create_namespace(&namespace, None).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identifier_segments() {
+        assert_eq!(
+            identifier_segments("public.inventory.orders").unwrap(),
+            vec!["public", "inventory", "orders"]
+        );
+        assert_eq!(
+            identifier_segments(r#"public."inventory.v2"."orders ""x""""#).unwrap(),
+            vec!["public", "inventory.v2", "orders \"x\""]
+        );
+        assert!(identifier_segments("public..orders").is_err());
+        assert!(identifier_segments("public.inventory.").is_err());
+        assert!(identifier_segments(r#"public."inventory.orders"#).is_err());
+        assert!(identifier_segments(r#"public."inventory"x.orders"#).is_err());
+        assert!(identifier_segments(r#"public.inv"entory.orders"#).is_err());
+    }
+
+    #[test]
+    fn test_resolve_identifier() {
+        let identifier =
+            resolve_identifier("bronze.sales.inventory.orders", Some("bronze")).unwrap();
+        assert_eq!(identifier.namespace().to_vec(), vec!["sales", "inventory"]);
+        assert_eq!(identifier.name(), "orders");
+
+        let identifier = resolve_identifier(r#"bronze.inventory."orders.v2""#, None).unwrap();
+        assert_eq!(identifier.namespace().to_vec(), vec!["inventory"]);
+        assert_eq!(identifier.name(), "orders.v2");
+
+        assert!(
+            resolve_identifier("bronze.inventory.orders", Some("public"))
+                .unwrap_err()
+                .to_string()
+                .contains("doesn't match the catalog public")
+        );
+        let identifier = resolve_identifier("inventory.orders", None).unwrap();
+        assert_eq!(identifier.namespace().to_vec(), vec!["inventory"]);
+        assert_eq!(identifier.name(), "orders");
+
+        assert!(resolve_identifier("inventory.orders", Some("bronze"))
+            .unwrap_err()
+            .to_string()
+            .contains("has to consist of the catalog"));
+        assert!(resolve_identifier("orders", None).is_err());
+    }
+}
@@ -1,40 +1,162 @@
 use std::{
     collections::HashMap,
-    io::BufRead,
-    ops::Deref,
-    sync::{atomic::AtomicI64, Arc},
+    io::{BufRead, Write},
+    mem,
+    pin::pin,
+    sync::{Arc, Mutex},
+    time::Duration,
 };
 
 use anyhow::anyhow;
-use arrow::{datatypes::Schema as ArrowSchema, error::ArrowError, json::ReaderBuilder};
+use arrow::{
+    datatypes::{DataType, Schema as ArrowSchema},
+    error::ArrowError,
+    json::ReaderBuilder,
+    record_batch::RecordBatch,
+};
+use chrono::{DateTime, Utc};
 use futures::{
     channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
-    lock::Mutex,
-    SinkExt, StreamExt, TryStreamExt,
-};
-use iceberg_rust::{
-    arrow::write::write_parquet_partitioned,
-    catalog::{identifier::Identifier, tabular::Tabular},
+    executor::block_on,
+    future::{pending, select, select_all, Either},
+    stream, try_join, FutureExt, SinkExt, StreamExt, TryStreamExt,
 };
-use singer::messages::Message;
+use iceberg_rust::{catalog::tabular::Tabular, spec::manifest::DataFile, table::Table};
+use parquet::arrow::{async_reader::ParquetObjectReader, ParquetRecordBatchStreamBuilder};
 
+use jsonschema::{Draft, JSONSchema};
 use serde_json::Value as JsonValue;
-use tracing::{debug, debug_span, Instrument};
+use tokio::{
+    sync::watch,
+    time::{sleep_until, Instant},
+};
+use tracing::{debug, debug_span, warn, Instrument};
+use uuid::Uuid;
 
-use crate::{error::SingerIcebergError, plugin::TargetPlugin, state::SINGER_BOOKMARK};
+use crate::{
+    audit::{write_audit, StreamRun},
+    batch::{
+        add_parquet_file, batch_file, check_parquet_schema, matching_field_ids, name_mapping,
+        project_batch, read_jsonl, NAME_MAPPING,
+    },
+    buffer::{Budget, Reservation},
+    commit::{
+        commit_properties, commit_schema, commit_snapshot, equality_delete_file, CommitRetry,
+        SnapshotProperties,
+    },
+    dead_letter::{dead_letter, DeadLetterQueue},
+    delete::{batch_to_json, is_deleted, position_delete_files, record_key},
+    error::SingerIcebergError,
+    identifier::resolve_identifier,
+    messages::{BatchFormat, Message},
+    metrics::{write_report, Metrics, DEFAULT_METRICS_INTERVAL},
+    plugin::{DeleteMode, DeleteType, OnInvalidRecord, StreamConfig, TargetPlugin, WriteMode},
+    schema::{current_schema, evolve_schema, iceberg_to_arrow, DEFAULT_MAX_NESTING_DEPTH},
+    sort::default_sort_order,
+    state::{
+        base_state, committed_bookmarks, committed_property, store_state, stored_state,
+        StateWriter, SINGER_BOOKMARK, SINGER_SNAPSHOT_ID,
+    },
+    write::{write_parquet_partitioned, DataFileWriter},
+};
 
 static ARROW_BATCH_SIZE: usize = 8192;
 static SINGER_VERSION: &str = "singer.version";
 
+// Snapshot summary entries that trace every snapshot back to the run and the source position
+pub(crate) static SINGER_RUN_ID: &str = "singer.run-id";
+static SINGER_TAP_STREAM_ID: &str = "singer.tap-stream-id";
+static SINGER_RECORD_COUNT: &str = "singer.record-count";
+static SINGER_REPLICATION_METHOD: &str = "singer.replication-method";
+
+// Message together with its share of the buffer budget
+type BufferedMessage = (Message, Arc<Reservation>);
+
+// What a stream waits for between messages
+enum Event {
+    Message(Option<BufferedMessage>),
+    Checkpoint,
+    Pressure,
+}
+
+// Ingest the singer messages of the input. If enabled, the committed state is written to the
+// output after every commit. Metrics are logged periodically and reported at the end of the run,
+// even if it failed.
 pub async fn ingest(
     plugin: Arc<dyn TargetPlugin>,
-    input: &mut dyn BufRead,
+    input: impl BufRead + Send + 'static,
+    output: impl Write + Send + 'static,
+) -> Result<(), SingerIcebergError> {
+    let interval = plugin
+        .metrics()
+        .interval
+        .unwrap_or(DEFAULT_METRICS_INTERVAL);
+    if interval == 0 {
+        return Err(SingerIcebergError::Anyhow(anyhow!(
+            "Interval of the metrics has to be at least one second."
+        )));
+    }
+
+    let metrics = Arc::new(Metrics::new(Uuid::new_v4()));
+
+    let budget = Budget::new(plugin.buffer());
+
+    let sync = pin!(sync_streams(
+        plugin.clone(),
+        input,
+        output,
+        metrics.clone(),
+        budget.clone()
+    ));
+    let periodic = pin!(metrics.log_periodically(Duration::from_secs(interval)));
+    let result = match select(sync, periodic).await {
+        Either::Left((result, _)) => result,
+        Either::Right((never, _)) => match never {},
+    };
+
+    metrics.log_counters();
+    let report = write_report(
+        plugin.metrics(),
+        &metrics.report(result.as_ref().err(), &budget),
+    );
+
+    result.and(report)
+}
+
+async fn sync_streams(
+    plugin: Arc<dyn TargetPlugin>,
+    input: impl BufRead + Send + 'static,
+    output: impl Write + Send + 'static,
+    metrics: Arc<Metrics>,
+    budget: Arc<Budget>,
 ) -> Result<(), SingerIcebergError> {
     let streams = plugin.streams();
-    // Create sender and reviever for every stream
+
+    // Rows of the audit table are only written after all streams were committed, so an invalid
+    // identifier has to fail before the sync
+    if let Some(audit) = plugin.audit() {
+        resolve_identifier(&audit.table, plugin.catalog_name())?;
+    }
+
+    let run_id = metrics.run_id();
+
+    let runs: Arc<Mutex<Vec<StreamRun>>> = Arc::new(Mutex::new(Vec::new()));
+
+    let state_writer = if plugin.emit_state() {
+        Some(Arc::new(StateWriter::new(
+            stored_state(plugin.as_ref()).await?,
+            committed_bookmarks(plugin.clone()).await?,
+            output,
+        )))
+    } else {
+        None
+    };
+    // Create sender and reviever for every stream. The channels are unbounded, the backpressure
+    // comes from the budget that is reserved for a message before it is sent and returned after it
+    // was processed.
     let (mut message_senders, message_recievers): (
-        HashMap<String, UnboundedSender<Message>>,
-        Vec<UnboundedReceiver<Message>>,
+        HashMap<String, UnboundedSender<BufferedMessage>>,
+        Vec<UnboundedReceiver<BufferedMessage>>,
     ) = streams
         .keys()
         .map(|stream| {
@@ -49,253 +171,1046 @@ pub async fn ingest(
         senders.send(reciever).await?;
     }
 
-    let state = Arc::new(Mutex::new(JsonValue::Null));
-
     // Process messages for every stream
     let handle = recievers
         .map(Ok::<_, SingerIcebergError>)
         .try_for_each_concurrent(None, |mut messages| {
             let plugin = plugin.clone();
-            let streams = streams;
-            let state = state.clone();
+            let state_writer = state_writer.clone();
+            let runs = runs.clone();
+            let metrics = metrics.clone();
+            let mut pressure = budget.pressure();
             async move {
                 let schema = match messages.next().await.ok_or(SingerIcebergError::Unknown)? {
-                    Message::Schema(schema) => Ok(schema),
+                    (Message::Schema(schema), _) => Ok(schema),
                     _ => Err(SingerIcebergError::NoSchema),
                 }?;
 
-                let active_version = Arc::new(AtomicI64::new(0));
-
                 let stream = schema.stream;
 
                 debug!("Syncing stream {}", &stream);
                 debug!("Schema: {}", serde_json::to_string(&schema.schema)?);
 
-                let identifier = &streams
-                    .get(&stream)
-                    .ok_or(SingerIcebergError::Anyhow(anyhow!(
-                        "Stream {} not present in config",
-                        &stream
-                    )))?
-                    .identifier;
+                let stream_config =
+                    streams
+                        .get(&stream)
+                        .ok_or(SingerIcebergError::Anyhow(anyhow!(
+                            "Stream {} not present in config",
+                            &stream
+                        )))?;
+
+                let identifier = &stream_config.identifier;
 
-                let compiled_schema =
-                    jsonschema::JSONSchema::compile(&serde_json::to_value(&schema.schema)?)
-                        .unwrap();
+                let mut compiled_schema = compile_schema(&schema.schema)?;
 
                 let catalog = plugin.catalog().await?;
 
-                let ident = Identifier::try_new(
-                    &identifier
-                        .split('.')
-                        .collect::<Vec<_>>()
-                        .into_iter()
-                        .rev()
-                        .take(2)
-                        .rev()
-                        .map(ToOwned::to_owned)
-                        .collect::<Vec<_>>(),
-                )?;
+                let ident = resolve_identifier(identifier, plugin.catalog_name())?;
 
                 let table = catalog.clone().load_tabular(&ident).await?;
 
-                let mut table = if let Tabular::Table(table) = table {
+                let table = if let Tabular::Table(table) = table {
                     table
                 } else {
                     return Err(SingerIcebergError::Unknown);
                 };
 
-                let previous_version = table.metadata().properties.get(SINGER_VERSION);
+                let mut sink = StreamSink::new(
+                    stream.clone(),
+                    table,
+                    stream_config,
+                    plugin.as_ref(),
+                    metrics,
+                    schema.key_properties,
+                    state_writer,
+                )
+                .await?;
 
-                let table_schema = table
-                    .metadata()
-                    .current_schema(plugin.branch().as_deref())?;
-
-                let table_arrow_schema: Arc<ArrowSchema> =
-                    Arc::new((table_schema.fields()).try_into()?);
-
-                let batches = messages
-                    .filter_map(|message| {
-                        let active_version = active_version.clone();
-                        async move {
-                            match message {
-                                Message::Record(record) => Some(record),
-                                Message::ActivateVersion(version) => {
-                                    active_version.store(
-                                        version.version,
-                                        std::sync::atomic::Ordering::Relaxed,
-                                    );
-                                    None
+                // Failed streams are audited as well
+                let result = async {
+                    sink.evolve(&schema.schema).await?;
+
+                    let checkpoints = plugin.commit();
+
+                    let mut last_commit = Instant::now();
+
+                    loop {
+                        let deadline = checkpoints
+                            .seconds
+                            .map(|seconds| last_commit + Duration::from_secs(seconds));
+                        let holding = !sink.reservations.is_empty();
+
+                        let message = match next_event(
+                            &mut messages,
+                            deadline,
+                            holding.then_some(&mut pressure),
+                        )
+                        .await
+                        {
+                            Event::Message(message) => message,
+                            Event::Checkpoint => {
+                                sink.commit().await?;
+                                last_commit = Instant::now();
+                                continue;
+                            }
+                            Event::Pressure => {
+                                sink.release().await?;
+                                continue;
+                            }
+                        };
+
+                        // The reservation is returned once the message is processed, the reservation
+                        // of a record once it was written
+                        let Some((message, reservation)) = message else {
+                            break;
+                        };
+
+                        match message {
+                            Message::Record(record) => {
+                                sink.receive(record.record, &compiled_schema).await?;
+                                sink.reservations.push(reservation);
+
+                                if checkpoints
+                                    .records
+                                    .is_some_and(|records| sink.len() >= records)
+                                {
+                                    sink.commit().await?;
+                                    last_commit = Instant::now();
                                 }
-                                _ => None,
                             }
-                        }
-                    })
-                    // Check if record conforms to schema
-                    .map(|message| {
-                        compiled_schema
-                            .validate(&message.record)
-                            .map_err(|mut err| {
-                                let error = format!("{}", err.next().unwrap());
-                                SingerIcebergError::Anyhow(anyhow::Error::msg(error))
-                            })?;
-                        let value = message.record;
-
-                        Ok::<_, SingerIcebergError>(value)
-                    })
-                    .try_chunks(ARROW_BATCH_SIZE)
-                    .map_err(|err| ArrowError::ExternalError(Box::new(err)))
-                    // Convert messages to arrow batches
-                    .and_then(|batches| {
-                        let table_arrow_schema = table_arrow_schema.clone();
-                        async move {
-                            let mut decoder =
-                                ReaderBuilder::new(table_arrow_schema.clone()).build_decoder()?;
-                            decoder.serialize(&batches)?;
-                            let record_batch = decoder.flush()?.ok_or(ArrowError::MemoryError(
-                                "Data of recordbatch is empty.".to_string(),
-                            ))?;
-                            Ok(record_batch)
-                        }
-                    });
+                            Message::Batch(batch) => {
+                                for url in &batch.manifest {
+                                    match batch.encoding.format {
+                                        BatchFormat::Jsonl => {
+                                            let (object_store, path) = batch_file(
+                                                url,
+                                                plugin.bucket(),
+                                                sink.table.object_store(),
+                                            )?;
+                                            for record in read_jsonl(
+                                                &object_store,
+                                                &path,
+                                                batch.encoding.compression,
+                                            )
+                                            .await?
+                                            {
+                                                sink.receive(record, &compiled_schema).await?;
+                                            }
+                                        }
+                                        BatchFormat::Parquet => {
+                                            sink.add_parquet(url, plugin.bucket()).await?
+                                        }
+                                    }
+                                }
 
-                let files = write_parquet_partitioned(
-                    table.metadata(),
-                    batches,
-                    table.object_store(),
-                    plugin.branch().as_deref(),
-                )
-                .await?;
+                                if checkpoints
+                                    .records
+                                    .is_some_and(|records| sink.len() >= records)
+                                {
+                                    sink.commit().await?;
+                                    last_commit = Instant::now();
+                                }
+                            }
+                            Message::ActivateVersion(version) => {
+                                sink.active_version = version.version;
+                            }
+                            Message::State(state) => {
+                                // The state covers all records that were received before it
+                                if let Some(bookmark) = stream_bookmark(&state.value, &stream)? {
+                                    debug!("State of stream {}: {}", &stream, &bookmark);
+                                    sink.bookmark = Some(bookmark);
+                                }
 
-                if !files.is_empty() {
-                    let stream_state = {
-                        let state = state.lock().await;
-                        let state = match state.deref() {
-                            JsonValue::Object(object) => match object.get("bookmarks") {
-                                Some(JsonValue::Object(object)) => Ok(object),
-                                _ => Err(SingerIcebergError::Anyhow(anyhow!(
-                                    "State value has to be an object."
-                                ))),
-                            },
-                            _ => Err(SingerIcebergError::Anyhow(anyhow!(
-                                "State value has to be an object."
-                            ))),
-                        }?;
-
-                        state.get(&stream).and_then(|x| match x {
-                            JsonValue::Object(object) => Some(serde_json::to_string(&object).ok()?),
-                            _ => None,
-                        })
-                    };
-
-                    let active_version = active_version.load(std::sync::atomic::Ordering::Relaxed);
-
-                    let transaction = if previous_version != Some(&active_version.to_string()) {
-                        table
-                            .new_transaction(plugin.branch().as_deref())
-                            .rewrite(files)
-                    } else {
-                        table
-                            .new_transaction(plugin.branch().as_deref())
-                            .append(files)
-                    };
-
-                    if let Some(state) = &stream_state {
-                        debug!("State of stream {}: {}", &stream, &state);
+                                if checkpoints.on_state {
+                                    sink.commit().await?;
+                                    last_commit = Instant::now();
+                                }
+                            }
+                            Message::Schema(schema) => {
+                                sink.evolve(&schema.schema).await?;
+                                compiled_schema = compile_schema(&schema.schema)?;
+                                sink.key_properties = schema.key_properties;
+                            }
+                        }
                     }
 
-                    let transaction = match stream_state {
-                        Some(x) => transaction
-                            .update_properties(vec![(SINGER_BOOKMARK.to_string(), x.to_string())]),
-                        None => transaction,
-                    };
-
-                    let transaction = if active_version != 0 {
-                        transaction.update_properties(vec![(
-                            SINGER_VERSION.to_string(),
-                            active_version.to_string(),
-                        )])
-                    } else {
-                        transaction
-                    };
-
-                    transaction.commit().await?;
+                    sink.commit().await
                 }
+                .await;
 
-                Ok(())
+                if sink.rejected > 0 {
+                    warn!(
+                        "Rejected {} invalid records of stream {}",
+                        sink.rejected, &stream
+                    );
+                }
+
+                runs.lock().unwrap().push(sink.run(result.as_ref().err()));
+
+                result
             }
             .instrument(debug_span!("sync_stream"))
         });
 
-    let mut versions: HashMap<String, i64> = HashMap::new();
+    // Read the input in a separate thread, so that streams are committed while reading. Reading
+    // waits while the budget of buffered messages is exhausted.
+    let (line_sender, mut lines) = unbounded();
+    let reader_budget = budget.clone();
+    tokio::task::spawn_blocking(move || {
+        for line in input.lines() {
+            let size = line.as_ref().map(|line| line.len()).unwrap_or_default();
+            let reservation = block_on(reader_budget.reserve(size));
+            if line_sender
+                .unbounded_send((line, Arc::new(reservation)))
+                .is_err()
+            {
+                break;
+            }
+        }
+    });
 
-    // Send messages to channel based on stream
-    for line in input.lines() {
-        let line = line?;
+    let reader_state_writer = state_writer.clone();
 
-        if line.starts_with('{') {
-            let message: Message = serde_json::from_str(&line)?;
-            match &message {
-                Message::Schema(schema) => {
-                    message_senders
-                        .get_mut(&schema.stream)
-                        .ok_or(SingerIcebergError::Anyhow(anyhow!(
-                            "Stream {} not found.",
-                            &schema.stream,
-                        )))?
-                        .send(message)
-                        .await?
-                }
-                Message::Record(record) => {
-                    message_senders
-                        .get_mut(&record.stream)
-                        .ok_or(SingerIcebergError::Anyhow(anyhow!(
-                            "Stream {} not found.",
-                            &record.stream,
-                        )))?
-                        .send(message)
-                        .await?
-                }
-                Message::ActivateVersion(record) => {
-                    if let Some(version) = versions.get(&record.stream) {
-                        if *version != record.version {
-                            let (s, r) = unbounded();
-                            message_senders
-                                .insert(record.stream.clone(), s)
-                                .ok_or(SingerIcebergError::Anyhow(anyhow!(
-                                    "Stream must be available."
-                                )))?
-                                .close_channel();
-                            senders.send(r).await?;
+    let read = async move {
+        let mut versions: HashMap<String, i64> = HashMap::new();
+
+        // Last STATE message of the input, which is stored after all streams were committed
+        let mut last_state = None;
+
+        // Latest schema message of every stream, required to start a stream after a version change
+        let mut schemas: HashMap<String, Message> = HashMap::new();
+
+        // Send messages to channel based on stream
+        while let Some((line, reservation)) = lines.next().await {
+            let line = line?;
+
+            if line.starts_with('{') {
+                let message: Message = serde_json::from_str(&line)?;
+                match &message {
+                    Message::Schema(schema) => {
+                        schemas.insert(schema.stream.clone(), message.clone());
+                        message_senders
+                            .get_mut(&schema.stream)
+                            .ok_or(SingerIcebergError::Anyhow(anyhow!(
+                                "Stream {} not found.",
+                                &schema.stream,
+                            )))?
+                            .send((message, reservation))
+                            .await?
+                    }
+                    Message::Record(record) => {
+                        message_senders
+                            .get_mut(&record.stream)
+                            .ok_or(SingerIcebergError::Anyhow(anyhow!(
+                                "Stream {} not found.",
+                                &record.stream,
+                            )))?
+                            .send((message, reservation))
+                            .await?
+                    }
+                    Message::Batch(batch) => {
+                        message_senders
+                            .get_mut(&batch.stream)
+                            .ok_or(SingerIcebergError::Anyhow(anyhow!(
+                                "Stream {} not found.",
+                                &batch.stream,
+                            )))?
+                            .send((message, reservation))
+                            .await?
+                    }
+                    Message::ActivateVersion(record) => {
+                        if let Some(version) = versions.get(&record.stream) {
+                            if *version != record.version {
+                                let (mut s, r) = unbounded();
+                                if let Some(schema) = schemas.get(&record.stream) {
+                                    s.send((schema.clone(), reservation.clone())).await?;
+                                }
+                                message_senders
+                                    .insert(record.stream.clone(), s)
+                                    .ok_or(SingerIcebergError::Anyhow(anyhow!(
+                                        "Stream must be available."
+                                    )))?
+                                    .close_channel();
+                                senders.send(r).await?;
+                                versions.insert(record.stream.clone(), record.version);
+                            }
+                        } else {
                             versions.insert(record.stream.clone(), record.version);
                         }
-                    } else {
-                        versions.insert(record.stream.clone(), record.version);
+                        message_senders
+                            .get_mut(&record.stream)
+                            .ok_or(SingerIcebergError::Anyhow(anyhow!(
+                                "Stream {} not found.",
+                                &record.stream,
+                            )))?
+                            .send((message, reservation))
+                            .await?
+                    }
+                    Message::State(state) => {
+                        let base = base_state(&state.value, streams)?;
+                        if let Some(state_writer) = &reader_state_writer {
+                            state_writer.update(base);
+                        }
+                        last_state = Some(state.value.clone());
+
+                        // Pass the state to every started stream, so that it is ordered with the records
+                        for stream in schemas.keys() {
+                            if let Some(sender) = message_senders.get_mut(stream) {
+                                sender.send((message.clone(), reservation.clone())).await?;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        senders.close_channel();
+
+        message_senders
+            .into_iter()
+            .for_each(|(_, sender)| sender.close_channel());
+
+        Ok::<_, SingerIcebergError>(last_state)
+    };
+
+    let result = try_join!(handle, read);
+
+    debug!(
+        "Peak of buffered messages: {} records, {} bytes",
+        budget.peak_records(),
+        budget.peak_memory()
+    );
+
+    // The audit of a failed run doesn't replace the error of the run
+    if let Some(audit) = plugin.audit() {
+        let runs = mem::take(&mut *runs.lock().unwrap());
+        match (
+            &result,
+            write_audit(audit, plugin.as_ref(), &run_id, &runs).await,
+        ) {
+            (Ok(_), Err(error)) => return Err(error),
+            (Err(_), Err(error)) => warn!("Failed to audit the failed run {}: {}", run_id, error),
+            _ => (),
+        }
+    }
+
+    let ((), last_state) = result?;
+
+    // The complete state is stored once the bookmarks it contains were committed
+    if let Some(state) = last_state {
+        store_state(plugin.as_ref(), &state).await?;
+    }
+
+    Ok(())
+}
+
+// Buffers the records of a stream and commits them to the table
+struct StreamSink<'a> {
+    stream: String,
+    table: Table,
+    config: &'a StreamConfig,
+    branch: Option<String>,
+    retry: CommitRetry,
+    metrics: Arc<Metrics>,
+    key_properties: Vec<String>,
+    arrow_schema: Arc<ArrowSchema>,
+    active_version: i64,
+    previous_version: Option<String>,
+    bookmark: Option<String>,
+    committed_bookmark: Option<String>,
+    records: Vec<JsonValue>,
+    // Open data files of streams that are written as the records arrive
+    writer: Option<DataFileWriter>,
+    // Parquet files of BATCH messages that are added as they are
+    data_files: Vec<DataFile>,
+    // Budget of the records that weren't written to the object store yet
+    reservations: Vec<Arc<Reservation>>,
+    len: usize,
+    dead_letter_queue: Option<DeadLetterQueue>,
+    dead_letters: Vec<JsonValue>,
+    rejected: usize,
+    state_writer: Option<Arc<StateWriter>>,
+    // Statistics of the run for the audit table
+    started_at: DateTime<Utc>,
+    initial_bookmark: Option<String>,
+    valid: usize,
+    bytes_written: i64,
+    snapshot_id: Option<i64>,
+}
+
+impl<'a> StreamSink<'a> {
+    async fn new(
+        stream: String,
+        table: Table,
+        config: &'a StreamConfig,
+        plugin: &dyn TargetPlugin,
+        metrics: Arc<Metrics>,
+        key_properties: Vec<String>,
+        state_writer: Option<Arc<StateWriter>>,
+    ) -> Result<Self, SingerIcebergError> {
+        let dead_letter_queue = match &config.on_invalid_record {
+            OnInvalidRecord::DeadLetter(dead_letter) => {
+                Some(DeadLetterQueue::new(dead_letter, plugin, &table).await?)
+            }
+            _ => None,
+        };
+        let retry = CommitRetry::new(plugin.commit(), &table.metadata().properties)?;
+        // After a rollback the version and bookmark of the current snapshot apply
+        let branch = plugin.branch().as_deref();
+        let previous_version = committed_property(table.metadata(), branch, SINGER_VERSION)?;
+        let committed_bookmark = committed_property(table.metadata(), branch, SINGER_BOOKMARK)?;
+
+        let arrow_schema: Arc<ArrowSchema> = Arc::new(iceberg_to_arrow(
+            current_schema(table.metadata())?.fields(),
+        )?);
+
+        Ok(Self {
+            stream,
+            table,
+            config,
+            branch: plugin.branch().clone(),
+            retry,
+            metrics,
+            key_properties,
+            arrow_schema,
+            active_version: 0,
+            previous_version,
+            bookmark: committed_bookmark.clone(),
+            committed_bookmark: committed_bookmark.clone(),
+            records: Vec::new(),
+            writer: None,
+            data_files: Vec::new(),
+            reservations: Vec::new(),
+            len: 0,
+            dead_letter_queue,
+            dead_letters: Vec::new(),
+            rejected: 0,
+            state_writer,
+            started_at: Utc::now(),
+            initial_bookmark: committed_bookmark,
+            valid: 0,
+            bytes_written: 0,
+            snapshot_id: None,
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    // Records of streams without row level deletes are written to data files right away
+    fn streaming(&self) -> bool {
+        self.config.write_mode == WriteMode::Append && self.config.delete_mode.is_none()
+    }
+
+    async fn push(&mut self, mut record: JsonValue) -> Result<(), SingerIcebergError> {
+        if let JsonValue::Object(object) = &mut record {
+            for field in self.arrow_schema.fields() {
+                if let Some(value) = object.get_mut(field.name()) {
+                    prepare_value(value, field.data_type())?;
+                }
+            }
+        }
+        self.records.push(record);
+        self.len += 1;
+        self.valid += 1;
+        self.metrics.valid_records(&self.stream, 1);
+        if self.streaming() && self.records.len() >= ARROW_BATCH_SIZE {
+            self.write_records().await?;
+        }
+        Ok(())
+    }
+
+    // Write the buffered records of a streaming sink to its open data files
+    async fn write_records(&mut self) -> Result<(), SingerIcebergError> {
+        if self.records.is_empty() {
+            return Ok(());
+        }
+        let batch = records_to_batch(&mem::take(&mut self.records), self.arrow_schema.clone())?;
+        self.write_batch(batch).await
+    }
+
+    // Write a batch with the schema of the table to the open data files
+    async fn write_batch(&mut self, batch: RecordBatch) -> Result<(), SingerIcebergError> {
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => self.writer.insert(DataFileWriter::new(
+                self.table.metadata(),
+                self.table.object_store(),
+                &self.config.parquet,
+            )?),
+        };
+        writer.write(batch).await
+    }
+
+    // Check if record conforms to schema
+    async fn receive(
+        &mut self,
+        record: JsonValue,
+        compiled_schema: &JSONSchema,
+    ) -> Result<(), SingerIcebergError> {
+        let errors = compiled_schema
+            .validate(&record)
+            .err()
+            .map(|errors| errors.map(|err| err.to_string()).collect::<Vec<_>>());
+
+        match errors {
+            Some(errors) => self.reject(record, errors),
+            None => self.push(record).await,
+        }
+    }
+
+    // Parquet files are checked against the table schema instead of the JSON schema. They are
+    // added as data files if their records don't have to be partitioned, sorted or deduplicated.
+    // Otherwise their batches are brought into the table schema and written like other records,
+    // only upsert and delete streams deduplicate them as JSON records.
+    async fn add_parquet(
+        &mut self,
+        url: &str,
+        bucket: Option<&str>,
+    ) -> Result<(), SingerIcebergError> {
+        let (object_store, path) = batch_file(url, bucket, self.table.object_store())?;
+        let object_meta = object_store.head(&path).await?;
+        let builder = ParquetRecordBatchStreamBuilder::new(ParquetObjectReader::new(
+            object_store.clone(),
+            object_meta,
+        ))
+        .await?;
+
+        check_parquet_schema(builder.schema(), &self.arrow_schema, url)?;
+
+        let unpartitioned = self
+            .table
+            .metadata()
+            .default_partition_spec()?
+            .fields()
+            .is_empty();
+        let unsorted = default_sort_order(self.table.metadata()).is_none();
+
+        if self.streaming()
+            && unpartitioned
+            && unsorted
+            && matching_field_ids(builder.schema(), &self.arrow_schema)
+        {
+            let data_file =
+                add_parquet_file(&self.table, &object_store, &path, builder.metadata()).await?;
+            self.len += *data_file.record_count() as usize;
+            self.valid += *data_file.record_count() as usize;
+            self.metrics
+                .valid_records(&self.stream, *data_file.record_count() as u64);
+            self.data_files.push(data_file);
+        } else {
+            let mut batches = builder.build()?;
+            while let Some(batch) = batches.try_next().await? {
+                let batch = project_batch(&batch, &self.arrow_schema)?;
+                if self.streaming() {
+                    let rows = batch.num_rows();
+                    self.write_batch(batch).await?;
+                    self.len += rows;
+                    self.valid += rows;
+                    self.metrics.valid_records(&self.stream, rows as u64);
+                } else {
+                    for record in batch_to_json(&batch)? {
+                        self.push(record).await?;
                     }
-                    message_senders
-                        .get_mut(&record.stream)
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Apply the policy for invalid records to a record that failed the schema validation
+    fn reject(&mut self, record: JsonValue, errors: Vec<String>) -> Result<(), SingerIcebergError> {
+        self.rejected += 1;
+        self.metrics.rejected_record(&self.stream);
+        match &self.config.on_invalid_record {
+            OnInvalidRecord::Fail => Err(SingerIcebergError::Anyhow(anyhow::Error::msg(
+                errors.into_iter().next().unwrap_or_default(),
+            ))),
+            OnInvalidRecord::Skip => Ok(()),
+            OnInvalidRecord::DeadLetter(_) => {
+                self.dead_letters
+                    .push(dead_letter(&self.stream, record, errors));
+                Ok(())
+            }
+        }
+    }
+
+    // Update the table schema to the schema of a SCHEMA message
+    async fn evolve(&mut self, schema: &JsonValue) -> Result<(), SingerIcebergError> {
+        let max_depth = self
+            .config
+            .max_nesting_depth
+            .unwrap_or(DEFAULT_MAX_NESTING_DEPTH);
+        if let Some((schema, last_column_id)) =
+            evolve_schema(self.table.metadata(), schema, max_depth)?
+        {
+            // Buffered records are written with the old schema
+            self.commit().await?;
+
+            debug!(
+                "Update schema of stream {}: {}",
+                &self.stream,
+                serde_json::to_string(&schema)?
+            );
+
+            commit_schema(&mut self.table, schema, last_column_id, &self.retry).await?;
+
+            self.arrow_schema = Arc::new(iceberg_to_arrow(
+                current_schema(self.table.metadata())?.fields(),
+            )?);
+        }
+        Ok(())
+    }
+
+    // Return the budget of the buffered records while the reader waits for it. Streaming sinks
+    // write the records to the object store, other sinks commit them.
+    async fn release(&mut self) -> Result<(), SingerIcebergError> {
+        if !self.streaming() {
+            return self.commit().await;
+        }
+        self.write_records().await?;
+        if let Some(writer) = &mut self.writer {
+            writer.flush().await?;
+        }
+        self.reservations.clear();
+        Ok(())
+    }
+
+    async fn commit(&mut self) -> Result<(), SingerIcebergError> {
+        // The budget of the records is returned once they are committed
+        let _reservations = mem::take(&mut self.reservations);
+
+        // Rejected records are written before the bookmark that covers them is committed
+        if let Some(queue) = &mut self.dead_letter_queue {
+            queue.write(mem::take(&mut self.dead_letters)).await?;
+        }
+
+        if self.streaming() {
+            self.write_records().await?;
+        }
+
+        let records = mem::take(&mut self.records);
+        let writer = self.writer.take();
+        let data_files = mem::take(&mut self.data_files);
+        let record_count = mem::take(&mut self.len);
+
+        // Added parquet files without field ids are read by the names of their columns
+        let name_mapping = if data_files.is_empty() {
+            None
+        } else {
+            Some(serde_json::to_string(&name_mapping(
+                current_schema(self.table.metadata())?.fields(),
+            ))?)
+        };
+
+        let branch = self.branch.as_deref();
+
+        // The first commit of a new version replaces the table. Streams without ACTIVATE_VERSION
+        // messages always append.
+        let rewrite = self.active_version != 0
+            && self.previous_version != Some(self.active_version.to_string());
+
+        let (files, delete_files) = if self.streaming() {
+            let mut files = match writer {
+                Some(writer) => writer.close().await?,
+                None => Vec::new(),
+            };
+
+            files.extend(data_files);
+
+            (files, Vec::new())
+        } else {
+            let table_schema = current_schema(self.table.metadata())?;
+
+            let equality_ids = self
+                .key_properties
+                .iter()
+                .map(|key| {
+                    Ok(table_schema
+                        .fields()
+                        .get_name(key)
                         .ok_or(SingerIcebergError::Anyhow(anyhow!(
-                            "Stream {} not found.",
-                            &record.stream,
+                            "Key property {} of stream {} doesn't exist in table schema.",
+                            key,
+                            &self.stream
                         )))?
-                        .send(message)
+                        .id)
+                })
+                .collect::<Result<Vec<_>, SingerIcebergError>>()?;
+
+            if equality_ids.is_empty() {
+                return Err(SingerIcebergError::Anyhow(anyhow!(
+                    "Stream {} has no key properties to delete rows by.",
+                    &self.stream
+                )));
+            }
+
+            let (records, deletes) = split_deletes(
+                records,
+                &self.key_properties,
+                &self.config.write_mode,
+                self.config.delete_mode.as_ref(),
+            );
+
+            let files = write_parquet_partitioned(
+                self.table.metadata(),
+                stream::iter(
+                    records
+                        .chunks(ARROW_BATCH_SIZE)
+                        .map(|batch| records_to_batch(batch, self.arrow_schema.clone())),
+                ),
+                self.table.object_store(),
+                &self.config.parquet,
+            )
+            .await?;
+
+            // Deletes are only needed if the new records are appended
+            let delete_files = if !rewrite && !deletes.is_empty() {
+                match self.config.delete_type {
+                    DeleteType::Equality => write_parquet_partitioned(
+                        self.table.metadata(),
+                        stream::iter(
+                            deletes
+                                .chunks(ARROW_BATCH_SIZE)
+                                .map(|batch| records_to_batch(batch, self.arrow_schema.clone())),
+                        ),
+                        self.table.object_store(),
+                        &self.config.parquet,
+                    )
+                    .await?
+                    .into_iter()
+                    .map(|file| equality_delete_file(file, &equality_ids))
+                    .collect::<Result<_, _>>()?,
+                    DeleteType::Position => {
+                        position_delete_files(
+                            &self.table,
+                            branch,
+                            &records_to_batch(&deletes, self.arrow_schema.clone())?,
+                            &self.key_properties,
+                            &self.config.parquet,
+                        )
                         .await?
+                    }
                 }
-                Message::State(new_state) => {
-                    let mut state = state.lock().await;
-                    *state = new_state.value.clone()
-                }
+            } else {
+                Vec::new()
+            };
+
+            (files, delete_files)
+        };
+
+        let bookmark = self
+            .bookmark
+            .clone()
+            .filter(|bookmark| Some(bookmark) != self.committed_bookmark.as_ref());
+
+        if files.is_empty() && delete_files.is_empty() {
+            // Advance the bookmark of an appended table even if no new records arrived. The bookmark
+            // belongs to the current snapshot of the branch.
+            if let (false, Some(bookmark)) = (rewrite, bookmark) {
+                let snapshot_id = self
+                    .table
+                    .metadata()
+                    .current_snapshot(branch)?
+                    .map(|snapshot| {
+                        (
+                            SINGER_SNAPSHOT_ID.to_string(),
+                            snapshot.snapshot_id().to_string(),
+                        )
+                    });
+                commit_properties(
+                    &mut self.table,
+                    branch,
+                    [(SINGER_BOOKMARK.to_string(), bookmark.clone())]
+                        .into_iter()
+                        .chain(snapshot_id)
+                        .collect(),
+                    &self.retry,
+                )
+                .await?;
+                self.committed_bookmark = Some(bookmark);
+                self.write_state()?;
             }
+            return Ok(());
         }
-    }
 
-    senders.close_channel();
+        let properties = bookmark
+            .clone()
+            .map(|x| (SINGER_BOOKMARK.to_string(), x))
+            .into_iter()
+            .chain(
+                (self.active_version != 0)
+                    .then(|| (SINGER_VERSION.to_string(), self.active_version.to_string())),
+            )
+            .chain(name_mapping.map(|x| (NAME_MAPPING.to_string(), x)))
+            .collect::<Vec<_>>();
 
-    message_senders
+        // The summary records the source bookmark of every snapshot, even if it didn't change
+        let summary = [
+            (SINGER_TAP_STREAM_ID, Some(self.stream.clone())),
+            (SINGER_RUN_ID, Some(self.metrics.run_id().to_string())),
+            (SINGER_RECORD_COUNT, Some(record_count.to_string())),
+            (
+                SINGER_VERSION,
+                (self.active_version != 0).then(|| self.active_version.to_string()),
+            ),
+            (
+                SINGER_REPLICATION_METHOD,
+                Some(self.config.replication_method.to_string()),
+            ),
+            (SINGER_BOOKMARK, self.bookmark.clone()),
+        ]
         .into_iter()
-        .for_each(|(_, sender)| sender.close_channel());
+        .filter_map(|(key, value)| Some((key.to_owned(), value?)))
+        .collect::<Vec<_>>();
+
+        let bytes_written = files
+            .iter()
+            .chain(delete_files.iter())
+            .map(|file| *file.file_size_in_bytes())
+            .sum::<i64>();
+        let file_count = files.len() + delete_files.len();
+
+        let commit_start = Instant::now();
+
+        commit_snapshot(
+            &mut self.table,
+            branch,
+            files,
+            delete_files,
+            rewrite,
+            SnapshotProperties {
+                table: properties,
+                summary,
+            },
+            &self.retry,
+        )
+        .await?;
+
+        self.metrics.commit(
+            &self.stream,
+            file_count,
+            bytes_written,
+            commit_start.elapsed(),
+        );
+
+        self.bytes_written += bytes_written;
+        self.snapshot_id = self
+            .table
+            .metadata()
+            .current_snapshot(branch)?
+            .map(|snapshot| *snapshot.snapshot_id());
+
+        if self.active_version != 0 {
+            self.previous_version = Some(self.active_version.to_string());
+        }
+        if let Some(bookmark) = bookmark {
+            self.committed_bookmark = Some(bookmark);
+        }
+
+        self.write_state()
+    }
+
+    fn run(&self, error: Option<&SingerIcebergError>) -> StreamRun {
+        StreamRun {
+            stream: self.stream.clone(),
+            identifier: self.config.identifier.clone(),
+            snapshot_id: self.snapshot_id,
+            records_valid: self.valid,
+            records_rejected: self.rejected,
+            bytes_written: self.bytes_written,
+            started_at: self.started_at,
+            ended_at: Utc::now(),
+            bookmark_before: self.initial_bookmark.clone(),
+            bookmark_after: self.committed_bookmark.clone(),
+            activate_version: (self.active_version != 0).then_some(self.active_version),
+            error: error.map(ToString::to_string),
+        }
+    }
+
+    fn write_state(&self) -> Result<(), SingerIcebergError> {
+        match &self.state_writer {
+            Some(state_writer) => {
+                state_writer.write(&self.stream, self.committed_bookmark.as_deref())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+// Wait for the next message of a stream. Queued messages come first, the checkpoint deadline and
+// the pressure on the buffer budget only interrupt a stream that waits for messages.
+async fn next_event(
+    messages: &mut UnboundedReceiver<BufferedMessage>,
+    deadline: Option<Instant>,
+    pressure: Option<&mut watch::Receiver<bool>>,
+) -> Event {
+    if let Some(message) = messages.next().now_or_never() {
+        return Event::Message(message);
+    }
+    let message = messages.next().map(Event::Message).boxed();
+    let checkpoint = async move {
+        match deadline {
+            Some(deadline) => sleep_until(deadline).await,
+            None => pending().await,
+        }
+        Event::Checkpoint
+    }
+    .boxed();
+    let pressure = async move {
+        let reader_waits = match pressure {
+            Some(pressure) => pressure.wait_for(|x| *x).await.is_ok(),
+            None => false,
+        };
+        if !reader_waits {
+            pending::<()>().await;
+        }
+        Event::Pressure
+    }
+    .boxed();
+    select_all([message, checkpoint, pressure]).await.0
+}
 
-    handle.await?;
+// Extract the bookmark of a stream from a STATE message, which was validated when it was read
+fn stream_bookmark(state: &JsonValue, stream: &str) -> Result<Option<String>, SingerIcebergError> {
+    match state
+        .get("bookmarks")
+        .and_then(|bookmarks| bookmarks.get(stream))
+    {
+        Some(JsonValue::Object(object)) => Ok(Some(serde_json::to_string(object)?)),
+        _ => Ok(None),
+    }
+}
+
+// Singer schemas follow JSON schema draft 4, for example with boolean `exclusiveMaximum`s
+fn compile_schema(schema: &JsonValue) -> Result<JSONSchema, SingerIcebergError> {
+    JSONSchema::options()
+        .with_draft(Draft::Draft4)
+        .compile(&without_multiple_of(schema))
+        .map_err(|err| SingerIcebergError::Anyhow(anyhow!("Invalid schema: {}", err)))
+}
+
+// The floating point check of `multipleOf` rejects decimals like 19.99 for 0.01. The scale of
+// decimal columns is enforced when the records are converted.
+fn without_multiple_of(schema: &JsonValue) -> JsonValue {
+    match schema {
+        JsonValue::Object(object) => JsonValue::Object(
+            object
+                .iter()
+                .filter(|(key, value)| *key != "multipleOf" || !value.is_number())
+                .map(|(key, value)| (key.clone(), without_multiple_of(value)))
+                .collect(),
+        ),
+        JsonValue::Array(values) => {
+            JsonValue::Array(values.iter().map(without_multiple_of).collect())
+        }
+        value => value.clone(),
+    }
+}
 
+// Serialize values of string columns that aren't strings, for example JSON objects and arrays,
+// and numbers of decimal columns
+fn prepare_value(value: &mut JsonValue, data_type: &DataType) -> Result<(), SingerIcebergError> {
+    match (value, data_type) {
+        (JsonValue::Object(object), DataType::Struct(fields)) => {
+            for field in fields {
+                if let Some(value) = object.get_mut(field.name()) {
+                    prepare_value(value, field.data_type())?;
+                }
+            }
+        }
+        (JsonValue::Array(values), DataType::List(field)) => {
+            for value in values {
+                prepare_value(value, field.data_type())?;
+            }
+        }
+        (value, DataType::Utf8) if !value.is_string() && !value.is_null() => {
+            *value = JsonValue::String(serde_json::to_string(value)?);
+        }
+        // Decimals are only parsed from the text of a number
+        (value @ JsonValue::Number(_), DataType::Decimal128(_, _)) => {
+            *value = JsonValue::String(value.to_string());
+        }
+        _ => (),
+    }
     Ok(())
 }
+
+pub(crate) fn records_to_batch(
+    records: &[JsonValue],
+    schema: Arc<ArrowSchema>,
+) -> Result<RecordBatch, ArrowError> {
+    let mut decoder = ReaderBuilder::new(schema).build_decoder()?;
+    decoder.serialize(records)?;
+    decoder.flush()?.ok_or(ArrowError::MemoryError(
+        "Data of recordbatch is empty.".to_string(),
+    ))
+}
+
+// Keep only the last record for every key, in the position of its first occurrence
+fn deduplicate(records: Vec<JsonValue>, key_properties: &[String]) -> Vec<JsonValue> {
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    let mut deduplicated = Vec::with_capacity(records.len());
+    for record in records {
+        let key = record_key(&record, key_properties);
+        match positions.get(&key) {
+            Some(position) => deduplicated[*position] = record,
+            None => {
+                positions.insert(key, deduplicated.len());
+                deduplicated.push(record);
+            }
+        }
+    }
+    deduplicated
+}
+
+// Split the records into the rows to write and the rows whose keys have to be deleted from
+// earlier snapshots
+fn split_deletes(
+    records: Vec<JsonValue>,
+    key_properties: &[String],
+    write_mode: &WriteMode,
+    delete_mode: Option<&DeleteMode>,
+) -> (Vec<JsonValue>, Vec<JsonValue>) {
+    let keep = |record: &JsonValue| delete_mode != Some(&DeleteMode::Hard) || !is_deleted(record);
+    match write_mode {
+        WriteMode::Upsert => {
+            let records = deduplicate(records, key_properties);
+            let deletes = records.clone();
+            (records.into_iter().filter(keep).collect(), deletes)
+        }
+        WriteMode::Append => {
+            if delete_mode.is_none() {
+                return (records, Vec::new());
+            }
+            let keys = records
+                .iter()
+                .map(|record| record_key(record, key_properties))
+                .collect::<Vec<_>>();
+            // Position of the last tombstone for every key
+            let tombstones: HashMap<&String, usize> = keys
+                .iter()
+                .zip(records.iter())
+                .enumerate()
+                .filter(|(_, (_, record))| is_deleted(record))
+                .map(|(i, (key, _))| (key, i))
+                .collect();
+            let mut positions = tombstones.values().copied().collect::<Vec<_>>();
+            positions.sort_unstable();
+            let deletes = positions.iter().map(|i| records[*i].clone()).collect();
+            // Rows before the last tombstone of their key are deleted within this commit
+            let records = records
+                .into_iter()
+                .zip(keys.iter())
+                .enumerate()
+                .filter(|(i, (record, key))| match tombstones.get(key) {
+                    Some(position) => i > position || (i == position && keep(record)),
+                    None => true,
+                })
+                .map(|(_, (record, _))| record)
+                .collect();
+            (records, deletes)
+        }
+    }
+}
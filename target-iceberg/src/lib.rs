@@ -1,6 +1,18 @@
+pub mod audit;
+pub mod batch;
+pub mod buffer;
 pub mod catalog;
+pub mod commit;
+pub mod dead_letter;
+pub mod delete;
 pub mod error;
+pub mod identifier;
 pub mod ingest;
+pub mod messages;
+pub mod metrics;
+pub mod partition;
 pub mod plugin;
 pub mod schema;
+pub mod sort;
 pub mod state;
+pub mod write;
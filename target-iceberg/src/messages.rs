@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use singer::messages::{ActivateVersion, Record, State};
+
+// Singer messages that keep the original JSON schema of SCHEMA messages. The schema type of the
+// singer crate drops `$ref`, `definitions` and nullable objects and arrays.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Message {
+    Schema(Schema),
+    Record(Record),
+    State(State),
+    ActivateVersion(ActivateVersion),
+    Batch(Batch),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schema {
+    pub stream: String,
+    pub schema: JsonValue,
+    pub key_properties: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bookmark_properties: Option<Vec<String>>,
+}
+
+// Records of a stream that were written to files instead of the output
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Batch {
+    pub stream: String,
+    pub encoding: BatchEncoding,
+    pub manifest: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchEncoding {
+    pub format: BatchFormat,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compression: Option<BatchCompression>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchFormat {
+    Jsonl,
+    Parquet,
+}
+
+// Compression of JSONL files. Parquet files are compressed internally.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchCompression {
+    None,
+    Gzip,
+}
@@ -0,0 +1,185 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::Infallible,
+    fs,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use serde_json::{json, Value as JsonValue};
+use tokio::time::sleep;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::{buffer::Budget, error::SingerIcebergError, plugin::MetricsConfig};
+
+pub(crate) static DEFAULT_METRICS_INTERVAL: u64 = 60;
+
+// What a stream processed so far
+#[derive(Debug, Default, Clone, Serialize)]
+struct StreamCounters {
+    records_read: u64,
+    records_valid: u64,
+    records_rejected: u64,
+    files_written: u64,
+    bytes_written: u64,
+    commits: u64,
+    commit_seconds: f64,
+    commit_seconds_max: f64,
+}
+
+// Metrics of a run of the target. Counters are logged as METRIC lines in the format of the Singer
+// SDK with the increase since the last line, commits are logged as timers.
+pub(crate) struct Metrics {
+    run_id: Uuid,
+    started_at: DateTime<Utc>,
+    start: Instant,
+    // Current and last logged counters of every stream
+    streams: Mutex<HashMap<String, (StreamCounters, StreamCounters)>>,
+}
+
+impl Metrics {
+    pub(crate) fn new(run_id: Uuid) -> Self {
+        Self {
+            run_id,
+            started_at: Utc::now(),
+            start: Instant::now(),
+            streams: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn run_id(&self) -> Uuid {
+        self.run_id
+    }
+
+    fn update(&self, stream: &str, update: impl FnOnce(&mut StreamCounters)) {
+        let mut streams = self.streams.lock().unwrap();
+        match streams.get_mut(stream) {
+            Some((counters, _)) => update(counters),
+            None => {
+                let mut counters = StreamCounters::default();
+                update(&mut counters);
+                streams.insert(stream.to_owned(), (counters, StreamCounters::default()));
+            }
+        }
+    }
+
+    pub(crate) fn valid_records(&self, stream: &str, records: u64) {
+        self.update(stream, |counters| {
+            counters.records_read += records;
+            counters.records_valid += records;
+        });
+    }
+
+    pub(crate) fn rejected_record(&self, stream: &str) {
+        self.update(stream, |counters| {
+            counters.records_read += 1;
+            counters.records_rejected += 1;
+        });
+    }
+
+    pub(crate) fn commit(&self, stream: &str, files: usize, bytes: i64, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        self.update(stream, |counters| {
+            counters.files_written += files as u64;
+            counters.bytes_written += bytes as u64;
+            counters.commits += 1;
+            counters.commit_seconds += seconds;
+            counters.commit_seconds_max = counters.commit_seconds_max.max(seconds);
+        });
+        log_metric("timer", "commit_duration", json!(seconds), stream);
+    }
+
+    // Log the increase of the counters since the last METRIC lines
+    pub(crate) fn log_counters(&self) {
+        let mut streams = self.streams.lock().unwrap();
+        for (stream, (counters, logged)) in streams.iter_mut() {
+            for (metric, value, previous) in [
+                ("record_count", counters.records_valid, logged.records_valid),
+                (
+                    "invalid_record_count",
+                    counters.records_rejected,
+                    logged.records_rejected,
+                ),
+                ("file_count", counters.files_written, logged.files_written),
+                (
+                    "bytes_written",
+                    counters.bytes_written,
+                    logged.bytes_written,
+                ),
+            ] {
+                if value > previous {
+                    log_metric("counter", metric, json!(value - previous), stream);
+                }
+            }
+            *logged = counters.clone();
+        }
+    }
+
+    pub(crate) async fn log_periodically(&self, interval: Duration) -> Infallible {
+        loop {
+            sleep(interval).await;
+            self.log_counters();
+        }
+    }
+
+    // Summary of the run, which failed if an error is given
+    pub(crate) fn report(&self, error: Option<&SingerIcebergError>, budget: &Budget) -> JsonValue {
+        let duration = self.start.elapsed().as_secs_f64();
+
+        let streams = self
+            .streams
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(stream, (counters, _))| (stream.clone(), counters.clone()))
+            .collect::<BTreeMap<_, _>>();
+
+        let records_read = streams
+            .values()
+            .map(|counters| counters.records_read)
+            .sum::<u64>();
+
+        json!({
+            "run_id": self.run_id.to_string(),
+            "status": if error.is_none() { "succeeded" } else { "failed" },
+            "error": error.map(ToString::to_string),
+            "started_at": self.started_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+            "ended_at": Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+            "duration_seconds": duration,
+            "records_read": records_read,
+            "records_per_second": if duration > 0.0 { records_read as f64 / duration } else { 0.0 },
+            "buffer": {
+                "peak_records": budget.peak_records(),
+                "peak_memory": budget.peak_memory(),
+            },
+            "streams": streams,
+        })
+    }
+}
+
+// The report is written to the configured file or otherwise to stderr
+pub(crate) fn write_report(
+    config: &MetricsConfig,
+    report: &JsonValue,
+) -> Result<(), SingerIcebergError> {
+    match &config.report {
+        Some(path) => fs::write(path, serde_json::to_vec(report)?)?,
+        None => eprintln!("{}", report),
+    }
+    Ok(())
+}
+
+fn log_metric(kind: &str, metric: &str, value: JsonValue, stream: &str) {
+    info!(
+        "METRIC: {}",
+        json!({
+            "type": kind,
+            "metric": metric,
+            "value": value,
+            "tags": { "stream": stream },
+        })
+    );
+}
@@ -0,0 +1,623 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::anyhow;
+use apache_avro::types::Value as AvroValue;
+use arrow::{
+    array::{Array, ArrowPrimitiveType, AsArray, UInt32Array},
+    compute::take_record_batch,
+    datatypes::{
+        Date32Type, Float32Type, Float64Type, Int32Type, Int64Type, Time64MicrosecondType,
+        TimestampMicrosecondType,
+    },
+    record_batch::RecordBatch,
+};
+use chrono::{DateTime, Datelike, NaiveDate};
+use iceberg_rust::spec::{
+    error::Error as SpecError,
+    manifest_list::FieldSummary,
+    partition::{PartitionField, PartitionSpec, Transform},
+    schema::Schema,
+    types::{PrimitiveType, StructType, Type},
+    values::{Struct, Value},
+};
+use serde_json::json;
+
+use crate::{error::SingerIcebergError, plugin::PartitionFieldConfig};
+
+static FIRST_PARTITION_FIELD_ID: i32 = 1000;
+static MICROS_PER_HOUR: i64 = 3_600_000_000;
+static MICROS_PER_DAY: i64 = 86_400_000_000;
+
+// Partition values and the rows of the partition
+type Partition = (Vec<Option<Value>>, Vec<RecordBatch>);
+
+// Build the partition spec of a new table. Fails for unknown columns, transforms that can't be
+// applied to their column and partition field names that are used twice.
+pub(crate) fn partition_spec(
+    config: &[PartitionFieldConfig],
+    fields: &StructType,
+) -> Result<PartitionSpec, SingerIcebergError> {
+    let mut names = HashSet::new();
+
+    let partition_fields = config
+        .iter()
+        .enumerate()
+        .map(|(i, partition_field)| {
+            let column = &partition_field.column;
+            let transform = &partition_field.transform;
+
+            let field = fields.get_name(column).ok_or(anyhow!(
+                "Partition column {} doesn't exist in the schema.",
+                column
+            ))?;
+            if result_type(transform, &field.field_type).is_none() {
+                return Err(SingerIcebergError::Anyhow(anyhow!(
+                    "Partition transform {} can't be applied to column {} of type {}.",
+                    transform_name(transform),
+                    column,
+                    field.field_type
+                )));
+            }
+
+            let name = partition_field
+                .name
+                .clone()
+                .unwrap_or_else(|| default_name(column, transform));
+            // Partition values are stored in avro records named after the partition fields
+            if name.is_empty()
+                || name.starts_with(|x: char| x.is_ascii_digit())
+                || !name.chars().all(|x| x.is_ascii_alphanumeric() || x == '_')
+            {
+                return Err(SingerIcebergError::Anyhow(anyhow!(
+                    "Partition field name {} may only contain letters, digits and underscores. Choose another name with \"name\".",
+                    name
+                )));
+            }
+            if !names.insert(name.clone()) {
+                return Err(SingerIcebergError::Anyhow(anyhow!(
+                    "Partition field name {} is used more than once.",
+                    name
+                )));
+            }
+            if fields.get_name(&name).is_some_and(|other| {
+                other.id != field.id || !matches!(transform, Transform::Identity)
+            }) {
+                return Err(SingerIcebergError::Anyhow(anyhow!(
+                    "Partition field name {} conflicts with a column of the schema.",
+                    name
+                )));
+            }
+
+            Ok(PartitionField::new(
+                field.id,
+                FIRST_PARTITION_FIELD_ID + i as i32,
+                &name,
+                transform.clone(),
+            ))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(PartitionSpec::builder()
+        .with_fields(partition_fields)
+        .build()
+        .map_err(SpecError::from)?)
+}
+
+fn default_name(column: &str, transform: &Transform) -> String {
+    match transform {
+        Transform::Identity => column.to_owned(),
+        Transform::Bucket(_) => column.to_owned() + "_bucket",
+        Transform::Truncate(_) => column.to_owned() + "_trunc",
+        Transform::Year => column.to_owned() + "_year",
+        Transform::Month => column.to_owned() + "_month",
+        Transform::Day => column.to_owned() + "_day",
+        Transform::Hour => column.to_owned() + "_hour",
+        Transform::Void => column.to_owned() + "_null",
+    }
+}
+
+fn transform_name(transform: &Transform) -> String {
+    serde_json::to_value(transform)
+        .ok()
+        .and_then(|x| x.as_str().map(ToOwned::to_owned))
+        .unwrap_or_default()
+}
+
+// Type of the partition values. Returns None for transforms that aren't supported for the type.
+fn result_type(transform: &Transform, source: &Type) -> Option<Type> {
+    use PrimitiveType::*;
+    let Type::Primitive(primitive) = source else {
+        return None;
+    };
+    match (transform, primitive) {
+        (_, Decimal { .. } | Uuid | Fixed(_) | Binary) => None,
+        (Transform::Identity | Transform::Void, _) => Some(source.clone()),
+        (
+            Transform::Bucket(_),
+            Int | Long | Date | Time | Timestamp | Timestamptz | PrimitiveType::String,
+        ) => Some(Type::Primitive(Int)),
+        (Transform::Truncate(_), Int | Long | PrimitiveType::String) => Some(source.clone()),
+        (Transform::Year | Transform::Month | Transform::Day, Date | Timestamp | Timestamptz) => {
+            Some(Type::Primitive(Int))
+        }
+        (Transform::Hour, Timestamp | Timestamptz) => Some(Type::Primitive(Int)),
+        _ => None,
+    }
+}
+
+fn partition_type(field: &PartitionField, schema: &Schema) -> Result<Type, SingerIcebergError> {
+    let source = schema
+        .fields()
+        .get(*field.source_id() as usize)
+        .ok_or(anyhow!(
+            "Source column of partition field {} doesn't exist.",
+            field.name()
+        ))?;
+    result_type(field.transform(), &source.field_type).ok_or(SingerIcebergError::Anyhow(anyhow!(
+        "Partition transform {} can't be applied to column {} of type {}.",
+        transform_name(field.transform()),
+        source.name,
+        source.field_type
+    )))
+}
+
+// Split the rows of the record batches by their partition values. The partitions are returned in
+// the order of their first row.
+pub(crate) fn partition_batches(
+    batches: Vec<RecordBatch>,
+    spec: &PartitionSpec,
+    schema: &Schema,
+) -> Result<Vec<Partition>, SingerIcebergError> {
+    if spec.fields().is_empty() {
+        return Ok(vec![(Vec::new(), batches)]);
+    }
+
+    let mut partitions: Vec<Partition> = Vec::new();
+    let mut lookup: HashMap<Vec<Option<Value>>, usize> = HashMap::new();
+
+    for batch in batches {
+        let columns = spec
+            .fields()
+            .iter()
+            .map(|field| partition_column(&batch, field, schema))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut rows: Vec<(Vec<Option<Value>>, Vec<u32>)> = Vec::new();
+        let mut batch_lookup: HashMap<Vec<Option<Value>>, usize> = HashMap::new();
+
+        for row in 0..batch.num_rows() {
+            let values = columns
+                .iter()
+                .map(|column| column[row].clone())
+                .collect::<Vec<_>>();
+            match batch_lookup.get(&values) {
+                Some(index) => rows[*index].1.push(row as u32),
+                None => {
+                    batch_lookup.insert(values.clone(), rows.len());
+                    rows.push((values, vec![row as u32]));
+                }
+            }
+        }
+
+        for (values, indices) in rows {
+            let batch = take_record_batch(&batch, &UInt32Array::from(indices))?;
+            match lookup.get(&values) {
+                Some(index) => partitions[*index].1.push(batch),
+                None => {
+                    lookup.insert(values.clone(), partitions.len());
+                    partitions.push((values, vec![batch]));
+                }
+            }
+        }
+    }
+
+    Ok(partitions)
+}
+
+// The partition values of a single partition field for all rows of the batch
+fn partition_column(
+    batch: &RecordBatch,
+    field: &PartitionField,
+    schema: &Schema,
+) -> Result<Vec<Option<Value>>, SingerIcebergError> {
+    let source = schema
+        .fields()
+        .get(*field.source_id() as usize)
+        .ok_or(anyhow!(
+            "Source column of partition field {} doesn't exist.",
+            field.name()
+        ))?;
+    let Some(array) = batch.column_by_name(&source.name) else {
+        return Ok(vec![None; batch.num_rows()]);
+    };
+    let Type::Primitive(primitive) = &source.field_type else {
+        return Err(SingerIcebergError::Anyhow(anyhow!(
+            "Partition column {} has to be a primitive column.",
+            source.name
+        )));
+    };
+
+    let values = primitive_column(array, primitive).ok_or(anyhow!(
+        "Partition column {} has the unsupported type {}.",
+        source.name,
+        array.data_type()
+    ))?;
+
+    values
+        .into_iter()
+        .map(|value| match value {
+            Some(value) => transform(&value, field.transform()),
+            None => Ok(None),
+        })
+        .collect()
+}
+
+// The values of an arrow array of a primitive column. Returns None for unsupported types.
+pub(crate) fn primitive_column(
+    array: &dyn Array,
+    primitive: &PrimitiveType,
+) -> Option<Vec<Option<Value>>> {
+    match primitive {
+        PrimitiveType::Boolean => array
+            .as_boolean_opt()
+            .map(|array| array.iter().map(|x| x.map(Value::Boolean)).collect()),
+        PrimitiveType::Int => primitive_values::<Int32Type>(array, Value::Int),
+        PrimitiveType::Long => primitive_values::<Int64Type>(array, Value::LongInt),
+        PrimitiveType::Float => primitive_values::<Float32Type>(array, |x| Value::Float(x.into())),
+        PrimitiveType::Double => {
+            primitive_values::<Float64Type>(array, |x| Value::Double(x.into()))
+        }
+        PrimitiveType::Date => primitive_values::<Date32Type>(array, Value::Date),
+        PrimitiveType::Time => primitive_values::<Time64MicrosecondType>(array, Value::Time),
+        PrimitiveType::Timestamp => {
+            primitive_values::<TimestampMicrosecondType>(array, Value::Timestamp)
+        }
+        PrimitiveType::Timestamptz => {
+            primitive_values::<TimestampMicrosecondType>(array, Value::TimestampTZ)
+        }
+        PrimitiveType::String => array.as_string_opt::<i32>().map(|array| {
+            array
+                .iter()
+                .map(|x| x.map(|x| Value::String(x.to_owned())))
+                .collect()
+        }),
+        _ => None,
+    }
+}
+
+fn primitive_values<T: ArrowPrimitiveType>(
+    array: &dyn Array,
+    value: impl Fn(T::Native) -> Value,
+) -> Option<Vec<Option<Value>>> {
+    array
+        .as_primitive_opt::<T>()
+        .map(|array| array.iter().map(|x| x.map(&value)).collect())
+}
+
+// Apply a partition transform as defined by the iceberg spec
+pub(crate) fn transform(
+    value: &Value,
+    transform: &Transform,
+) -> Result<Option<Value>, SingerIcebergError> {
+    let result = match (transform, value) {
+        (Transform::Identity, value) => Some(value.clone()),
+        (Transform::Void, _) => None,
+        (Transform::Bucket(n), value) => {
+            let hash = bucket_hash(value)?;
+            Some(Value::Int((hash & i32::MAX) % *n as i32))
+        }
+        (Transform::Truncate(width), Value::Int(x)) => {
+            Some(Value::Int(x - x.rem_euclid(*width as i32)))
+        }
+        (Transform::Truncate(width), Value::LongInt(x)) => {
+            Some(Value::LongInt(x - x.rem_euclid(*width as i64)))
+        }
+        (Transform::Truncate(width), Value::String(x)) => {
+            Some(Value::String(x.chars().take(*width as usize).collect()))
+        }
+        (Transform::Year, Value::Date(days)) => Some(Value::Int(date(*days)?.year() - 1970)),
+        (Transform::Month, Value::Date(days)) => Some(Value::Int(months(date(*days)?))),
+        (Transform::Day, Value::Date(days)) => Some(Value::Int(*days)),
+        (Transform::Year, Value::Timestamp(micros) | Value::TimestampTZ(micros)) => {
+            Some(Value::Int(timestamp(*micros)?.year() - 1970))
+        }
+        (Transform::Month, Value::Timestamp(micros) | Value::TimestampTZ(micros)) => {
+            Some(Value::Int(months(timestamp(*micros)?)))
+        }
+        (Transform::Day, Value::Timestamp(micros) | Value::TimestampTZ(micros)) => {
+            Some(Value::Int(micros.div_euclid(MICROS_PER_DAY) as i32))
+        }
+        (Transform::Hour, Value::Timestamp(micros) | Value::TimestampTZ(micros)) => {
+            Some(Value::Int(micros.div_euclid(MICROS_PER_HOUR) as i32))
+        }
+        (transform, value) => {
+            return Err(SingerIcebergError::Anyhow(anyhow!(
+                "Partition transform {} can't be applied to value {}.",
+                transform_name(transform),
+                value
+            )))
+        }
+    };
+    Ok(result)
+}
+
+// 32-bit murmur3 hash of the binary representation of the value
+fn bucket_hash(value: &Value) -> Result<i32, SingerIcebergError> {
+    let bytes = match value {
+        Value::Int(x) | Value::Date(x) => (*x as i64).to_le_bytes().to_vec(),
+        Value::LongInt(x) | Value::Time(x) | Value::Timestamp(x) | Value::TimestampTZ(x) => {
+            x.to_le_bytes().to_vec()
+        }
+        Value::String(x) => x.as_bytes().to_vec(),
+        value => {
+            return Err(SingerIcebergError::Anyhow(anyhow!(
+                "Value {} can't be bucketed.",
+                value
+            )))
+        }
+    };
+    Ok(murmur3::murmur3_32(&mut bytes.as_slice(), 0)? as i32)
+}
+
+fn date(days: i32) -> Result<NaiveDate, SingerIcebergError> {
+    NaiveDate::from_ymd_opt(1970, 1, 1)
+        .and_then(|epoch| epoch.checked_add_signed(chrono::Duration::days(days as i64)))
+        .ok_or(SingerIcebergError::Anyhow(anyhow!(
+            "Date {} is out of range.",
+            days
+        )))
+}
+
+fn timestamp(micros: i64) -> Result<NaiveDate, SingerIcebergError> {
+    DateTime::from_timestamp_micros(micros)
+        .map(|x| x.date_naive())
+        .ok_or(SingerIcebergError::Anyhow(anyhow!(
+            "Timestamp {} is out of range.",
+            micros
+        )))
+}
+
+fn months(date: NaiveDate) -> i32 {
+    (date.year() - 1970) * 12 + date.month0() as i32
+}
+
+// Data files of a partition are stored in a directory for every partition field
+pub(crate) fn partition_path(spec: &PartitionSpec, values: &[Option<Value>]) -> String {
+    spec.fields()
+        .iter()
+        .zip(values)
+        .map(|(field, value)| {
+            let value = value
+                .as_ref()
+                .map(ToString::to_string)
+                .unwrap_or("null".to_owned());
+            field.name().to_owned() + "=" + &escape(&value) + "/"
+        })
+        .collect()
+}
+
+fn escape(value: &str) -> String {
+    value
+        .bytes()
+        .map(|x| {
+            if x.is_ascii_alphanumeric() || matches!(x, b'-' | b'_' | b'.') {
+                (x as char).to_string()
+            } else {
+                format!("%{:02X}", x)
+            }
+        })
+        .collect()
+}
+
+// Partition values of a data file keyed by the names of the partition fields
+pub(crate) fn partition_struct(spec: &PartitionSpec, values: Vec<Option<Value>>) -> Struct {
+    spec.fields()
+        .iter()
+        .map(|field| field.name().clone())
+        .zip(values)
+        .collect()
+}
+
+// Summaries of the partition values of a manifest for the manifest list. iceberg-rust reads the
+// bounds with the type of the source column, so they are left out for transforms that change the
+// type and for columns whose type can be widened by a schema change.
+pub(crate) fn partition_summaries<'a>(
+    spec: &PartitionSpec,
+    schema: &Schema,
+    partitions: impl Iterator<Item = &'a Struct> + Clone,
+) -> Result<Vec<FieldSummary>, SingerIcebergError> {
+    spec.fields()
+        .iter()
+        .map(|field| {
+            let source = schema
+                .fields()
+                .get(*field.source_id() as usize)
+                .map(|x| &x.field_type);
+            let with_bounds = match source {
+                Some(Type::Primitive(
+                    PrimitiveType::Int | PrimitiveType::Float | PrimitiveType::Decimal { .. },
+                )) => false,
+                Some(source) => partition_type(field, schema)? == *source,
+                None => false,
+            };
+            let values = partitions
+                .clone()
+                .map(|partition| partition.get(field.name()).cloned().flatten());
+            let mut summary = FieldSummary {
+                contains_null: false,
+                contains_nan: Some(false),
+                lower_bound: None,
+                upper_bound: None,
+            };
+            for value in values {
+                let value = match value {
+                    None => {
+                        summary.contains_null = true;
+                        continue;
+                    }
+                    Some(Value::Float(x)) if x.is_nan() => {
+                        summary.contains_nan = Some(true);
+                        continue;
+                    }
+                    Some(Value::Double(x)) if x.is_nan() => {
+                        summary.contains_nan = Some(true);
+                        continue;
+                    }
+                    Some(value) => value,
+                };
+                if !with_bounds {
+                    continue;
+                }
+                if summary.lower_bound.as_ref().is_none_or(|x| value < *x) {
+                    summary.lower_bound = Some(value.clone());
+                }
+                if summary.upper_bound.as_ref().is_none_or(|x| value > *x) {
+                    summary.upper_bound = Some(value);
+                }
+            }
+            Ok(summary)
+        })
+        .collect()
+}
+
+// Avro schema of the partition values in manifest files. Unlike the schema of iceberg-rust the
+// fields are named after the partition fields and have the type of the transformed values.
+pub(crate) fn partition_value_schema(
+    spec: &PartitionSpec,
+    schema: &Schema,
+) -> Result<String, SingerIcebergError> {
+    let fields = spec
+        .fields()
+        .iter()
+        .map(|field| {
+            let avro_type = match partition_type(field, schema)? {
+                Type::Primitive(PrimitiveType::Boolean) => "boolean",
+                Type::Primitive(PrimitiveType::Int | PrimitiveType::Date) => "int",
+                Type::Primitive(
+                    PrimitiveType::Long
+                    | PrimitiveType::Time
+                    | PrimitiveType::Timestamp
+                    | PrimitiveType::Timestamptz,
+                ) => "long",
+                Type::Primitive(PrimitiveType::Float) => "float",
+                Type::Primitive(PrimitiveType::Double) => "double",
+                _ => "string",
+            };
+            Ok(json!({
+                "name": field.name(),
+                "type": ["null", avro_type],
+                "field-id": field.field_id(),
+                "default": null
+            }))
+        })
+        .collect::<Result<Vec<_>, SingerIcebergError>>()?;
+
+    Ok(json!({ "type": "record", "name": "r102", "fields": fields }).to_string())
+}
+
+// Read the partition values of a manifest entry that was written with `partition_value_schema`
+pub(crate) fn avro_partition_struct(
+    value: &AvroValue,
+    spec: &PartitionSpec,
+    schema: &Schema,
+) -> Result<Struct, SingerIcebergError> {
+    let AvroValue::Record(record) = value else {
+        return Err(SingerIcebergError::Anyhow(anyhow!(
+            "The partition of a manifest entry has to be a record."
+        )));
+    };
+    spec.fields()
+        .iter()
+        .map(|field| {
+            let result_type = partition_type(field, schema)?;
+            let value = record
+                .iter()
+                .find(|(name, _)| name == field.name())
+                .map(|(_, value)| avro_value(value, &result_type))
+                .transpose()?
+                .flatten();
+            Ok((field.name().clone(), value))
+        })
+        .collect()
+}
+
+fn avro_value(value: &AvroValue, result_type: &Type) -> Result<Option<Value>, SingerIcebergError> {
+    let value = match value {
+        AvroValue::Null => return Ok(None),
+        AvroValue::Union(_, value) => return avro_value(value, result_type),
+        AvroValue::Boolean(x) => Value::Boolean(*x),
+        AvroValue::Int(x) | AvroValue::Date(x) => Value::Int(*x),
+        AvroValue::Long(x)
+        | AvroValue::TimeMicros(x)
+        | AvroValue::TimestampMicros(x)
+        | AvroValue::LocalTimestampMicros(x) => Value::LongInt(*x),
+        AvroValue::Float(x) => Value::Float((*x).into()),
+        AvroValue::Double(x) => Value::Double((*x).into()),
+        AvroValue::String(x) => Value::String(x.clone()),
+        value => {
+            return Err(SingerIcebergError::Anyhow(anyhow!(
+                "Unsupported partition value {:?}.",
+                value
+            )))
+        }
+    };
+    Ok(Some(value.cast(result_type)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use iceberg_rust::spec::{partition::Transform, values::Value};
+
+    use super::transform;
+
+    // Test vectors of the iceberg spec
+    #[test]
+    fn test_bucket() {
+        let bucket =
+            |value: Value| match transform(&value, &Transform::Bucket(i32::MAX as u32)).unwrap() {
+                Some(Value::Int(x)) => x,
+                x => panic!("Unexpected bucket {:?}", x),
+            };
+        assert_eq!(bucket(Value::Int(34)), 2017239379);
+        assert_eq!(bucket(Value::LongInt(34)), 2017239379);
+        assert_eq!(bucket(Value::Date(17486)), 1494153226);
+        assert_eq!(bucket(Value::Time(81068000000)), 1484720659);
+        assert_eq!(bucket(Value::Timestamp(1510871468000000)), 99539207);
+        assert_eq!(bucket(Value::TimestampTZ(1510871468000000)), 99539207);
+        assert_eq!(bucket(Value::String("iceberg".to_owned())), 1210000089);
+    }
+
+    #[test]
+    fn test_truncate() {
+        let truncate = |value: Value, width: u32| {
+            transform(&value, &Transform::Truncate(width))
+                .unwrap()
+                .unwrap()
+        };
+        assert_eq!(truncate(Value::Int(1), 10), Value::Int(0));
+        assert_eq!(truncate(Value::Int(-1), 10), Value::Int(-10));
+        assert_eq!(truncate(Value::LongInt(1234), 100), Value::LongInt(1200));
+        assert_eq!(
+            truncate(Value::String("iceberg".to_owned()), 3),
+            Value::String("ice".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_time_transforms() {
+        let apply =
+            |value: Value, transform_: Transform| transform(&value, &transform_).unwrap().unwrap();
+        // 2017-11-16 and 2017-11-16T22:31:08
+        assert_eq!(apply(Value::Date(17486), Transform::Year), Value::Int(47));
+        assert_eq!(apply(Value::Date(17486), Transform::Month), Value::Int(574));
+        assert_eq!(apply(Value::Date(17486), Transform::Day), Value::Int(17486));
+        let timestamp = Value::TimestampTZ(1510871468000000);
+        assert_eq!(apply(timestamp.clone(), Transform::Year), Value::Int(47));
+        assert_eq!(apply(timestamp.clone(), Transform::Month), Value::Int(574));
+        assert_eq!(apply(timestamp.clone(), Transform::Day), Value::Int(17486));
+        assert_eq!(apply(timestamp, Transform::Hour), Value::Int(419686));
+        // Values before 1970 round down
+        assert_eq!(apply(Value::Timestamp(-1), Transform::Day), Value::Int(-1));
+        assert_eq!(apply(Value::Date(-1), Transform::Month), Value::Int(-1));
+    }
+}
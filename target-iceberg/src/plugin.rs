@@ -1,8 +1,25 @@
-use std::{collections::HashMap, fmt::Display, sync::Arc};
+use std::{collections::HashMap, fmt::Display, fs, sync::Arc};
 
+use anyhow::anyhow;
 use async_trait::async_trait;
-use iceberg_rust::catalog::Catalog;
-use serde::{Deserialize, Serialize};
+use dashtool_common::S3Config;
+use iceberg_rust::{
+    catalog::Catalog,
+    spec::{
+        partition::Transform,
+        sort::{NullOrder, SortDirection},
+        table_metadata::FormatVersion,
+    },
+};
+use object_store::{
+    aws::AmazonS3Builder,
+    azure::{AzureConfigKey, MicrosoftAzureBuilder},
+    gcp::GoogleCloudStorageBuilder,
+    local::LocalFileSystem,
+    memory::InMemory,
+    ObjectStore,
+};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
 
 use crate::error::SingerIcebergError;
 
@@ -10,15 +27,138 @@ use crate::error::SingerIcebergError;
 pub trait TargetPlugin {
     async fn catalog(&self) -> Result<Arc<dyn Catalog>, SingerIcebergError>;
     fn bucket(&self) -> Option<&str>;
+    // Object store of the bucket that was built from the configured object store
+    fn object_store(&self) -> Arc<dyn ObjectStore>;
     fn streams(&self) -> &HashMap<String, StreamConfig>;
     fn branch(&self) -> &Option<String>;
+    // The other settings default to those of a config without them
+    fn commit(&self) -> &CommitConfig {
+        &DEFAULT_COMMIT
+    }
+    fn buffer(&self) -> &BufferConfig {
+        &DEFAULT_BUFFER
+    }
+    fn emit_state(&self) -> bool {
+        default_emit_state()
+    }
+    // The first name of every identifier has to match the name of the catalog if it has one
+    fn catalog_name(&self) -> Option<&str> {
+        None
+    }
+    // Whether namespaces have to be created before their tables
+    fn explicit_namespaces(&self) -> bool {
+        false
+    }
+    // Whether the catalog assigns the id of the sort order of new tables, like REST catalogs do
+    fn assigns_sort_order_ids(&self) -> bool {
+        false
+    }
+    fn audit(&self) -> Option<&AuditConfig> {
+        None
+    }
+    fn state_location(&self) -> Option<&str> {
+        None
+    }
+    fn metrics(&self) -> &MetricsConfig {
+        &DEFAULT_METRICS
+    }
 }
 
+static DEFAULT_COMMIT: CommitConfig = CommitConfig {
+    records: None,
+    seconds: None,
+    on_state: false,
+    retries: None,
+    retry_min_wait: None,
+    retry_max_wait: None,
+};
+
+static DEFAULT_BUFFER: BufferConfig = BufferConfig {
+    records: None,
+    memory: None,
+};
+
+static DEFAULT_METRICS: MetricsConfig = MetricsConfig {
+    interval: None,
+    report: None,
+};
+
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct BaseConfig {
     pub streams: HashMap<String, StreamConfig>,
     pub bucket: Option<String>,
     pub branch: Option<String>,
+    #[serde(default)]
+    pub commit: CommitConfig,
+    #[serde(default)]
+    pub buffer: BufferConfig,
+    #[serde(default = "default_emit_state")]
+    pub emit_state: bool,
+    pub audit: Option<AuditConfig>,
+    // Path in the bucket of the object that keeps the last STATE message, defaults to
+    // "singer/state.json"
+    pub state_location: Option<String>,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+}
+
+fn default_emit_state() -> bool {
+    true
+}
+
+// Checkpoints at which the buffered records of a stream are committed. Without any checkpoint
+// every stream is committed once at the end of the input. Commits that conflict with a concurrent
+// change of the table are retried on top of the changed table.
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct CommitConfig {
+    // Commit after the given number of records
+    pub records: Option<usize>,
+    // Commit after the given number of seconds
+    pub seconds: Option<u64>,
+    // Commit whenever a STATE message arrives
+    #[serde(default)]
+    pub on_state: bool,
+    // Retries of a commit that conflicts with a concurrent change of the table, defaults to the
+    // `commit.retry.num-retries` table property or 4
+    pub retries: Option<u32>,
+    // Wait in milliseconds before the first retry, doubled with every further retry. Defaults to
+    // the `commit.retry.min-wait-ms` table property or 100.
+    pub retry_min_wait: Option<u64>,
+    // Maximum wait in milliseconds between retries, defaults to the `commit.retry.max-wait-ms`
+    // table property or 60000
+    pub retry_max_wait: Option<u64>,
+}
+
+// Iceberg table that records every run of the target. Each run appends a row per stream with the
+// record counts, the committed snapshot and the bookmarks of the run.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditConfig {
+    // Identifier of the audit table, which is created if it doesn't exist
+    pub table: String,
+}
+
+// METRIC log lines of the sync and the JSON report at the end of every run
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct MetricsConfig {
+    // Seconds between the METRIC log lines of the counters, defaults to 60
+    pub interval: Option<u64>,
+    // File the report is written to, defaults to stderr
+    pub report: Option<String>,
+}
+
+// Limits for the messages that were read from the input but not yet processed by their stream.
+// Reading the input pauses while a limit is reached.
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct BufferConfig {
+    // Maximum number of buffered messages
+    pub records: Option<usize>,
+    // Maximum size of the buffered messages in bytes of JSON text, defaults to 64 MiB
+    pub memory: Option<usize>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -27,8 +167,242 @@ pub struct StreamConfig {
     pub identifier: String,
     #[serde(default)]
     pub replication_method: Replication,
+    #[serde(default)]
+    pub write_mode: WriteMode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delete_mode: Option<DeleteMode>,
+    #[serde(default)]
+    pub delete_type: DeleteType,
+    // Partition fields of new tables in the order of the partition spec
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub partition_by: Option<Vec<PartitionFieldConfig>>,
+    // Sort order of new tables. The rows of every data file are sorted by it.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub partition_by: Option<HashMap<String, String>>,
+    pub sort_by: Option<Vec<SortFieldConfig>>,
+    #[serde(default)]
+    pub parquet: ParquetConfig,
+    // Properties of the table. They are set on new tables and updated on existing tables when
+    // the streams are selected.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub properties: HashMap<String, String>,
+    // Iceberg format version of new tables, defaults to 2
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format_version: Option<TableFormatVersion>,
+    // Location of new tables, either a full URL or a path in the bucket. Defaults to the
+    // identifier as path in the bucket.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    // Objects and arrays nested deeper than this are stored as JSON strings
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_nesting_depth: Option<usize>,
+    #[serde(default)]
+    pub on_invalid_record: OnInvalidRecord,
+}
+
+// Partition field that is derived from a top level column of the stream
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PartitionFieldConfig {
+    pub column: String,
+    // Iceberg transform of the column: "identity", "year", "month", "day", "hour", "bucket[N]",
+    // "truncate[W]" or "void"
+    #[serde(deserialize_with = "deserialize_transform")]
+    pub transform: Transform,
+    // Name of the partition field, defaults to the column for identity transforms and to the
+    // column with a suffix for the transform otherwise, e.g. "id_bucket"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+// Settings of the parquet files that are written for a stream. Unset settings fall back to the
+// `write.parquet.*` and `write.target-file-size-bytes` properties of the table.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ParquetConfig {
+    // Compression codec, defaults to zstd
+    pub compression: Option<ParquetCompression>,
+    // Level of the zstd, gzip or brotli compression, defaults to 1 for zstd
+    pub compression_level: Option<i32>,
+    // Row groups are flushed once they reach this size in bytes
+    pub row_group_size: Option<usize>,
+    // Maximum size of a data page in bytes
+    pub page_size: Option<usize>,
+    // Maximum size of the dictionary page of a column in bytes
+    pub dictionary_size: Option<usize>,
+    // Dictionary encoding of the columns, enabled by default
+    pub dictionary_enabled: Option<bool>,
+    // A new data file is started once a file reaches this size in bytes, defaults to 512 MB
+    pub target_file_size: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ParquetCompression {
+    Zstd,
+    Snappy,
+    Gzip,
+    Lz4,
+    Brotli,
+    Uncompressed,
+}
+
+// Iceberg format version 1 or 2. Version 1 tables don't support delete files.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "u8", into = "u8")]
+pub enum TableFormatVersion {
+    V1,
+    V2,
+}
+
+impl TryFrom<u8> for TableFormatVersion {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(TableFormatVersion::V1),
+            2 => Ok(TableFormatVersion::V2),
+            _ => Err(format!(
+                "Unsupported format version {}. Expected 1 or 2.",
+                value
+            )),
+        }
+    }
+}
+
+impl From<TableFormatVersion> for u8 {
+    fn from(value: TableFormatVersion) -> Self {
+        match value {
+            TableFormatVersion::V1 => 1,
+            TableFormatVersion::V2 => 2,
+        }
+    }
+}
+
+impl From<TableFormatVersion> for FormatVersion {
+    fn from(value: TableFormatVersion) -> Self {
+        match value {
+            TableFormatVersion::V1 => FormatVersion::V1,
+            TableFormatVersion::V2 => FormatVersion::V2,
+        }
+    }
+}
+
+// Sort field of a top level column of the stream
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SortFieldConfig {
+    pub column: String,
+    // "asc" (default) or "desc"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub direction: Option<SortDirection>,
+    // "nulls-first" or "nulls-last", defaults to nulls first for ascending and nulls last for
+    // descending columns
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub null_order: Option<NullOrder>,
+}
+
+fn deserialize_transform<'de, D>(deserializer: D) -> Result<Transform, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let transform = String::deserialize(deserializer)?;
+    let width = |prefix: &str| {
+        transform
+            .strip_prefix(prefix)
+            .and_then(|x| x.strip_prefix('['))
+            .and_then(|x| x.strip_suffix(']'))
+            .and_then(|x| x.parse::<u32>().ok())
+            .filter(|x| *x > 0)
+    };
+    match transform.as_str() {
+        "identity" => Ok(Transform::Identity),
+        "year" => Ok(Transform::Year),
+        "month" => Ok(Transform::Month),
+        "day" => Ok(Transform::Day),
+        "hour" => Ok(Transform::Hour),
+        "void" => Ok(Transform::Void),
+        _ => width("bucket")
+            .map(Transform::Bucket)
+            .or(width("truncate").map(Transform::Truncate))
+            .ok_or_else(|| {
+                D::Error::custom(format!(
+                    "Unknown partition transform \"{}\". Expected identity, year, month, day, hour, bucket[N], truncate[W] or void.",
+                    transform
+                ))
+            }),
+    }
+}
+
+// Object store of the data and metadata files, without any parameters the files are kept in memory
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(
+    from = "Option<ObjectStoreConfigSerde>",
+    into = "Option<ObjectStoreConfigSerde>"
+)]
+pub enum ObjectStoreConfig {
+    S3(S3Config),
+    Gcs(GcsConfig),
+    Azure(AzureConfig),
+    Local(LocalConfig),
+    Memory,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct GcsConfig {
+    // Path to the service account file or its JSON key
+    pub google_service_account: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AzureConfig {
+    pub azure_storage_account_name: String,
+    pub azure_storage_account_key: Option<String>,
+    pub azure_storage_sas_token: Option<String>,
+    // Custom endpoint, e.g. of an Azurite container
+    pub azure_endpoint: Option<String>,
+    pub azure_use_emulator: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LocalConfig {
+    // The bucket becomes "file://" followed by the directory
+    pub local_base_directory: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum ObjectStoreConfigSerde {
+    S3(S3Config),
+    Gcs(GcsConfig),
+    Azure(AzureConfig),
+    Local(LocalConfig),
+}
+
+impl From<Option<ObjectStoreConfigSerde>> for ObjectStoreConfig {
+    fn from(value: Option<ObjectStoreConfigSerde>) -> Self {
+        match value {
+            None => ObjectStoreConfig::Memory,
+            Some(ObjectStoreConfigSerde::S3(value)) => ObjectStoreConfig::S3(value),
+            Some(ObjectStoreConfigSerde::Gcs(value)) => ObjectStoreConfig::Gcs(value),
+            Some(ObjectStoreConfigSerde::Azure(value)) => ObjectStoreConfig::Azure(value),
+            Some(ObjectStoreConfigSerde::Local(value)) => ObjectStoreConfig::Local(value),
+        }
+    }
+}
+
+impl From<ObjectStoreConfig> for Option<ObjectStoreConfigSerde> {
+    fn from(value: ObjectStoreConfig) -> Self {
+        match value {
+            ObjectStoreConfig::Memory => None,
+            ObjectStoreConfig::S3(value) => Some(ObjectStoreConfigSerde::S3(value)),
+            ObjectStoreConfig::Gcs(value) => Some(ObjectStoreConfigSerde::Gcs(value)),
+            ObjectStoreConfig::Azure(value) => Some(ObjectStoreConfigSerde::Azure(value)),
+            ObjectStoreConfig::Local(value) => Some(ObjectStoreConfigSerde::Local(value)),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Default)]
@@ -42,6 +416,56 @@ pub enum Replication {
     LogBased,
 }
 
+#[derive(Debug, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WriteMode {
+    #[default]
+    Append,
+    // Replace rows with the same `key_properties` by writing equality deletes
+    Upsert,
+}
+
+// How records with a `_sdc_deleted_at` value are applied to the table
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeleteMode {
+    // Delete the row and drop the tombstone record
+    Hard,
+    // Delete the previous row and keep the tombstone record
+    Soft,
+}
+
+// What happens to records that don't conform to the schema of their stream
+#[derive(Debug, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum OnInvalidRecord {
+    // Abort the sync with the first validation error
+    #[default]
+    Fail,
+    // Drop the record and count it
+    Skip,
+    // Write the record together with its validation errors to a dead-letter location
+    DeadLetter(DeadLetter),
+}
+
+// Dead-letter location of the rejected records of a stream
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum DeadLetter {
+    // Identifier of an iceberg table, which is created if it doesn't exist
+    Table(String),
+    // Directory of the bucket for JSONL files
+    Jsonl(String),
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeleteType {
+    #[default]
+    Equality,
+    Position,
+}
+
 impl Display for Replication {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -52,13 +476,125 @@ impl Display for Replication {
     }
 }
 
+// Build the object store of the configuration. Returns the object store and the bucket name
+// including the scheme of the object store.
+pub fn object_store(
+    config: &ObjectStoreConfig,
+    bucket: Option<&str>,
+) -> Result<(Arc<dyn ObjectStore>, Option<String>), SingerIcebergError> {
+    match config {
+        ObjectStoreConfig::Memory => Ok((Arc::new(InMemory::new()), bucket.map(ToOwned::to_owned))),
+        ObjectStoreConfig::S3(s3_config) => {
+            let bucket_name = bucket
+                .ok_or(SingerIcebergError::Anyhow(anyhow!("No bucket specified.")))?
+                .trim_start_matches("s3://");
+
+            let mut builder = AmazonS3Builder::new()
+                .with_region(&s3_config.aws_region)
+                .with_bucket_name(bucket_name)
+                .with_access_key_id(&s3_config.aws_access_key_id)
+                .with_secret_access_key(s3_config.aws_secret_access_key.as_ref().ok_or(
+                    SingerIcebergError::Anyhow(anyhow!("No aws secret access key given.")),
+                )?);
+
+            if let Some(endpoint) = &s3_config.aws_endpoint {
+                builder = builder.with_endpoint(endpoint);
+            }
+
+            if let Some(allow_http) = &s3_config.aws_allow_http {
+                builder = builder.with_allow_http(allow_http.parse().map_err(anyhow::Error::msg)?);
+            }
+
+            Ok((
+                Arc::new(builder.build()?),
+                Some("s3://".to_owned() + bucket_name),
+            ))
+        }
+        ObjectStoreConfig::Gcs(gcs_config) => {
+            let bucket_name = bucket
+                .ok_or(SingerIcebergError::Anyhow(anyhow!("No bucket specified.")))?
+                .trim_start_matches("gs://");
+
+            let builder = GoogleCloudStorageBuilder::new().with_bucket_name(bucket_name);
+
+            // The service account is either given as JSON or as the path to the JSON file
+            let builder = if gcs_config
+                .google_service_account
+                .trim_start()
+                .starts_with('{')
+            {
+                builder.with_service_account_key(&gcs_config.google_service_account)
+            } else {
+                builder.with_service_account_path(&gcs_config.google_service_account)
+            };
+
+            Ok((
+                Arc::new(builder.build()?),
+                Some("gs://".to_owned() + bucket_name),
+            ))
+        }
+        ObjectStoreConfig::Azure(azure_config) => {
+            let bucket =
+                bucket.ok_or(SingerIcebergError::Anyhow(anyhow!("No bucket specified.")))?;
+
+            let mut builder =
+                MicrosoftAzureBuilder::new().with_account(&azure_config.azure_storage_account_name);
+
+            // Urls like "az://container" or "abfss://container@account.dfs.core.windows.net"
+            // are parsed by the builder, plain names are container names
+            let full_bucket_name = if bucket.contains("://") {
+                builder = builder.with_url(bucket);
+                bucket.trim_end_matches('/').to_owned()
+            } else {
+                builder = builder.with_container_name(bucket);
+                "az://".to_owned() + bucket
+            };
+
+            if let Some(access_key) = &azure_config.azure_storage_account_key {
+                builder = builder.with_access_key(access_key);
+            }
+
+            if let Some(sas_token) = &azure_config.azure_storage_sas_token {
+                builder = builder.with_config(AzureConfigKey::SasKey, sas_token);
+            }
+
+            if let Some(endpoint) = &azure_config.azure_endpoint {
+                builder = builder
+                    .with_endpoint(endpoint.to_owned())
+                    .with_allow_http(endpoint.starts_with("http://"));
+            }
+
+            if let Some(use_emulator) = &azure_config.azure_use_emulator {
+                builder =
+                    builder.with_use_emulator(use_emulator.parse().map_err(anyhow::Error::msg)?);
+            }
+
+            Ok((Arc::new(builder.build()?), Some(full_bucket_name)))
+        }
+        ObjectStoreConfig::Local(local_config) => {
+            // Table locations are absolute file urls, so the object store has to start at the root
+            fs::create_dir_all(&local_config.local_base_directory)?;
+            let directory = fs::canonicalize(&local_config.local_base_directory)?;
+            let directory = directory
+                .to_str()
+                .ok_or(SingerIcebergError::Anyhow(anyhow!(
+                    "The base directory has to be valid unicode."
+                )))?;
+
+            Ok((
+                Arc::new(LocalFileSystem::new()),
+                Some("file://".to_owned() + directory.trim_end_matches('/')),
+            ))
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
-    use dashtool_common::ObjectStoreConfig;
     use serde::{Deserialize, Serialize};
 
-    use super::BaseConfig;
+    use super::{object_store, BaseConfig, ObjectStoreConfig};
 
     #[derive(Debug, Serialize, Deserialize)]
     pub struct Config {
@@ -83,4 +619,65 @@ mod tests {
             panic!("Wrong object_store type")
         };
     }
+
+    #[test]
+    fn test_gcs_config() {
+        let config: Config = serde_json::from_str(
+            r#"
+            {
+                "streams": {},
+                "bucket": "gs://warehouse",
+                "googleServiceAccount": "{\"private_key\": \"\", \"private_key_id\": \"\", \"client_email\": \"\", \"disable_oauth\": true}"
+            }
+            "#,
+        )
+        .expect("Failed to parse config");
+
+        let ObjectStoreConfig::Gcs(_) = config.object_store else {
+            panic!("Wrong object_store type")
+        };
+
+        let (store, bucket) = object_store(&config.object_store, config.base.bucket.as_deref())
+            .expect("Failed to build object store");
+
+        assert_eq!(store.to_string(), "GoogleCloudStorage(warehouse)");
+        assert_eq!(bucket.as_deref(), Some("gs://warehouse"));
+    }
+
+    #[test]
+    fn test_azure_config() {
+        let config: Config = serde_json::from_str(
+            r#"
+            {
+                "streams": {},
+                "bucket": "abfss://warehouse@account.dfs.core.windows.net",
+                "azureStorageAccountName": "account",
+                "azureStorageAccountKey": "a2V5",
+                "azureEndpoint": "http://azurite:10000/account"
+            }
+            "#,
+        )
+        .expect("Failed to parse config");
+
+        let ObjectStoreConfig::Azure(_) = config.object_store else {
+            panic!("Wrong object_store type")
+        };
+
+        let (store, bucket) = object_store(&config.object_store, config.base.bucket.as_deref())
+            .expect("Failed to build object store");
+
+        assert_eq!(
+            store.to_string(),
+            "MicrosoftAzure { account: account, container: warehouse }"
+        );
+        assert_eq!(
+            bucket.as_deref(),
+            Some("abfss://warehouse@account.dfs.core.windows.net")
+        );
+
+        let (_, bucket) = object_store(&config.object_store, Some("warehouse"))
+            .expect("Failed to build object store");
+
+        assert_eq!(bucket.as_deref(), Some("az://warehouse"));
+    }
 }
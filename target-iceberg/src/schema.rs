@@ -1,44 +1,292 @@
-use arrow::datatypes::{DataType, Field, Schema, SchemaBuilder, TimeUnit};
-use singer::schema::{Compound, Format, JsonSchema, Primitive, Type};
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::anyhow;
+use arrow::datatypes::{DataType, Field, Schema as ArrowSchema, TimeUnit};
+use iceberg_rust::spec::{
+    arrow::schema::PARQUET_FIELD_ID_META_KEY,
+    error::Error as SpecError,
+    schema::Schema,
+    table_metadata::TableMetadata,
+    types::{ListType, PrimitiveType, StructField, StructType, Type},
+};
+use serde_json::{Map, Value as JsonValue};
+use singer::schema::{Format, JsonSchema, Primitive};
 
 use crate::error::SingerIcebergError;
 
-pub fn schema_to_arrow(schema: &JsonSchema) -> Result<Schema, SingerIcebergError> {
-    if let Type::Compound(Compound::Object(object)) = &schema.r#type {
-        let mut builder = SchemaBuilder::new();
-        for (name, r#type) in &object.properties {
-            let field = match r#type {
-                Type::Primitive { r#type } => {
-                    Field::new(trim_name(name), primitive_to_arrow(r#type), false)
-                }
-                Type::Single { r#type } => {
-                    Field::new(trim_name(name), primitive_to_arrow(&r#type[0]), false)
-                }
-                Type::Variant { r#type } => {
-                    let value = match (&r#type[0], &r#type[1]) {
-                        (prim, Primitive::Null) => prim,
-                        (Primitive::Null, prim) => prim,
-                        (first, _) => first,
+pub static DEFAULT_MAX_NESTING_DEPTH: usize = 8;
+
+// Convert the JSON schema of a stream into iceberg fields. Objects and arrays nested deeper than
+// `max_depth` and recursive references are stored as JSON strings. The `sql-datatype` metadata of
+// the top level columns determines the type of numeric columns.
+pub fn schema_to_iceberg(
+    schema: &JsonValue,
+    sql_datatypes: &HashMap<String, String>,
+    max_depth: usize,
+) -> Result<StructType, SingerIcebergError> {
+    let converter = Converter {
+        definitions: schema.get("definitions").and_then(JsonValue::as_object),
+        sql_datatypes,
+        max_depth,
+    };
+    match schema.get("properties") {
+        Some(JsonValue::Object(properties)) => {
+            let fields = converter.fields(properties, 0, &mut Vec::new())?;
+            Ok(StructType::new(assign_ids(fields.iter(), &mut 0)))
+        }
+        _ => Err(SingerIcebergError::NoSchema),
+    }
+}
+
+struct Converter<'a> {
+    definitions: Option<&'a Map<String, JsonValue>>,
+    sql_datatypes: &'a HashMap<String, String>,
+    max_depth: usize,
+}
+
+impl<'a> Converter<'a> {
+    fn fields(
+        &self,
+        properties: &'a Map<String, JsonValue>,
+        depth: usize,
+        refs: &mut Vec<&'a str>,
+    ) -> Result<Vec<StructField>, SingerIcebergError> {
+        properties
+            .iter()
+            .map(|(name, schema)| {
+                let sql_datatype = match depth {
+                    0 => self.sql_datatypes.get(name).map(String::as_str),
+                    _ => None,
+                };
+                let (field_type, nullable) = self.field_type(schema, sql_datatype, depth, refs)?;
+                Ok(StructField {
+                    id: 0,
+                    name: trim_name(name).to_owned(),
+                    required: !nullable,
+                    field_type,
+                    doc: None,
+                })
+            })
+            .collect()
+    }
+
+    // Returns the iceberg type of a JSON schema and whether it allows null values
+    fn field_type(
+        &self,
+        schema: &'a JsonValue,
+        sql_datatype: Option<&str>,
+        depth: usize,
+        refs: &mut Vec<&'a str>,
+    ) -> Result<(Type, bool), SingerIcebergError> {
+        if let Some(reference) = schema.get("$ref").and_then(JsonValue::as_str) {
+            let name = reference.trim_start_matches("#/definitions/");
+            // Recursive references can't be represented by a fixed schema
+            if refs.contains(&name) {
+                return Ok((Type::Primitive(PrimitiveType::String), true));
+            }
+            let definition =
+                self.definitions
+                    .and_then(|x| x.get(name))
+                    .ok_or(SingerIcebergError::Anyhow(anyhow!(
+                        "Reference {} can't be resolved.",
+                        reference
+                    )))?;
+            refs.push(name);
+            let field_type = self.field_type(definition, sql_datatype, depth, refs);
+            refs.pop();
+            return field_type;
+        }
+
+        let types = match schema.get("type") {
+            Some(JsonValue::String(r#type)) => vec![r#type.as_str()],
+            Some(JsonValue::Array(types)) => types.iter().filter_map(JsonValue::as_str).collect(),
+            _ => match schema.get("anyOf").and_then(JsonValue::as_array) {
+                Some(schemas) => {
+                    let (nulls, schemas): (Vec<_>, Vec<_>) = schemas
+                        .iter()
+                        .partition(|x| x.get("type").and_then(JsonValue::as_str) == Some("null"));
+                    return match schemas.as_slice() {
+                        [schema] => {
+                            let (field_type, nullable) =
+                                self.field_type(schema, sql_datatype, depth, refs)?;
+                            Ok((field_type, nullable || !nulls.is_empty()))
+                        }
+                        _ => Ok((Type::Primitive(PrimitiveType::String), true)),
                     };
-                    Field::new(trim_name(name), primitive_to_arrow(value), true)
-                }
-                Type::PrimitiveFormat { r#type: _, format } => {
-                    Field::new(trim_name(name), primitivedate_to_arrow(format), false)
                 }
-                Type::SingleFormat { r#type: _, format } => {
-                    Field::new(trim_name(name), primitivedate_to_arrow(format), false)
+                None => Vec::new(),
+            },
+        };
+
+        let nullable = types.is_empty() || types.contains(&"null");
+
+        let field_type = match types
+            .into_iter()
+            .filter(|x| *x != "null")
+            .collect::<Vec<_>>()
+            .as_slice()
+        {
+            ["object"] => match schema.get("properties") {
+                Some(JsonValue::Object(properties))
+                    if depth < self.max_depth && !properties.is_empty() =>
+                {
+                    Type::Struct(StructType::new(self.fields(properties, depth + 1, refs)?))
                 }
-                Type::VariantFormat { r#type: _, format } => {
-                    Field::new(trim_name(name), primitivedate_to_arrow(format), true)
+                _ => Type::Primitive(PrimitiveType::String),
+            },
+            ["array"] => match schema.get("items") {
+                Some(items) if depth < self.max_depth => {
+                    let (element, nullable) = self.field_type(items, None, depth + 1, refs)?;
+                    Type::List(ListType {
+                        element_id: 0,
+                        element_required: !nullable,
+                        element: Box::new(element),
+                    })
                 }
-                Type::Compound(_) => Field::new(trim_name(name), DataType::Null, true),
-                Type::Empty(_) => Field::new(trim_name(name), DataType::Null, true),
-            };
-            builder.push(field);
+                _ => Type::Primitive(PrimitiveType::String),
+            },
+            ["string"] => match schema.get("format").and_then(JsonValue::as_str) {
+                Some("date-time") => Type::Primitive(PrimitiveType::Timestamp),
+                Some("date") => Type::Primitive(PrimitiveType::Date),
+                Some("time") => Type::Primitive(PrimitiveType::Time),
+                _ => Type::Primitive(PrimitiveType::String),
+            },
+            ["integer"] => Type::Primitive(
+                sql_datatype
+                    .and_then(sql_datatype_to_iceberg)
+                    .unwrap_or_else(|| integer_type(schema)),
+            ),
+            ["number"] => Type::Primitive(
+                sql_datatype
+                    .and_then(sql_datatype_to_iceberg)
+                    .unwrap_or_else(|| number_type(schema)),
+            ),
+            ["boolean"] => Type::Primitive(PrimitiveType::Boolean),
+            // Values with multiple or unknown types are stored as JSON
+            _ => Type::Primitive(PrimitiveType::String),
+        };
+
+        Ok((field_type, nullable))
+    }
+}
+
+// Integers use 32 bits if the minimum and maximum allow it, otherwise 64 bits or a decimal
+fn integer_type(schema: &JsonValue) -> PrimitiveType {
+    let (Some(minimum), Some(maximum)) = (
+        schema.get("minimum").and_then(JsonValue::as_f64),
+        schema.get("maximum").and_then(JsonValue::as_f64),
+    ) else {
+        return PrimitiveType::Long;
+    };
+    if minimum >= i32::MIN as f64 && maximum <= i32::MAX as f64 {
+        PrimitiveType::Int
+    } else if minimum >= i64::MIN as f64 && maximum <= i64::MAX as f64 {
+        PrimitiveType::Long
+    } else {
+        match integer_digits(minimum.abs().max(maximum.abs()), false) {
+            precision @ 1..=38 => PrimitiveType::Decimal {
+                precision,
+                scale: 0,
+            },
+            _ => PrimitiveType::Double,
+        }
+    }
+}
+
+// Numbers with a `multipleOf` are decimals with the scale of the `multipleOf` and the precision
+// of the bounds. Other numbers are doubles.
+fn number_type(schema: &JsonValue) -> PrimitiveType {
+    let Some(scale) = schema
+        .get("multipleOf")
+        .and_then(JsonValue::as_f64)
+        .and_then(decimal_scale)
+    else {
+        return PrimitiveType::Double;
+    };
+    // The bounds are either draft 4 booleans or draft 6 numbers
+    let bound = |key: &str, exclusive: &str| match schema.get(exclusive) {
+        Some(JsonValue::Number(bound)) => Some((bound.as_f64()?.abs(), true)),
+        Some(JsonValue::Bool(exclusive)) => Some((schema.get(key)?.as_f64()?.abs(), *exclusive)),
+        _ => Some((schema.get(key)?.as_f64()?.abs(), false)),
+    };
+    let digits = match (
+        bound("minimum", "exclusiveMinimum"),
+        bound("maximum", "exclusiveMaximum"),
+    ) {
+        (Some(minimum), Some(maximum)) => {
+            integer_digits(minimum.0, minimum.1).max(integer_digits(maximum.0, maximum.1))
         }
-        Ok(builder.finish())
+        _ => 38 - scale,
+    };
+    match digits + scale {
+        precision @ 1..=38 => PrimitiveType::Decimal { precision, scale },
+        _ => PrimitiveType::Double,
+    }
+}
+
+// Number of digits before the decimal point of values up to the bound
+fn integer_digits(bound: f64, exclusive: bool) -> u32 {
+    if bound < 1.0 {
+        1
+    } else if exclusive {
+        bound.log10().ceil() as u32
     } else {
-        Err(SingerIcebergError::NoSchema)
+        bound.log10().floor() as u32 + 1
+    }
+}
+
+// Number of decimal places of a `multipleOf`
+fn decimal_scale(multiple_of: f64) -> Option<u32> {
+    (0..=38).find(|scale| {
+        let value = multiple_of * 10f64.powi(*scale as i32);
+        (value - value.round()).abs() < 1e-9 * value.abs().max(1.0)
+    })
+}
+
+// Numeric types of the `sql-datatype` metadata of Postgres and MySQL taps
+fn sql_datatype_to_iceberg(sql_datatype: &str) -> Option<PrimitiveType> {
+    let sql_datatype = sql_datatype.trim().to_lowercase();
+    let unsigned = sql_datatype.contains("unsigned");
+    let name = sql_datatype
+        .split(|c: char| c == '(' || c.is_whitespace())
+        .next()?;
+    let arguments = sql_datatype
+        .split_once('(')
+        .and_then(|(_, arguments)| arguments.split_once(')'))
+        .map(|(arguments, _)| {
+            arguments
+                .split(',')
+                .map(|x| x.trim().parse::<u32>())
+                .collect::<Result<Vec<_>, _>>()
+        });
+    match (name, unsigned) {
+        ("tinyint" | "smallint" | "int2" | "smallserial" | "mediumint", _) => {
+            Some(PrimitiveType::Int)
+        }
+        ("int" | "integer" | "int4" | "serial", false) => Some(PrimitiveType::Int),
+        ("int" | "integer" | "int4" | "serial", true) => Some(PrimitiveType::Long),
+        ("bigint" | "int8" | "bigserial", false) => Some(PrimitiveType::Long),
+        ("bigint" | "int8" | "bigserial", true) => Some(PrimitiveType::Decimal {
+            precision: 20,
+            scale: 0,
+        }),
+        ("real" | "float" | "float4" | "float8" | "double", _) => Some(PrimitiveType::Double),
+        ("money", _) => Some(PrimitiveType::Decimal {
+            precision: 19,
+            scale: 2,
+        }),
+        ("numeric" | "decimal", _) => match arguments?.ok()?.as_slice() {
+            [precision @ 1..=38] => Some(PrimitiveType::Decimal {
+                precision: *precision,
+                scale: 0,
+            }),
+            [precision @ 1..=38, scale] if scale <= precision => Some(PrimitiveType::Decimal {
+                precision: *precision,
+                scale: *scale,
+            }),
+            _ => None,
+        },
+        _ => None,
     }
 }
 
@@ -46,13 +294,73 @@ fn trim_name(name: &str) -> &str {
     name.trim().trim_start_matches("\"").trim_end_matches("\"")
 }
 
+// Assign new ids to the fields, starting after `last_column_id`. The fields of a struct get
+// consecutive ids before the ids of their nested fields.
+fn assign_ids<'a>(
+    fields: impl Iterator<Item = &'a StructField>,
+    last_column_id: &mut i32,
+) -> Vec<StructField> {
+    let mut fields = fields.cloned().collect::<Vec<_>>();
+    for field in fields.iter_mut() {
+        *last_column_id += 1;
+        field.id = *last_column_id;
+    }
+    for field in fields.iter_mut() {
+        field.field_type = assign_type_ids(&field.field_type, last_column_id);
+    }
+    fields
+}
+
+fn assign_type_ids(field_type: &Type, last_column_id: &mut i32) -> Type {
+    match field_type {
+        Type::Struct(fields) => {
+            Type::Struct(StructType::new(assign_ids(fields.iter(), last_column_id)))
+        }
+        Type::List(list) => {
+            *last_column_id += 1;
+            Type::List(ListType {
+                element_id: *last_column_id,
+                element_required: list.element_required,
+                element: Box::new(assign_type_ids(&list.element, last_column_id)),
+            })
+        }
+        field_type => field_type.clone(),
+    }
+}
+
+// Highest field id of the fields including nested fields
+fn max_field_id<'a>(fields: impl Iterator<Item = &'a StructField>) -> i32 {
+    fn max_type_id(field_type: &Type) -> i32 {
+        match field_type {
+            Type::Struct(fields) => max_field_id(fields.iter()),
+            Type::List(list) => list.element_id.max(max_type_id(&list.element)),
+            _ => 0,
+        }
+    }
+    fields
+        .map(|field| field.id.max(max_type_id(&field.field_type)))
+        .max()
+        .unwrap_or_default()
+}
+
+// Arrow schema of the columns that a new table gets for the stream schema
+pub fn schema_to_arrow(schema: &JsonSchema) -> Result<ArrowSchema, SingerIcebergError> {
+    let fields = schema_to_iceberg(
+        &serde_json::to_value(schema)?,
+        &HashMap::new(),
+        DEFAULT_MAX_NESTING_DEPTH,
+    )?;
+    Ok(iceberg_to_arrow(&fields)?)
+}
+
+// Integers and numbers without bounds use 64 bits like their iceberg columns
 #[inline]
 pub fn primitive_to_arrow(input: &Primitive) -> DataType {
     match input {
         Primitive::Null => DataType::Null,
         Primitive::Boolean => DataType::Boolean,
-        Primitive::Integer => DataType::Int32,
-        Primitive::Number => DataType::Float32,
+        Primitive::Integer => DataType::Int64,
+        Primitive::Number => DataType::Float64,
         Primitive::String => DataType::Utf8,
     }
 }
@@ -60,8 +368,328 @@ pub fn primitive_to_arrow(input: &Primitive) -> DataType {
 #[inline]
 pub fn primitivedate_to_arrow(input: &Format) -> DataType {
     match input {
-        Format::Time => DataType::Time32(TimeUnit::Millisecond),
+        Format::Time => DataType::Time64(TimeUnit::Microsecond),
         Format::Date => DataType::Date32,
         Format::DateTime => DataType::Timestamp(TimeUnit::Microsecond, None),
     }
 }
+
+// Convert iceberg fields to an arrow schema. Unlike the conversion of iceberg-rust this supports
+// nested fields and stores the field ids of all fields for parquet.
+pub(crate) fn iceberg_to_arrow(fields: &StructType) -> Result<ArrowSchema, SpecError> {
+    Ok(ArrowSchema::new(
+        fields
+            .iter()
+            .map(|field| field_to_arrow(field.id, &field.name, field.required, &field.field_type))
+            .collect::<Result<Vec<_>, _>>()?,
+    ))
+}
+
+fn field_to_arrow(
+    id: i32,
+    name: &str,
+    required: bool,
+    field_type: &Type,
+) -> Result<Field, SpecError> {
+    let data_type = match field_type {
+        Type::Struct(fields) => DataType::Struct(
+            fields
+                .iter()
+                .map(|field| {
+                    field_to_arrow(field.id, &field.name, field.required, &field.field_type)
+                })
+                .collect::<Result<_, _>>()?,
+        ),
+        Type::List(list) => DataType::List(Arc::new(field_to_arrow(
+            list.element_id,
+            "element",
+            list.element_required,
+            &list.element,
+        )?)),
+        field_type => field_type.try_into()?,
+    };
+    Ok(
+        Field::new(name, data_type, !required).with_metadata(HashMap::from_iter(vec![(
+            PARQUET_FIELD_ID_META_KEY.to_owned(),
+            id.to_string(),
+        )])),
+    )
+}
+
+// The schema that new data is written with. Unlike `TableMetadata::current_schema` this doesn't
+// return the schema of the current snapshot, which is outdated after a schema update.
+pub(crate) fn current_schema(metadata: &TableMetadata) -> Result<&Schema, SpecError> {
+    metadata
+        .schemas
+        .get(&metadata.current_schema_id)
+        .ok_or_else(|| SpecError::InvalidFormat("schema".to_string()))
+}
+
+// Merge the schema of a SCHEMA message into the table schema. New columns are added as optional
+// columns, int and float columns are widened to long and double, decimal columns get the digits
+// of incoming integers and required columns become optional. Returns the new schema and last column id if the table schema has to change.
+pub(crate) fn evolve_schema(
+    metadata: &TableMetadata,
+    schema: &JsonValue,
+    max_depth: usize,
+) -> Result<Option<(Schema, i32)>, SingerIcebergError> {
+    let table_schema = current_schema(metadata)?;
+    let incoming = schema_to_iceberg(
+        schema,
+        &table_sql_datatypes(table_schema.fields(), schema),
+        max_depth,
+    )?;
+
+    // The last column id of new tables doesn't include nested fields
+    let mut last_column_id = metadata
+        .schemas
+        .values()
+        .map(|schema| max_field_id(schema.fields().iter()))
+        .fold(metadata.last_column_id, i32::max);
+    let mut changed = false;
+
+    let fields = merge_fields(
+        table_schema.fields(),
+        &incoming,
+        &mut last_column_id,
+        &mut changed,
+    )?;
+
+    if !changed {
+        return Ok(None);
+    }
+
+    let schema_id = metadata.schemas.keys().max().copied().unwrap_or_default() + 1;
+
+    let mut builder = Schema::builder();
+    builder
+        .with_schema_id(schema_id)
+        .with_fields(StructType::new(fields));
+    if let Some(identifier_field_ids) = table_schema.identifier_field_ids() {
+        builder.with_identifier_field_ids(identifier_field_ids.clone());
+    }
+
+    Ok(Some((
+        builder.build().map_err(SpecError::from)?,
+        last_column_id,
+    )))
+}
+
+// SCHEMA messages don't have the sql-datatype metadata of the catalog. Numeric columns without
+// bounds in the message keep the narrower type of the table column, which came from the metadata.
+fn table_sql_datatypes(fields: &StructType, schema: &JsonValue) -> HashMap<String, String> {
+    fields
+        .iter()
+        .filter(|field| {
+            schema
+                .get("properties")
+                .and_then(|properties| properties.get(&field.name))
+                .is_some_and(unbounded_number)
+        })
+        .filter_map(|field| match field.field_type {
+            Type::Primitive(PrimitiveType::Int) => Some((field.name.clone(), "int".to_owned())),
+            Type::Primitive(PrimitiveType::Decimal { precision, scale }) => Some((
+                field.name.clone(),
+                format!("numeric({},{})", precision, scale),
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+// Integers without minimum and maximum and numbers without multipleOf
+fn unbounded_number(schema: &JsonValue) -> bool {
+    let types = match schema.get("type") {
+        Some(JsonValue::String(x)) => vec![x.as_str()],
+        Some(JsonValue::Array(x)) => x.iter().filter_map(JsonValue::as_str).collect(),
+        _ => return false,
+    };
+    match types.as_slice() {
+        ["integer"] | ["null", "integer"] | ["integer", "null"] => {
+            schema.get("minimum").is_none() || schema.get("maximum").is_none()
+        }
+        ["number"] | ["null", "number"] | ["number", "null"] => schema.get("multipleOf").is_none(),
+        _ => false,
+    }
+}
+
+// Number of decimal digits of the largest value of an integer type
+fn integer_precision(integer: &PrimitiveType) -> u32 {
+    match integer {
+        PrimitiveType::Int => 10,
+        _ => 19,
+    }
+}
+
+fn merge_fields(
+    fields: &StructType,
+    incoming: &StructType,
+    last_column_id: &mut i32,
+    changed: &mut bool,
+) -> Result<Vec<StructField>, SingerIcebergError> {
+    let mut fields = fields.iter().cloned().collect::<Vec<_>>();
+
+    for field in fields.iter_mut() {
+        if field.required && incoming.get_name(&field.name).is_none() {
+            field.required = false;
+            *changed = true;
+        }
+    }
+
+    for new_field in incoming.iter() {
+        match fields.iter_mut().find(|x| x.name == new_field.name) {
+            Some(field) => {
+                if field.required && !new_field.required {
+                    field.required = false;
+                    *changed = true;
+                }
+                field.field_type = merge_type(
+                    &field.name,
+                    &field.field_type,
+                    &new_field.field_type,
+                    last_column_id,
+                    changed,
+                )?;
+            }
+            None => {
+                *changed = true;
+                let mut new_field = assign_ids(std::iter::once(new_field), last_column_id)
+                    .pop()
+                    .ok_or(SingerIcebergError::Unknown)?;
+                new_field.required = false;
+                fields.push(new_field);
+            }
+        }
+    }
+
+    Ok(fields)
+}
+
+fn merge_type(
+    name: &str,
+    field_type: &Type,
+    incoming: &Type,
+    last_column_id: &mut i32,
+    changed: &mut bool,
+) -> Result<Type, SingerIcebergError> {
+    match (field_type, incoming) {
+        (Type::Struct(fields), Type::Struct(incoming)) => Ok(Type::Struct(StructType::new(
+            merge_fields(fields, incoming, last_column_id, changed)?,
+        ))),
+        (Type::List(list), Type::List(incoming)) => {
+            if list.element_required && !incoming.element_required {
+                *changed = true;
+            }
+            Ok(Type::List(ListType {
+                element_id: list.element_id,
+                element_required: list.element_required && incoming.element_required,
+                element: Box::new(merge_type(
+                    name,
+                    &list.element,
+                    &incoming.element,
+                    last_column_id,
+                    changed,
+                )?),
+            }))
+        }
+        (Type::Primitive(PrimitiveType::Int), Type::Primitive(PrimitiveType::Long))
+        | (Type::Primitive(PrimitiveType::Float), Type::Primitive(PrimitiveType::Double)) => {
+            *changed = true;
+            Ok(incoming.clone())
+        }
+        (
+            Type::Primitive(PrimitiveType::Decimal { precision, scale }),
+            Type::Primitive(PrimitiveType::Decimal {
+                precision: new_precision,
+                scale: new_scale,
+            }),
+        ) if scale == new_scale && new_precision > precision => {
+            *changed = true;
+            Ok(incoming.clone())
+        }
+        // Narrower values still fit into the table column
+        (Type::Primitive(PrimitiveType::Long), Type::Primitive(PrimitiveType::Int))
+        | (Type::Primitive(PrimitiveType::Double), Type::Primitive(PrimitiveType::Float)) => {
+            Ok(field_type.clone())
+        }
+        (
+            Type::Primitive(PrimitiveType::Decimal { precision, scale }),
+            Type::Primitive(PrimitiveType::Decimal {
+                precision: new_precision,
+                scale: new_scale,
+            }),
+        ) if new_scale <= scale && new_precision - new_scale <= precision - scale => {
+            Ok(field_type.clone())
+        }
+        // Integers fit into decimal columns with enough digits before the decimal point, other
+        // decimal columns are widened to keep their scale
+        (
+            Type::Primitive(PrimitiveType::Decimal { precision, scale }),
+            Type::Primitive(integer @ (PrimitiveType::Int | PrimitiveType::Long)),
+        ) if *scale + integer_precision(integer) <= 38 => {
+            let digits = integer_precision(integer);
+            if precision - scale >= digits {
+                Ok(field_type.clone())
+            } else {
+                *changed = true;
+                Ok(Type::Primitive(PrimitiveType::Decimal {
+                    precision: scale + digits,
+                    scale: *scale,
+                }))
+            }
+        }
+        // Any value can be stored in a string column, other values are serialized as JSON
+        (Type::Primitive(PrimitiveType::String), _) => Ok(field_type.clone()),
+        (old, new) if old == new => Ok(field_type.clone()),
+        (old, new) => Err(SingerIcebergError::Anyhow(anyhow!(
+            "Column {} can't be changed from type {} to {}.",
+            name,
+            old,
+            new
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::datatypes::DataType;
+    use singer::schema::JsonSchema;
+
+    use super::schema_to_arrow;
+
+    #[test]
+    fn test_schema_to_arrow() {
+        let schema: JsonSchema = serde_json::from_str(
+            r#"
+            {
+                "type": "object",
+                "properties": {
+                    "id": { "type": "integer" },
+                    "address": {
+                        "type": "object",
+                        "properties": { "city": { "type": ["null", "string"] } }
+                    },
+                    "tags": { "type": "array", "items": { "type": "string" } }
+                }
+            }
+            "#,
+        )
+        .unwrap();
+
+        let schema = schema_to_arrow(&schema).unwrap();
+
+        assert_eq!(
+            schema.field_with_name("id").unwrap().data_type(),
+            &DataType::Int64
+        );
+        let DataType::Struct(fields) = schema.field_with_name("address").unwrap().data_type()
+        else {
+            panic!("Address isn't a struct")
+        };
+        assert_eq!(fields[0].name(), "city");
+        assert!(matches!(
+            schema.field_with_name("tags").unwrap().data_type(),
+            DataType::List(_)
+        ));
+    }
+}
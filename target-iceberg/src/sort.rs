@@ -0,0 +1,126 @@
+use std::collections::HashSet;
+
+use anyhow::anyhow;
+use arrow::{
+    compute::{concat_batches, lexsort_to_indices, take_record_batch, SortColumn, SortOptions},
+    record_batch::RecordBatch,
+};
+use iceberg_rust::spec::{
+    partition::Transform,
+    schema::Schema,
+    sort::{NullOrder, SortDirection, SortField, SortOrder},
+    table_metadata::TableMetadata,
+    types::{StructType, Type},
+};
+
+use crate::{error::SingerIcebergError, plugin::SortFieldConfig};
+
+// Sort order id 0 is reserved for unsorted tables
+static FIRST_SORT_ORDER_ID: i32 = 1;
+
+// Build the sort order of a new table. Fails for unknown or nested columns and columns that are
+// used more than once.
+pub(crate) fn sort_order(
+    config: &[SortFieldConfig],
+    fields: &StructType,
+) -> Result<SortOrder, SingerIcebergError> {
+    let mut columns = HashSet::new();
+
+    let sort_fields = config
+        .iter()
+        .map(|sort_field| {
+            let column = &sort_field.column;
+            let field = fields.get_name(column).ok_or(anyhow!(
+                "Sort column {} doesn't exist in the schema.",
+                column
+            ))?;
+            if !matches!(field.field_type, Type::Primitive(_)) {
+                return Err(SingerIcebergError::Anyhow(anyhow!(
+                    "Sort column {} has to be a primitive column.",
+                    column
+                )));
+            }
+            if !columns.insert(column) {
+                return Err(SingerIcebergError::Anyhow(anyhow!(
+                    "Sort column {} is used more than once.",
+                    column
+                )));
+            }
+
+            let direction = sort_field
+                .direction
+                .clone()
+                .unwrap_or(SortDirection::Ascending);
+            // Like in SQL nulls are the smallest values by default
+            let null_order = sort_field.null_order.clone().unwrap_or(match direction {
+                SortDirection::Ascending => NullOrder::First,
+                SortDirection::Descending => NullOrder::Last,
+            });
+
+            Ok(SortField {
+                source_id: field.id,
+                transform: Transform::Identity,
+                direction,
+                null_order,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(SortOrder {
+        order_id: FIRST_SORT_ORDER_ID,
+        fields: sort_fields,
+    })
+}
+
+// Sort the rows of a partition into a single record batch. Only the leading identity fields of the
+// sort order are applied, because other transforms don't produce a total order of the rows.
+pub(crate) fn sort_batches(
+    batches: Vec<RecordBatch>,
+    sort_order: &SortOrder,
+    schema: &Schema,
+) -> Result<Vec<RecordBatch>, SingerIcebergError> {
+    let fields = sort_order
+        .fields
+        .iter()
+        .take_while(|field| matches!(field.transform, Transform::Identity))
+        .filter_map(|field| Some((schema.fields().get(field.source_id as usize)?, field)))
+        .collect::<Vec<_>>();
+
+    let Some(first) = batches.first() else {
+        return Ok(batches);
+    };
+    if fields.is_empty() {
+        return Ok(batches);
+    }
+
+    let batch = concat_batches(&first.schema(), &batches)?;
+
+    let columns = fields
+        .into_iter()
+        .filter_map(|(column, field)| {
+            Some(SortColumn {
+                values: batch.column_by_name(&column.name)?.clone(),
+                options: Some(SortOptions {
+                    descending: matches!(field.direction, SortDirection::Descending),
+                    nulls_first: matches!(field.null_order, NullOrder::First),
+                }),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if columns.is_empty() {
+        return Ok(vec![batch]);
+    }
+
+    let indices = lexsort_to_indices(&columns, None)?;
+
+    Ok(vec![take_record_batch(&batch, &indices)?])
+}
+
+// The sort order that new data files are written with
+pub(crate) fn default_sort_order(metadata: &TableMetadata) -> Option<&SortOrder> {
+    metadata
+        .sort_orders
+        .get(&metadata.default_sort_order_id)
+        .filter(|sort_order| !sort_order.fields.is_empty())
+}
@@ -1,38 +1,173 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, io::Write, iter, str::FromStr, sync::Arc};
 
+use anyhow::anyhow;
+use chrono::DateTime;
 use futures::{lock::Mutex, stream, StreamExt, TryStreamExt};
-use iceberg_rust::catalog::{identifier::Identifier, tabular::Tabular};
-use serde_json::{Map, Value};
+use iceberg_rust::{
+    catalog::tabular::Tabular,
+    spec::{snapshot::Snapshot, table_metadata::TableMetadata, util::strip_prefix},
+    table::Table,
+};
+use serde_json::{json, Map, Value};
 
-use crate::{error::SingerIcebergError, plugin::TargetPlugin};
+use crate::{
+    dead_letter::location,
+    error::SingerIcebergError,
+    identifier::resolve_identifier,
+    ingest::SINGER_RUN_ID,
+    plugin::{StreamConfig, TargetPlugin},
+};
 
 pub(crate) static SINGER_BOOKMARK: &str = "singer.bookmark";
+// Id of the snapshot that the committed table properties belong to
+pub(crate) static SINGER_SNAPSHOT_ID: &str = "singer.snapshot-id";
+
+static DEFAULT_STATE_LOCATION: &str = "singer/state.json";
+
+// Point in the history of the tables that the state is generated for
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum StateAt {
+    #[default]
+    Current,
+    Branch(String),
+    // The other tables as of the time of the snapshot
+    Snapshot(i64),
+    // Milliseconds since the epoch
+    Timestamp(i64),
+}
+
+// Snapshot ids are numbers and timestamps follow RFC 3339, everything else is a branch
+impl FromStr for StateAt {
+    type Err = SingerIcebergError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Ok(snapshot_id) = value.parse::<i64>() {
+            Ok(StateAt::Snapshot(snapshot_id))
+        } else if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+            Ok(StateAt::Timestamp(timestamp.timestamp_millis()))
+        } else if value.is_empty() {
+            Err(SingerIcebergError::Anyhow(anyhow!(
+                "The state has to be generated at a timestamp, a snapshot id or a branch."
+            )))
+        } else {
+            Ok(StateAt::Branch(value.to_owned()))
+        }
+    }
+}
 
 pub async fn generate_state(plugin: Arc<dyn TargetPlugin>) -> Result<Value, SingerIcebergError> {
+    generate_state_at(plugin, &StateAt::Current).await
+}
+
+// The stored state of the last sync together with the bookmarks of the tables. Only the bookmarks
+// follow the history of the tables, the other keys are always the latest ones.
+pub async fn generate_state_at(
+    plugin: Arc<dyn TargetPlugin>,
+    at: &StateAt,
+) -> Result<Value, SingerIcebergError> {
+    let state = stored_state(plugin.as_ref()).await?;
+    state_value(&state, &bookmarks_at(plugin, at).await?)
+}
+
+// Bookmarks that are stored in the tables of the streams. Streams without a table have no bookmark.
+pub(crate) async fn committed_bookmarks(
+    plugin: Arc<dyn TargetPlugin>,
+) -> Result<HashMap<String, String>, SingerIcebergError> {
+    bookmarks_at(plugin, &StateAt::Current).await
+}
+
+async fn bookmarks_at(
+    plugin: Arc<dyn TargetPlugin>,
+    at: &StateAt,
+) -> Result<HashMap<String, String>, SingerIcebergError> {
+    let tables = stream_tables(plugin.clone()).await?;
+
+    let branch = plugin.branch().as_deref();
+
+    // A snapshot id belongs to a single table, the other tables are restored as of its time
+    let timestamp = match at {
+        StateAt::Snapshot(snapshot_id) => Some(
+            tables
+                .values()
+                .find_map(|table| table.metadata().snapshots.get(snapshot_id))
+                .map(|snapshot| *snapshot.timestamp_ms())
+                .ok_or(SingerIcebergError::Anyhow(anyhow!(
+                    "Snapshot {} doesn't exist in the tables of the streams.",
+                    snapshot_id
+                )))?,
+        ),
+        StateAt::Timestamp(timestamp) => Some(*timestamp),
+        _ => None,
+    };
+
+    let mut bookmarks = HashMap::new();
+
+    for (stream, table) in &tables {
+        let metadata = table.metadata();
+
+        let bookmark = match at {
+            StateAt::Current => committed_property(metadata, branch, SINGER_BOOKMARK)?,
+            // The bookmark property belongs to the branch the target writes to
+            StateAt::Branch(name) if name == branch.unwrap_or("main") => {
+                committed_property(metadata, branch, SINGER_BOOKMARK)?
+            }
+            StateAt::Branch(name) => {
+                if !metadata.refs.contains_key(name) && !metadata.snapshots.is_empty() {
+                    return Err(SingerIcebergError::Anyhow(anyhow!(
+                        "Table {} has no branch {}.",
+                        table.identifier(),
+                        name
+                    )));
+                }
+                property_at(
+                    metadata,
+                    metadata.current_snapshot(Some(name))?,
+                    SINGER_BOOKMARK,
+                )
+            }
+            StateAt::Snapshot(snapshot_id) if metadata.snapshots.contains_key(snapshot_id) => {
+                property_at(
+                    metadata,
+                    metadata.snapshots.get(snapshot_id),
+                    SINGER_BOOKMARK,
+                )
+            }
+            StateAt::Snapshot(_) | StateAt::Timestamp(_) => property_at(
+                metadata,
+                snapshot_at(metadata, branch, timestamp.unwrap_or_default())?,
+                SINGER_BOOKMARK,
+            ),
+        };
+
+        if let Some(bookmark) = bookmark {
+            bookmarks.insert(stream.clone(), bookmark);
+        }
+    }
+
+    Ok(bookmarks)
+}
+
+// Tables of the streams that exist
+async fn stream_tables(
+    plugin: Arc<dyn TargetPlugin>,
+) -> Result<HashMap<String, Table>, SingerIcebergError> {
     let streams = plugin.streams();
 
-    let bookmarks: Arc<Mutex<HashMap<String, String>>> = Arc::new(Mutex::new(HashMap::new()));
+    let tables: Arc<Mutex<HashMap<String, Table>>> = Arc::new(Mutex::new(HashMap::new()));
 
     stream::iter(streams.iter())
         .map(Ok::<_, SingerIcebergError>)
         .try_for_each_concurrent(None, |(stream, identifier)| {
-            let bookmarks = bookmarks.clone();
+            let tables = tables.clone();
             let plugin = plugin.clone();
             async move {
                 let catalog = plugin.catalog().await?;
 
-                let ident = Identifier::try_new(
-                    &identifier
-                        .identifier
-                        .split('.')
-                        .collect::<Vec<_>>()
-                        .into_iter()
-                        .rev()
-                        .take(2)
-                        .rev()
-                        .map(ToOwned::to_owned)
-                        .collect::<Vec<_>>(),
-                )?;
+                let ident = resolve_identifier(&identifier.identifier, plugin.catalog_name())?;
+
+                if !catalog.tabular_exists(&ident).await? {
+                    return Ok(());
+                }
 
                 let table = catalog.load_tabular(&ident).await?;
 
@@ -42,30 +177,250 @@ pub async fn generate_state(plugin: Arc<dyn TargetPlugin>) -> Result<Value, Sing
                     return Err(SingerIcebergError::Unknown);
                 };
 
-                if let Some(bookmark) = table.metadata().properties.get(SINGER_BOOKMARK) {
-                    bookmarks
-                        .lock()
-                        .await
-                        .insert(stream.clone(), bookmark.clone());
-                };
+                tables.lock().await.insert(stream.clone(), table);
                 Ok(())
             }
         })
         .await?;
 
-    let state = Value::Object(Map::from_iter(vec![
-        ("currently_syncing".to_owned(), Value::Null),
-        (
-            "bookmarks".to_string(),
-            Value::Object(Map::from_iter(
-                Arc::try_unwrap(bookmarks)
-                    .unwrap()
-                    .into_inner()
-                    .into_iter()
-                    .map(|(key, value)| (key, serde_json::from_str(&value).unwrap())),
-            )),
-        ),
-    ]));
+    Ok(Arc::try_unwrap(tables).unwrap().into_inner())
+}
+
+// Value of a property that the target commits together with its snapshots, like the bookmark. The
+// table property also advances without new snapshots and belongs to the snapshot in
+// `singer.snapshot-id`. If that snapshot isn't in the history of the branch, because the branch
+// was rolled back behind it or the property was committed to another branch, the value is taken
+// from the summary of the last snapshot of the target in the branch.
+pub(crate) fn committed_property(
+    metadata: &TableMetadata,
+    branch: Option<&str>,
+    key: &str,
+) -> Result<Option<String>, SingerIcebergError> {
+    let current = metadata.current_snapshot(branch)?;
+
+    match metadata.properties.get(SINGER_SNAPSHOT_ID) {
+        Some(snapshot_id)
+            if !ancestors(metadata, current)
+                .any(|snapshot| snapshot.snapshot_id().to_string() == *snapshot_id) =>
+        {
+            Ok(property_at(metadata, current, key))
+        }
+        _ => Ok(metadata.properties.get(key).cloned()),
+    }
+}
+
+// Value of a committed property as of a snapshot, from the summary of the last snapshot of the
+// target up to it
+fn property_at(metadata: &TableMetadata, snapshot: Option<&Snapshot>, key: &str) -> Option<String> {
+    ancestors(metadata, snapshot)
+        .find(|snapshot| is_target(snapshot))
+        .and_then(|snapshot| snapshot.summary().other.get(key).cloned())
+}
+
+// Snapshot of the branch that was current at the time
+fn snapshot_at<'a>(
+    metadata: &'a TableMetadata,
+    branch: Option<&str>,
+    timestamp: i64,
+) -> Result<Option<&'a Snapshot>, SingerIcebergError> {
+    Ok(ancestors(metadata, metadata.current_snapshot(branch)?)
+        .find(|snapshot| *snapshot.timestamp_ms() <= timestamp))
+}
+
+// The snapshot followed by its ancestors
+fn ancestors<'a>(
+    metadata: &'a TableMetadata,
+    snapshot: Option<&'a Snapshot>,
+) -> impl Iterator<Item = &'a Snapshot> {
+    iter::successors(snapshot, |snapshot| {
+        snapshot
+            .parent_snapshot_id()
+            .as_ref()
+            .and_then(|parent| metadata.snapshots.get(parent))
+    })
+}
+
+// Snapshots of the target carry the id of the run in their summary
+fn is_target(snapshot: &Snapshot) -> bool {
+    snapshot.summary().other.contains_key(SINGER_RUN_ID)
+}
+
+// Merge the bookmarks of the tables into the state
+fn state_value(
+    state: &Map<String, Value>,
+    bookmarks: &HashMap<String, String>,
+) -> Result<Value, SingerIcebergError> {
+    let mut state = state.clone();
+
+    let mut merged = match state.remove("bookmarks") {
+        Some(Value::Object(bookmarks)) => bookmarks,
+        _ => Map::new(),
+    };
+
+    for (stream, bookmark) in bookmarks {
+        let bookmark = serde_json::from_str(bookmark).map_err(|error| {
+            SingerIcebergError::Anyhow(anyhow!(
+                "Bookmark of stream {} is not valid JSON: {}.",
+                stream,
+                error
+            ))
+        })?;
+        merged.insert(stream.clone(), bookmark);
+    }
+
+    state.insert("bookmarks".to_owned(), Value::Object(merged));
+
+    Ok(Value::Object(state))
+}
+
+// The keys of a STATE value besides the bookmarks of the streams of the target. Their bookmarks
+// are only taken from the tables, because the tap may be ahead of the committed records.
+pub(crate) fn base_state(
+    value: &Value,
+    streams: &HashMap<String, StreamConfig>,
+) -> Result<Map<String, Value>, SingerIcebergError> {
+    let Value::Object(state) = value else {
+        return Err(SingerIcebergError::Anyhow(anyhow!(
+            "State value has to be an object."
+        )));
+    };
+
+    let mut state = state.clone();
+
+    match state.get_mut("bookmarks") {
+        Some(Value::Object(bookmarks)) => {
+            bookmarks.retain(|stream, _| !streams.contains_key(stream));
+        }
+        None | Some(Value::Null) => (),
+        Some(_) => {
+            return Err(SingerIcebergError::Anyhow(anyhow!(
+                "Bookmarks of the state have to be an object."
+            )))
+        }
+    }
 
     Ok(state)
 }
+
+// The last STATE value of a sync is kept in the bucket, so that the keys besides the bookmarks
+// survive until the next sync
+pub(crate) async fn store_state(
+    plugin: &dyn TargetPlugin,
+    state: &Value,
+) -> Result<(), SingerIcebergError> {
+    let location = state_location(plugin);
+
+    plugin
+        .object_store()
+        .put(
+            &strip_prefix(&location).into(),
+            serde_json::to_vec(state)?.into(),
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub(crate) async fn stored_state(
+    plugin: &dyn TargetPlugin,
+) -> Result<Map<String, Value>, SingerIcebergError> {
+    let location = state_location(plugin);
+
+    let object_store = plugin.object_store();
+
+    let bytes = match object_store.get(&strip_prefix(&location).into()).await {
+        Ok(result) => result.bytes().await?,
+        // Nothing is stored before the first sync
+        Err(object_store::Error::NotFound { .. }) => {
+            return Ok(Map::from_iter([(
+                "currently_syncing".to_owned(),
+                Value::Null,
+            )]))
+        }
+        Err(error) => return Err(error.into()),
+    };
+
+    let value: Value = serde_json::from_slice(&bytes).map_err(|error| {
+        SingerIcebergError::Anyhow(anyhow!(
+            "Stored state {} is not valid JSON: {}.",
+            location,
+            error
+        ))
+    })?;
+
+    base_state(&value, plugin.streams())
+}
+
+fn state_location(plugin: &dyn TargetPlugin) -> String {
+    location(
+        plugin.bucket(),
+        plugin.state_location().unwrap_or(DEFAULT_STATE_LOCATION),
+    )
+}
+
+// Writes a STATE message with the committed bookmarks of all streams after every commit. The other
+// keys are those of the last STATE message that was read.
+pub(crate) struct StateWriter {
+    inner: std::sync::Mutex<StateOutput>,
+}
+
+// State without the bookmarks of the streams, committed bookmarks and the output
+type StateOutput = (
+    Map<String, Value>,
+    HashMap<String, String>,
+    Box<dyn Write + Send>,
+);
+
+impl StateWriter {
+    pub(crate) fn new(
+        state: Map<String, Value>,
+        bookmarks: HashMap<String, String>,
+        output: impl Write + Send + 'static,
+    ) -> Self {
+        StateWriter {
+            inner: std::sync::Mutex::new((state, bookmarks, Box::new(output))),
+        }
+    }
+
+    pub(crate) fn update(&self, state: Map<String, Value>) {
+        self.inner.lock().unwrap().0 = state;
+    }
+
+    pub(crate) fn write(
+        &self,
+        stream: &str,
+        bookmark: Option<&str>,
+    ) -> Result<(), SingerIcebergError> {
+        let mut inner = self.inner.lock().unwrap();
+        let (state, bookmarks, output) = &mut *inner;
+        if let Some(bookmark) = bookmark {
+            bookmarks.insert(stream.to_owned(), bookmark.to_owned());
+        }
+        let message = json!({ "type": "STATE", "value": state_value(state, bookmarks)? });
+        writeln!(output, "{}", message)?;
+        output.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_at() {
+        assert_eq!(
+            "4702473617284826000".parse::<StateAt>().unwrap(),
+            StateAt::Snapshot(4702473617284826000)
+        );
+        assert_eq!(
+            "2024-01-31T12:00:00+01:00".parse::<StateAt>().unwrap(),
+            StateAt::Timestamp(1706698800000)
+        );
+        assert_eq!(
+            "audit".parse::<StateAt>().unwrap(),
+            StateAt::Branch("audit".to_owned())
+        );
+        assert!("".parse::<StateAt>().is_err());
+    }
+}
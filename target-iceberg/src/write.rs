@@ -0,0 +1,593 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    mem,
+    str::FromStr,
+    sync::Arc,
+};
+
+use anyhow::anyhow;
+
+use arrow::{datatypes::Schema as ArrowSchema, error::ArrowError, record_batch::RecordBatch};
+use futures::{pin_mut, Stream, StreamExt};
+use iceberg_rust::spec::{
+    error::Error as SpecError,
+    manifest::{AvroMap, Content, DataFile, FileFormat},
+    partition::PartitionSpec,
+    schema::Schema,
+    sort::SortOrder,
+    table_metadata::TableMetadata,
+    types::Type,
+    util::strip_prefix,
+    values::{Struct, Value},
+};
+use object_store::ObjectStore;
+use parquet::{
+    arrow::AsyncArrowWriter,
+    basic::{BrotliLevel, Compression, GzipLevel, ZstdLevel},
+    file::{metadata::RowGroupMetaData, properties::WriterProperties},
+    format::FileMetaData,
+    schema::types::{from_thrift, SchemaDescriptor},
+};
+use serde_json::Value as JsonValue;
+use tokio::io::AsyncWrite;
+use uuid::Uuid;
+
+use crate::{
+    error::SingerIcebergError,
+    partition::{partition_batches, partition_path, partition_struct},
+    plugin::{ParquetCompression, ParquetConfig},
+    schema::{current_schema, iceberg_to_arrow},
+    sort::{default_sort_order, sort_batches},
+};
+
+static DEFAULT_TARGET_FILE_SIZE: usize = 512_000_000;
+// Rows of sorted tables that are buffered before they are written sorted
+static SORT_BUFFER_SIZE: usize = 128 * 1024 * 1024;
+static DEFAULT_ZSTD_LEVEL: i32 = 1;
+
+// Table properties of the parquet writer
+static COMPRESSION_CODEC: &str = "write.parquet.compression-codec";
+static COMPRESSION_LEVEL: &str = "write.parquet.compression-level";
+static ROW_GROUP_SIZE: &str = "write.parquet.row-group-size-bytes";
+static PAGE_SIZE: &str = "write.parquet.page-size-bytes";
+static DICTIONARY_SIZE: &str = "write.parquet.dict-size-bytes";
+static TARGET_FILE_SIZE: &str = "write.target-file-size-bytes";
+
+// Settings of the parquet writer. The settings of the stream take precedence over the properties
+// of the table.
+struct ParquetWriterConfig {
+    properties: WriterProperties,
+    row_group_size: Option<usize>,
+    target_file_size: usize,
+}
+
+impl ParquetWriterConfig {
+    fn new(
+        config: &ParquetConfig,
+        table_properties: &HashMap<String, String>,
+    ) -> Result<Self, SingerIcebergError> {
+        let table_codec = table_properties
+            .get(COMPRESSION_CODEC)
+            .map(|codec| {
+                serde_json::from_value::<ParquetCompression>(JsonValue::String(
+                    codec.to_lowercase(),
+                ))
+                .map_err(|_| {
+                    anyhow!(
+                        "Unsupported compression codec {} in table property {}.",
+                        codec,
+                        COMPRESSION_CODEC
+                    )
+                })
+            })
+            .transpose()?;
+        let codec = config.compression.or(table_codec);
+        // The compression level of the table only applies to the codec of the table
+        let level = if config.compression_level.is_some() || codec == table_codec {
+            setting(
+                config.compression_level,
+                table_properties,
+                COMPRESSION_LEVEL,
+            )?
+        } else {
+            None
+        };
+        let unsigned_level = || {
+            level
+                .map(u32::try_from)
+                .transpose()
+                .map_err(|_| anyhow!("The compression level can't be negative."))
+        };
+
+        let compression = match codec.unwrap_or(ParquetCompression::Zstd) {
+            ParquetCompression::Zstd => {
+                Compression::ZSTD(ZstdLevel::try_new(level.unwrap_or(DEFAULT_ZSTD_LEVEL))?)
+            }
+            ParquetCompression::Gzip => Compression::GZIP(
+                unsigned_level()?
+                    .map(GzipLevel::try_new)
+                    .transpose()?
+                    .unwrap_or_default(),
+            ),
+            ParquetCompression::Brotli => Compression::BROTLI(
+                unsigned_level()?
+                    .map(BrotliLevel::try_new)
+                    .transpose()?
+                    .unwrap_or_default(),
+            ),
+            ParquetCompression::Snappy => Compression::SNAPPY,
+            ParquetCompression::Lz4 => Compression::LZ4_RAW,
+            ParquetCompression::Uncompressed => Compression::UNCOMPRESSED,
+        };
+
+        let mut properties = WriterProperties::builder().set_compression(compression);
+        if let Some(page_size) = setting(config.page_size, table_properties, PAGE_SIZE)? {
+            properties = properties.set_data_page_size_limit(page_size);
+        }
+        if let Some(dictionary_size) =
+            setting(config.dictionary_size, table_properties, DICTIONARY_SIZE)?
+        {
+            properties = properties.set_dictionary_page_size_limit(dictionary_size);
+        }
+        if let Some(dictionary_enabled) = config.dictionary_enabled {
+            properties = properties.set_dictionary_enabled(dictionary_enabled);
+        }
+
+        Ok(ParquetWriterConfig {
+            properties: properties.build(),
+            row_group_size: setting(config.row_group_size, table_properties, ROW_GROUP_SIZE)?,
+            target_file_size: setting(config.target_file_size, table_properties, TARGET_FILE_SIZE)?
+                .unwrap_or(DEFAULT_TARGET_FILE_SIZE),
+        })
+    }
+}
+
+// Properties of the parquet writer for files that aren't split by size, like delete files
+pub(crate) fn writer_properties(
+    config: &ParquetConfig,
+    table_properties: &HashMap<String, String>,
+) -> Result<WriterProperties, SingerIcebergError> {
+    Ok(ParquetWriterConfig::new(config, table_properties)?.properties)
+}
+
+// The setting of the config or otherwise the table property
+pub(crate) fn setting<T: FromStr>(
+    value: Option<T>,
+    properties: &HashMap<String, String>,
+    key: &str,
+) -> Result<Option<T>, SingerIcebergError> {
+    if value.is_some() {
+        return Ok(value);
+    }
+    properties
+        .get(key)
+        .map(|value| {
+            value.parse().map_err(|_| {
+                SingerIcebergError::Anyhow(anyhow!(
+                    "Invalid value {} of table property {}.",
+                    value,
+                    key
+                ))
+            })
+        })
+        .transpose()
+}
+
+// Partition the record batches, sort the rows of every partition by the sort order of the table
+// and write them to parquet files. Unlike the writer of iceberg-rust this doesn't fail for columns
+// that only contain null values.
+pub(crate) async fn write_parquet_partitioned(
+    metadata: &TableMetadata,
+    batches: impl Stream<Item = Result<RecordBatch, ArrowError>> + Send,
+    object_store: Arc<dyn ObjectStore>,
+    parquet: &ParquetConfig,
+) -> Result<Vec<DataFile>, SingerIcebergError> {
+    let mut writer = DataFileWriter::new(metadata, object_store, parquet)?;
+
+    pin_mut!(batches);
+
+    while let Some(batch) = batches.next().await {
+        writer.write(batch?).await?;
+    }
+
+    writer.close().await
+}
+
+// Writes record batches to an open parquet file of their partition as they arrive, so only the
+// current row group of every partition is kept in memory. The rows of sorted tables are buffered
+// until they exceed `SORT_BUFFER_SIZE` and are then written to new files in sort order.
+pub(crate) struct DataFileWriter {
+    files: FileConfig,
+    partition_spec: PartitionSpec,
+    sort_order: Option<SortOrder>,
+    partitions: HashMap<Vec<Option<Value>>, PartitionWriter>,
+    sort_buffer_size: usize,
+    written: Vec<DataFile>,
+}
+
+// Everything that is needed to write the files of a partition
+struct FileConfig {
+    schema: Schema,
+    arrow_schema: Arc<ArrowSchema>,
+    location: String,
+    object_store: Arc<dyn ObjectStore>,
+    writer_config: ParquetWriterConfig,
+}
+
+type ParquetFileWriter = AsyncArrowWriter<Box<dyn AsyncWrite + Unpin + Send>>;
+
+struct PartitionWriter {
+    location: String,
+    partition: Struct,
+    current: Option<(String, ParquetFileWriter)>,
+    unsorted: Vec<RecordBatch>,
+}
+
+impl DataFileWriter {
+    pub(crate) fn new(
+        metadata: &TableMetadata,
+        object_store: Arc<dyn ObjectStore>,
+        parquet: &ParquetConfig,
+    ) -> Result<Self, SingerIcebergError> {
+        let schema = current_schema(metadata)?;
+        Ok(Self {
+            files: FileConfig {
+                schema: schema.clone(),
+                arrow_schema: Arc::new(iceberg_to_arrow(schema.fields())?),
+                location: metadata.location.to_string() + "/data/",
+                object_store,
+                writer_config: ParquetWriterConfig::new(parquet, &metadata.properties)?,
+            },
+            partition_spec: metadata.default_partition_spec()?.clone(),
+            sort_order: default_sort_order(metadata).cloned(),
+            partitions: HashMap::new(),
+            sort_buffer_size: 0,
+            written: Vec::new(),
+        })
+    }
+
+    pub(crate) async fn write(&mut self, batch: RecordBatch) -> Result<(), SingerIcebergError> {
+        for (values, batches) in
+            partition_batches(vec![batch], &self.partition_spec, &self.files.schema)?
+        {
+            let partition = match self.partitions.entry(values) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let location = self.files.location.clone()
+                        + &partition_path(&self.partition_spec, entry.key());
+                    let partition = partition_struct(&self.partition_spec, entry.key().clone());
+                    entry.insert(PartitionWriter {
+                        location,
+                        partition,
+                        current: None,
+                        unsorted: Vec::new(),
+                    })
+                }
+            };
+            if self.sort_order.is_some() {
+                self.sort_buffer_size += batches
+                    .iter()
+                    .map(RecordBatch::get_array_memory_size)
+                    .sum::<usize>();
+                partition.unsorted.extend(batches);
+            } else {
+                for batch in batches {
+                    if let Some(file) = partition.write(&self.files, &batch).await? {
+                        self.written.push(file);
+                    }
+                }
+            }
+        }
+
+        if self.sort_buffer_size > SORT_BUFFER_SIZE {
+            self.write_sorted().await?;
+        }
+
+        Ok(())
+    }
+
+    // Write the buffered rows to the object store. Sorted tables write their rows to new sorted
+    // files, the open files of unsorted tables finish their row group.
+    pub(crate) async fn flush(&mut self) -> Result<(), SingerIcebergError> {
+        self.write_sorted().await?;
+        for partition in self.partitions.values_mut() {
+            if let Some((_, current)) = &mut partition.current {
+                current.flush().await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn close(mut self) -> Result<Vec<DataFile>, SingerIcebergError> {
+        self.write_sorted().await?;
+        for partition in self.partitions.values_mut() {
+            if let Some(file) = partition.close(&self.files).await? {
+                self.written.push(file);
+            }
+        }
+        Ok(self.written)
+    }
+
+    async fn write_sorted(&mut self) -> Result<(), SingerIcebergError> {
+        let Some(sort_order) = &self.sort_order else {
+            return Ok(());
+        };
+        for partition in self.partitions.values_mut() {
+            if partition.unsorted.is_empty() {
+                continue;
+            }
+            let batches = sort_batches(
+                mem::take(&mut partition.unsorted),
+                sort_order,
+                &self.files.schema,
+            )?;
+            for batch in batches {
+                if let Some(file) = partition.write(&self.files, &batch).await? {
+                    self.written.push(file);
+                }
+            }
+            // Every file of a sorted table has to be sorted as a whole
+            if let Some(file) = partition.close(&self.files).await? {
+                self.written.push(file);
+            }
+        }
+        self.sort_buffer_size = 0;
+        Ok(())
+    }
+}
+
+impl PartitionWriter {
+    // Write the batch to the open file of the partition. Returns the file once it reached the
+    // target file size.
+    async fn write(
+        &mut self,
+        files: &FileConfig,
+        batch: &RecordBatch,
+    ) -> Result<Option<DataFile>, SingerIcebergError> {
+        let writer_config = &files.writer_config;
+        let (_, current) = match &mut self.current {
+            Some(current) => current,
+            None => {
+                let file_path = self.location.clone() + &Uuid::new_v4().to_string() + ".parquet";
+                let (_, multipart) = files
+                    .object_store
+                    .put_multipart(&strip_prefix(&file_path).into())
+                    .await?;
+                self.current.insert((
+                    file_path,
+                    AsyncArrowWriter::try_new(
+                        multipart,
+                        files.arrow_schema.clone(),
+                        Some(writer_config.properties.clone()),
+                    )?,
+                ))
+            }
+        };
+        current.write(batch).await?;
+        if writer_config
+            .row_group_size
+            .is_some_and(|row_group_size| current.in_progress_size() >= row_group_size)
+        {
+            current.flush().await?;
+        }
+        if current.bytes_written() + current.in_progress_size() > writer_config.target_file_size {
+            return self.close(files).await;
+        }
+        Ok(None)
+    }
+
+    async fn close(&mut self, files: &FileConfig) -> Result<Option<DataFile>, SingerIcebergError> {
+        let Some((file_path, current)) = self.current.take() else {
+            return Ok(None);
+        };
+        let file_metadata = current.close().await?;
+        Ok(Some(
+            written_parquet_to_datafile(
+                file_path,
+                &file_metadata,
+                &files.schema,
+                &self.partition,
+                &files.object_store,
+            )
+            .await?,
+        ))
+    }
+}
+
+async fn written_parquet_to_datafile(
+    file_path: String,
+    file_metadata: &FileMetaData,
+    schema: &Schema,
+    partition: &Struct,
+    object_store: &Arc<dyn ObjectStore>,
+) -> Result<DataFile, SingerIcebergError> {
+    let parquet_schema = Arc::new(SchemaDescriptor::new(from_thrift(&file_metadata.schema)?));
+
+    let row_groups = file_metadata
+        .row_groups
+        .iter()
+        .map(|row_group| RowGroupMetaData::from_thrift(parquet_schema.clone(), row_group.clone()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    parquet_to_datafile(
+        file_path,
+        file_metadata.num_rows,
+        &row_groups,
+        schema,
+        partition,
+        object_store,
+    )
+    .await
+}
+
+// Create the data file of a parquet file with the statistics of its row groups
+pub(crate) async fn parquet_to_datafile(
+    file_path: String,
+    num_rows: i64,
+    row_groups: &[RowGroupMetaData],
+    schema: &Schema,
+    partition: &Struct,
+    object_store: &Arc<dyn ObjectStore>,
+) -> Result<DataFile, SingerIcebergError> {
+    let file_size_in_bytes = object_store
+        .head(&strip_prefix(&file_path).into())
+        .await?
+        .size as i64;
+
+    let mut column_sizes = HashMap::new();
+    let mut value_counts = HashMap::new();
+    let mut null_value_counts = HashMap::new();
+    let mut lower_bounds: HashMap<i32, Value> = HashMap::new();
+    let mut upper_bounds: HashMap<i32, Value> = HashMap::new();
+
+    for row_group in row_groups {
+        for column in row_group.columns() {
+            // Statistics are only collected for top level columns
+            let [name] = column.column_path().parts() else {
+                continue;
+            };
+            let Some(field) = schema.fields().get_name(name) else {
+                continue;
+            };
+            let id = field.id;
+
+            *column_sizes.entry(id).or_insert(0) += column.compressed_size();
+            *value_counts.entry(id).or_insert(0) += row_group.num_rows();
+
+            let Some(statistics) = column.statistics() else {
+                continue;
+            };
+
+            *null_value_counts.entry(id).or_insert(0) += statistics.null_count() as i64;
+
+            // Columns that only contain null values have no bounds
+            if !statistics.has_min_max_set() || !matches!(field.field_type, Type::Primitive(_)) {
+                continue;
+            }
+
+            if let Ok(min) = Value::try_from_bytes(statistics.min_bytes(), &field.field_type) {
+                lower_bounds
+                    .entry(id)
+                    .and_modify(|current| {
+                        if min < *current {
+                            *current = min.clone()
+                        }
+                    })
+                    .or_insert(min);
+            }
+            if let Ok(max) = Value::try_from_bytes(statistics.max_bytes(), &field.field_type) {
+                upper_bounds
+                    .entry(id)
+                    .and_modify(|current| {
+                        if max > *current {
+                            *current = max.clone()
+                        }
+                    })
+                    .or_insert(max);
+            }
+        }
+    }
+
+    Ok(DataFile::builder()
+        .with_content(Content::Data)
+        .with_file_path(file_path)
+        .with_file_format(FileFormat::Parquet)
+        .with_partition(partition.clone())
+        .with_record_count(num_rows)
+        .with_file_size_in_bytes(file_size_in_bytes)
+        .with_column_sizes(Some(AvroMap(column_sizes)))
+        .with_value_counts(Some(AvroMap(value_counts)))
+        .with_null_value_counts(Some(AvroMap(null_value_counts)))
+        .with_nan_value_counts(None)
+        .with_distinct_counts(None)
+        .with_lower_bounds(Some(lower_bounds))
+        .with_upper_bounds(Some(upper_bounds))
+        .build()
+        .map_err(SpecError::from)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use parquet::{
+        basic::{Compression, GzipLevel},
+        schema::types::ColumnPath,
+    };
+
+    use crate::plugin::{ParquetCompression, ParquetConfig};
+
+    use super::ParquetWriterConfig;
+
+    #[test]
+    fn test_writer_config() {
+        let table_properties = HashMap::from_iter(vec![
+            (
+                "write.parquet.compression-codec".to_owned(),
+                "SNAPPY".to_owned(),
+            ),
+            ("write.target-file-size-bytes".to_owned(), "1000".to_owned()),
+        ]);
+        let column = ColumnPath::from("id");
+
+        let config =
+            ParquetWriterConfig::new(&ParquetConfig::default(), &table_properties).unwrap();
+        assert_eq!(config.properties.compression(&column), Compression::SNAPPY);
+        assert_eq!(config.target_file_size, 1000);
+        assert_eq!(config.row_group_size, None);
+
+        // The settings of the stream take precedence
+        let config = ParquetWriterConfig::new(
+            &ParquetConfig {
+                compression: Some(ParquetCompression::Gzip),
+                compression_level: Some(9),
+                target_file_size: Some(2000),
+                ..Default::default()
+            },
+            &table_properties,
+        )
+        .unwrap();
+        assert_eq!(
+            config.properties.compression(&column),
+            Compression::GZIP(GzipLevel::try_new(9).unwrap())
+        );
+        assert_eq!(config.target_file_size, 2000);
+
+        // The level of the table doesn't apply to another codec of the stream
+        let config = ParquetWriterConfig::new(
+            &ParquetConfig {
+                compression: Some(ParquetCompression::Gzip),
+                ..Default::default()
+            },
+            &HashMap::from_iter(vec![
+                (
+                    "write.parquet.compression-codec".to_owned(),
+                    "zstd".to_owned(),
+                ),
+                (
+                    "write.parquet.compression-level".to_owned(),
+                    "19".to_owned(),
+                ),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            config.properties.compression(&column),
+            Compression::GZIP(GzipLevel::default())
+        );
+
+        let error = ParquetWriterConfig::new(
+            &ParquetConfig::default(),
+            &HashMap::from_iter(vec![(
+                "write.parquet.page-size-bytes".to_owned(),
+                "large".to_owned(),
+            )]),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Invalid value large of table property write.parquet.page-size-bytes."
+        );
+    }
+}